@@ -3,12 +3,16 @@ use thiserror::Error;
 
 #[derive(Error, Debug)]
 pub enum PerfError {
-    #[error("perf_event could not be opened.")]
-    FailedOpen,
+    #[error("perf_event could not be opened: {0}.")]
+    FailedOpen(io::Error),
     #[error("perf_event could not be started.")]
     FailedStart,
     #[error("perf_event could not be stopped.")]
     FailedStop,
+    #[error("perf_event counter could not be read.")]
+    FailedRead,
+    #[error("perf_event frequency could not be changed.")]
+    FailedSetFrequency,
     #[error("perf_event encountered an IO error.")]
     FailedIO(#[from] io::Error),
 }
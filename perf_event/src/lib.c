@@ -8,6 +8,7 @@
 #include <string.h>
 #include <sys/ioctl.h>
 #include <sys/mman.h>
+#include <time.h>
 #include <syscall.h>
 #include <unistd.h>
 
@@ -20,6 +21,28 @@ typedef struct x {
     size_t perf_buffer_size;
 } PerfEventHandle;
 
+/*!
+ * @brief Configuration of an event sampler.
+ */
+typedef struct {
+    uint32_t event_type;
+    uint64_t event_config;
+    size_t frequency;
+    size_t poll_freq;
+    size_t num_pages;
+    size_t callchain_depth_limit;
+    bool follow_children;
+    bool cgroup;
+    bool exclude_idle;
+    bool exclude_user;
+    bool exclude_kernel;
+    uint64_t sample_period;
+    bool sample_raw;
+    bool task_events;
+    uint8_t precise_ip;
+    bool cgroup_events;
+} PerfSamplerConfig;
+
 /*******************************************************************************
  * @brief Copy data from the perf ring buffer.
  *
@@ -75,34 +98,149 @@ pe_open(const struct perf_event_attr *attr, pid_t pid, int cpu, int group_fd,
 }
 
 bool
-pe_open_event_sampler(int cpu, pid_t pid, size_t frequency, size_t poll_freq,
-                      size_t num_pages, size_t callchain_depth_limit,
+pe_open_event_sampler(int cpu, pid_t pid, const PerfSamplerConfig *config,
                       PerfEventHandle *handle) {
+    if (config == NULL) {
+        return false;
+    }
+
     struct perf_event_attr attr = {0};
-    attr.type = PERF_TYPE_SOFTWARE;
+    attr.type = config->event_type;
     attr.size = sizeof(attr);
-    attr.config = PERF_COUNT_SW_TASK_CLOCK;
-    attr.sample_freq = frequency;
-    attr.freq = 1;
+    attr.config = config->event_config;
+    // A fixed period takes precedence over the frequency.
+    if (config->sample_period != 0) {
+        attr.sample_period = config->sample_period;
+    } else {
+        attr.sample_freq = config->frequency;
+        attr.freq = 1;
+    }
 
     attr.sample_type = PERF_SAMPLE_TID | PERF_SAMPLE_TIME | PERF_SAMPLE_CPU |
                        PERF_SAMPLE_IP | PERF_SAMPLE_CALLCHAIN;
+    if (config->sample_raw) {
+        attr.sample_type |= PERF_SAMPLE_RAW;
+    }
     attr.read_format = 0;
-    attr.sample_max_stack = callchain_depth_limit;
+    attr.sample_max_stack = config->callchain_depth_limit;
+    // Comparable with clock_gettime(), the default is the kernel's own clock.
+    attr.use_clockid = 1;
+    attr.clockid = CLOCK_MONOTONIC;
 
     attr.disabled = 1;
     attr.sample_id_all = 0;
-    attr.wakeup_events = poll_freq;
+    attr.wakeup_events = config->poll_freq;
+    // Children inherit the event, their records end up in this buffer.
+    // FORK and EXIT records announce them.
+    attr.inherit = config->follow_children;
+    attr.task = config->follow_children || config->task_events;
+    attr.comm = config->task_events;
+    attr.cgroup = config->cgroup_events;
+    attr.exclude_idle = config->exclude_idle;
+    attr.exclude_user = config->exclude_user;
+    attr.exclude_kernel = config->exclude_kernel;
+    attr.precise_ip = config->precise_ip;
+
+    unsigned long flags = PERF_FLAG_FD_CLOEXEC | PERF_FLAG_FD_NO_GROUP;
+    // pid is a file descriptor of the cgroup directory.
+    if (config->cgroup) {
+        flags |= PERF_FLAG_PID_CGROUP;
+    }
+    return pe_open(&attr, pid, cpu, -1, flags, config->num_pages, handle);
+}
+
+bool
+pe_open_counter(int cpu, pid_t pid, uint32_t event_type, uint64_t event_config,
+                PerfEventHandle *handle) {
+    if (handle == NULL) {
+        return false;
+    }
+
+    struct perf_event_attr attr = {0};
+    attr.type = event_type;
+    attr.size = sizeof(attr);
+    attr.config = event_config;
+    attr.disabled = 1;
+
+    int fd = syscall(SYS_perf_event_open, &attr, pid, cpu, -1,
+                     PERF_FLAG_FD_CLOEXEC | PERF_FLAG_FD_NO_GROUP);
+    if (fd < 0) {
+        return false;
+    }
+    // Counters are only read, there is no ring buffer.
+    handle->fd = fd;
+    handle->perf_buffer = NULL;
+    handle->perf_buffer_size = 0;
+    return true;
+}
+
+bool
+pe_read_counter(const PerfEventHandle *handle, uint64_t *value) {
+    if (handle == NULL || value == NULL) {
+        return false;
+    }
+    return read(handle->fd, value, sizeof(*value)) == sizeof(*value);
+}
+
+/*!
+ * @brief Value of a counter with the times it was enabled and scheduled.
+ */
+typedef struct {
+    uint64_t value;
+    uint64_t time_enabled;
+    uint64_t time_running;
+} PerfCounterReading;
 
-    return pe_open(&attr, pid, cpu, -1,
-                   PERF_FLAG_FD_CLOEXEC | PERF_FLAG_FD_NO_GROUP, num_pages,
-                   handle);
+bool
+pe_open_group_counter(pid_t pid, uint32_t event_type, uint64_t event_config,
+                      const PerfEventHandle *leader, bool inherit,
+                      bool enable_on_exec, PerfEventHandle *handle) {
+    if (handle == NULL) {
+        return false;
+    }
+
+    struct perf_event_attr attr = {0};
+    attr.type = event_type;
+    attr.size = sizeof(attr);
+    attr.config = event_config;
+    // Counters of a group are scheduled together, but the group might share
+    // the PMU with others, the times tell how long it was counting.
+    attr.read_format =
+        PERF_FORMAT_TOTAL_TIME_ENABLED | PERF_FORMAT_TOTAL_TIME_RUNNING;
+    // Children are added to the counts once they exit.
+    attr.inherit = inherit;
+    // The leader enables the whole group once the process calls exec(),
+    // other groups count right away.
+    attr.disabled = leader == NULL && enable_on_exec;
+    attr.enable_on_exec = leader == NULL && enable_on_exec;
+
+    int group_fd = leader == NULL ? -1 : leader->fd;
+    int fd = syscall(SYS_perf_event_open, &attr, pid, -1, group_fd,
+                     PERF_FLAG_FD_CLOEXEC);
+    if (fd < 0) {
+        return false;
+    }
+    handle->fd = fd;
+    handle->perf_buffer = NULL;
+    handle->perf_buffer_size = 0;
+    return true;
+}
+
+bool
+pe_read_group_counter(const PerfEventHandle *handle,
+                      PerfCounterReading *reading) {
+    if (handle == NULL || reading == NULL) {
+        return false;
+    }
+    return read(handle->fd, reading, sizeof(*reading)) == sizeof(*reading);
 }
 
 void
 pe_close(PerfEventHandle *handle) {
     if (handle != NULL) {
-        (void)munmap(handle->perf_buffer, handle->perf_buffer_size);
+        if (handle->perf_buffer != NULL) {
+            (void)munmap(handle->perf_buffer, handle->perf_buffer_size);
+        }
         (void)close(handle->fd);
     }
 }
@@ -112,8 +250,10 @@ pe_start(const PerfEventHandle *handle, bool do_reset) {
     if (handle == NULL) {
         return false;
     }
-    return (do_reset && ioctl(handle->fd, PERF_EVENT_IOC_RESET, 0) == 0) &&
-           ioctl(handle->fd, PERF_EVENT_IOC_ENABLE, 0) == 0;
+    if (do_reset && ioctl(handle->fd, PERF_EVENT_IOC_RESET, 0) != 0) {
+        return false;
+    }
+    return ioctl(handle->fd, PERF_EVENT_IOC_ENABLE, 0) == 0;
 }
 
 bool
@@ -124,9 +264,31 @@ pe_stop(const PerfEventHandle *handle) {
     return ioctl(handle->fd, PERF_EVENT_IOC_DISABLE, 0) == 0;
 }
 
+bool
+pe_set_frequency(const PerfEventHandle *handle, uint64_t frequency) {
+    if (handle == NULL) {
+        return false;
+    }
+    // For frequency-based events, the period argument sets the frequency.
+    return ioctl(handle->fd, PERF_EVENT_IOC_PERIOD, &frequency) == 0;
+}
+
+size_t
+pe_buffer_used(const PerfEventHandle *handle) {
+    if (handle == NULL || handle->perf_buffer == NULL) {
+        return 0;
+    }
+    struct perf_event_mmap_page *header = (void *)handle->perf_buffer;
+    atomic_thread_fence(memory_order_acquire);
+    return header->data_head - header->data_tail;
+}
+
 size_t
 pe_get_event(const PerfEventHandle *handle, unsigned char *dest, size_t n,
-             bool peek_only) {
+             bool peek_only, uint32_t *type) {
+    if (handle->perf_buffer == NULL) {
+        return 0;
+    }
     struct perf_event_mmap_page *header = (void *)handle->perf_buffer;
 
     // The ring buffer begins at the next page.
@@ -160,6 +322,8 @@ pe_get_event(const PerfEventHandle *handle, unsigned char *dest, size_t n,
         header->data_tail += event_header.size;
         atomic_thread_fence(memory_order_release);
     }
+    if (type != NULL)
+        *type = event_header.type;
     // Report true size of the event.
     return event_size;
 }
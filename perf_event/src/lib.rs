@@ -1,4 +1,5 @@
 use libc::pid_t;
+use std::io;
 use std::os::fd::AsRawFd;
 use std::os::raw::{c_int, c_uchar};
 use std::ptr;
@@ -15,6 +16,10 @@ pub struct PerfEventHandle {
     perf_buffer_size: usize,
 }
 
+// The ring buffer is mapped for the exclusive use of the handle, it can be
+// moved to another thread with it.
+unsafe impl Send for PerfEventHandle {}
+
 impl AsRawFd for PerfEventHandle {
     fn as_raw_fd(&self) -> std::os::fd::RawFd {
         self.fd
@@ -30,31 +35,173 @@ impl Drop for PerfEventHandle {
     }
 }
 
+/// Value of a counter from [PerfEventHandle::read_group_counter()].
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CounterReading {
+    /// Number of counted events.
+    pub value: u64,
+    /// Nanoseconds the counter was enabled.
+    pub time_enabled: u64,
+    /// Nanoseconds the counter was scheduled on the PMU, less than
+    /// `time_enabled` if it was multiplexed with other counters.
+    pub time_running: u64,
+}
+
+/// Configuration of a perf_event sampler.
+///
+/// See `man perf_event_open (2)` for details.
+#[repr(C)]
+#[derive(Debug, Clone)]
+pub struct SamplerConfig {
+    /// Type of the sampled event, one of `PERF_TYPE_*`.
+    pub event_type: u32,
+    /// The sampled event, its meaning depends on `event_type`.
+    pub event_config: u64,
+    /// Number of samples per second to generate.
+    pub frequency: usize,
+    /// How many many samples per POLLIN activation.
+    pub poll_freq: usize,
+    /// Size of the internal buffer for storing samples, in number of pages.
+    /// Must be a power of two.
+    pub num_pages: usize,
+    /// Maximum length of the stack trace to record.
+    pub callchain_depth_limit: usize,
+    /// Whether children of the sampled process created after opening the
+    /// sampler are sampled too. Enables [RecordType::Fork] and
+    /// [RecordType::Exit] records.
+    pub follow_children: bool,
+    /// Whether `pid` is a file descriptor of a cgroup directory, samples
+    /// only processes of the cgroup. Requires a specific CPU.
+    pub cgroup: bool,
+    /// Whether to not count the event while the CPU is idle.
+    pub exclude_idle: bool,
+    /// Whether to not count the event in user space.
+    pub exclude_user: bool,
+    /// Whether to not count the event in the kernel, allows unprivileged
+    /// users to sample their processes.
+    pub exclude_kernel: bool,
+    /// Sample every this many events instead of at `frequency`, 0 to use
+    /// the frequency.
+    pub sample_period: u64,
+    /// Whether samples carry raw data, e.g. of bpf-output events.
+    pub sample_raw: bool,
+    /// Whether to report processes and threads the sampler sees being
+    /// created, terminated or renamed. Enables [RecordType::Fork],
+    /// [RecordType::Exit] and [RecordType::Comm] records.
+    pub task_events: bool,
+    /// Allowed skid of the sampled instruction pointer, from 0 for any to
+    /// 3 for none. Levels above 0 need hardware support, e.g. PEBS.
+    pub precise_ip: u8,
+    /// Whether to report cgroups the sampler sees being created. Enables
+    /// [RecordType::Cgroup] records, requires Linux 5.7.
+    pub cgroup_events: bool,
+}
+
 extern "C" {
     fn pe_open_event_sampler(
         cpu: c_int,
         pid: pid_t,
-        frequency: usize,
-        poll_freq: usize,
-        num_pages: usize,
-        callchain_depth_limit: usize,
+        config: *const SamplerConfig,
+        handle: *mut PerfEventHandle,
+    ) -> bool;
+
+    fn pe_open_counter(
+        cpu: c_int,
+        pid: pid_t,
+        event_type: u32,
+        event_config: u64,
+        handle: *mut PerfEventHandle,
+    ) -> bool;
+
+    fn pe_read_counter(handle: *const PerfEventHandle, value: *mut u64) -> bool;
+
+    fn pe_open_group_counter(
+        pid: pid_t,
+        event_type: u32,
+        event_config: u64,
+        leader: *const PerfEventHandle,
+        inherit: bool,
+        enable_on_exec: bool,
         handle: *mut PerfEventHandle,
     ) -> bool;
 
+    fn pe_read_group_counter(handle: *const PerfEventHandle, reading: *mut CounterReading) -> bool;
+
     fn pe_close(handle: *mut PerfEventHandle);
 
     fn pe_start(handle: *const PerfEventHandle, do_reset: bool) -> bool;
 
     fn pe_stop(handle: *const PerfEventHandle) -> bool;
 
+    fn pe_set_frequency(handle: *const PerfEventHandle, frequency: u64) -> bool;
+
+    fn pe_buffer_used(handle: *const PerfEventHandle) -> usize;
+
     fn pe_get_event(
         handle: *const PerfEventHandle,
         dest: *mut c_uchar,
         n: usize,
         peek_only: bool,
+        record_type: *mut u32,
     ) -> usize;
 }
 
+/// Type of a record stored in the perf_event ring buffer.
+///
+/// See `enum perf_event_type` in `linux/perf_event.h`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordType {
+    Mmap,
+    Lost,
+    Comm,
+    Exit,
+    Throttle,
+    Unthrottle,
+    Fork,
+    Read,
+    Sample,
+    Cgroup,
+    /// Any other record, not interpreted by this crate.
+    Other(u32),
+}
+
+impl From<u32> for RecordType {
+    fn from(value: u32) -> Self {
+        match value {
+            1 => RecordType::Mmap,
+            2 => RecordType::Lost,
+            3 => RecordType::Comm,
+            4 => RecordType::Exit,
+            5 => RecordType::Throttle,
+            6 => RecordType::Unthrottle,
+            7 => RecordType::Fork,
+            8 => RecordType::Read,
+            9 => RecordType::Sample,
+            19 => RecordType::Cgroup,
+            x => RecordType::Other(x),
+        }
+    }
+}
+
+impl From<RecordType> for u32 {
+    fn from(value: RecordType) -> Self {
+        match value {
+            RecordType::Mmap => 1,
+            RecordType::Lost => 2,
+            RecordType::Comm => 3,
+            RecordType::Exit => 4,
+            RecordType::Throttle => 5,
+            RecordType::Unthrottle => 6,
+            RecordType::Fork => 7,
+            RecordType::Read => 8,
+            RecordType::Sample => 9,
+            RecordType::Cgroup => 19,
+            RecordType::Other(x) => x,
+        }
+    }
+}
+
 impl PerfEventHandle {
     /// Open a new perf_event sampler.
     ///
@@ -67,40 +214,129 @@ impl PerfEventHandle {
     ///
     /// * `cpu` Index of CPU to start sampling, -1 to sample all CPUs.
     /// * `pid` Process ID to sample, -1 to sample all processes.
-    /// * `frequency` Number of samples per second to generate.
-    /// * `poll_freq` How many many samples per POLLIN activation.
-    /// * `num_pages` Size of the internal buffer for storing samples,
-    ///   in number of pages. Must be a power of two.
-    /// * `callchain_depth_limit` Maximum length of the stack trace to record.
+    /// * `config` What and how to sample.
     ///
     /// Do note that either `cpu` or `pid` must not be `-1`, one cannot sample
     /// all processes on all CPUs, create an event per-CPU instead.
     pub fn new(
         cpu: c_int,
         pid: pid_t,
-        frequency: usize,
-        poll_freq: usize,
-        num_pages: usize,
-        callchain_depth_limit: usize,
+        config: &SamplerConfig,
+    ) -> Result<PerfEventHandle, PerfError> {
+        let mut handle = PerfEventHandle {
+            fd: -1,
+            perf_buffer: ptr::null_mut(),
+            perf_buffer_size: 0,
+        };
+        unsafe {
+            if pe_open_event_sampler(cpu, pid, config, &mut handle) {
+                Ok(handle)
+            } else {
+                Err(PerfError::FailedOpen(io::Error::last_os_error()))
+            }
+        }
+    }
+
+    /// Open a new perf_event counter.
+    ///
+    /// Counters do not generate samples, only [Self::read_counter()]
+    /// their current value. Like samplers, they are created stopped.
+    ///
+    /// # Arguments
+    ///
+    /// * `cpu` Index of CPU to count on, -1 to count on all CPUs.
+    /// * `pid` Process ID to count, -1 to count all processes.
+    /// * `event_type` Type of the counted event, one of `PERF_TYPE_*` or
+    ///   the type of a dynamic PMU.
+    /// * `event_config` The counted event, its meaning depends on `event_type`.
+    pub fn new_counter(
+        cpu: c_int,
+        pid: pid_t,
+        event_type: u32,
+        event_config: u64,
+    ) -> Result<PerfEventHandle, PerfError> {
+        let mut handle = PerfEventHandle {
+            fd: -1,
+            perf_buffer: ptr::null_mut(),
+            perf_buffer_size: 0,
+        };
+        unsafe {
+            if pe_open_counter(cpu, pid, event_type, event_config, &mut handle) {
+                Ok(handle)
+            } else {
+                Err(PerfError::FailedOpen(io::Error::last_os_error()))
+            }
+        }
+    }
+
+    /// Open a counter of a process or a thread, in a group.
+    ///
+    /// Counters of a group are scheduled on the PMU together, ratios of
+    /// their values are therefore exact. Unlike other counters, group
+    /// counters count right away, or from `exec()` with `enable_on_exec`.
+    ///
+    /// # Arguments
+    ///
+    /// * `pid` Process or thread ID to count.
+    /// * `event_type` Type of the counted event, one of `PERF_TYPE_*` or
+    ///   the type of a dynamic PMU.
+    /// * `event_config` The counted event, its meaning depends on `event_type`.
+    /// * `leader` Counter leading the group, `None` to create a new group.
+    /// * `inherit` Whether future children and threads are counted too,
+    ///   they are added to the count once they exit.
+    /// * `enable_on_exec` Whether a new group starts stopped, until the
+    ///   process calls `exec()`.
+    pub fn new_group_counter(
+        pid: pid_t,
+        event_type: u32,
+        event_config: u64,
+        leader: Option<&PerfEventHandle>,
+        inherit: bool,
+        enable_on_exec: bool,
     ) -> Result<PerfEventHandle, PerfError> {
         let mut handle = PerfEventHandle {
-            fd: 0,
+            fd: -1,
             perf_buffer: ptr::null_mut(),
             perf_buffer_size: 0,
         };
+        let leader = leader.map_or(ptr::null(), |leader| leader as *const _);
         unsafe {
-            if pe_open_event_sampler(
-                cpu,
+            if pe_open_group_counter(
                 pid,
-                frequency,
-                poll_freq,
-                num_pages,
-                callchain_depth_limit,
+                event_type,
+                event_config,
+                leader,
+                inherit,
+                enable_on_exec,
                 &mut handle,
             ) {
                 Ok(handle)
             } else {
-                Err(PerfError::FailedOpen)
+                Err(PerfError::FailedOpen(io::Error::last_os_error()))
+            }
+        }
+    }
+
+    /// Read the current value of a counter from [Self::new_group_counter()].
+    pub fn read_group_counter(&self) -> Result<CounterReading, PerfError> {
+        let mut reading = CounterReading::default();
+        unsafe {
+            if pe_read_group_counter(self, &mut reading) {
+                Ok(reading)
+            } else {
+                Err(PerfError::FailedRead)
+            }
+        }
+    }
+
+    /// Read the current value of a counter.
+    pub fn read_counter(&self) -> Result<u64, PerfError> {
+        let mut value = 0;
+        unsafe {
+            if pe_read_counter(self, &mut value) {
+                Ok(value)
+            } else {
+                Err(PerfError::FailedRead)
             }
         }
     }
@@ -131,19 +367,102 @@ impl PerfEventHandle {
         }
     }
 
-    /// Extract the next sample from the internal buffer.
+    /// Change the sampling frequency of a running sampler.
     ///
+    /// # Arguments
+    ///
+    /// * `frequency` Number of samples per second to generate.
+    pub fn set_frequency(&self, frequency: usize) -> Result<(), PerfError> {
+        unsafe {
+            if pe_set_frequency(self, frequency as u64) {
+                Ok(())
+            } else {
+                Err(PerfError::FailedSetFrequency)
+            }
+        }
+    }
+
+    /// Ratio of the ring buffer occupied by unread records, from 0 to 1.
+    pub fn buffer_fill(&self) -> f64 {
+        if self.buffer_size() == 0 {
+            return 0.0;
+        }
+        self.buffer_used() as f64 / self.buffer_size() as f64
+    }
+
+    /// Bytes of unread records in the ring buffer.
+    pub fn buffer_used(&self) -> usize {
+        unsafe { pe_buffer_used(self) }
+    }
+
+    /// Capacity of the ring buffer in bytes, zero for counters.
+    pub fn buffer_size(&self) -> usize {
+        if self.perf_buffer_size == 0 {
+            return 0;
+        }
+        // The first page holds the header, the rest is the ring buffer.
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGE_SIZE) as usize };
+        self.perf_buffer_size - page_size
+    }
+
+    /// Whether the sampled process has exited.
+    ///
+    /// perf_event signals POLLHUP then, no new records will be produced.
+    /// For inherited events, all children must have exited too.
+    pub fn has_exited(&self) -> bool {
+        self.poll(0) & libc::POLLHUP != 0
+    }
+
+    /// Block until new records are signalled or the process exits.
+    ///
+    /// Returns false on timeout.
     ///
     /// # Arguments
+    /// * `timeout_ms` Maximum time to wait in milliseconds, -1 for no limit.
+    pub fn wait(&self, timeout_ms: c_int) -> bool {
+        self.poll(timeout_ms) != 0
+    }
+
+    /// Poll the event, returns the signalled poll events.
+    fn poll(&self, timeout_ms: c_int) -> libc::c_short {
+        let mut poll_fd = libc::pollfd {
+            fd: self.fd,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let ready = unsafe { libc::poll(&mut poll_fd, 1, timeout_ms) };
+        if ready > 0 {
+            poll_fd.revents
+        } else {
+            0
+        }
+    }
+
+    /// Extract the next record from the internal buffer.
+    ///
     ///
-    /// * `dest` Buffer to place the sample into.
-    /// * `peek_only` Whether to keep the sample in the internal buffer.
-    ///   If true, the next call will return the same sample.
+    /// # Arguments
+    ///
+    /// * `dest` Buffer to place the record into, without its header.
+    ///   Can be empty to only learn the type and size of the record.
+    /// * `peek_only` Whether to keep the record in the internal buffer.
+    ///   If true, the next call will return the same record.
     ///
     /// # Returns
     ///
-    /// True size of the sample.
-    pub fn get_event(&self, dest: &mut [u8], peek_only: bool) -> usize {
-        unsafe { pe_get_event(self, dest.as_mut_ptr(), dest.len(), peek_only) }
+    /// Type and true size of the record, `None` if there is no record.
+    pub fn get_event(&self, dest: &mut [u8], peek_only: bool) -> Option<(RecordType, usize)> {
+        let mut record_type = 0;
+        let size = unsafe {
+            pe_get_event(
+                self,
+                dest.as_mut_ptr(),
+                dest.len(),
+                peek_only,
+                &mut record_type,
+            )
+        };
+        // Record types start from 1, 0 means nothing was read.
+        (record_type != 0).then(|| (record_type.into(), size))
     }
 }
@@ -0,0 +1,46 @@
+//! Cost of copying callchains out of raw samples, inline vs. heap storage.
+//!
+//! `Sample::callchain` is a `Vec`, this compares it to an inline small
+//! vector. Run with `cargo bench -p tauphi-core --bench callchain`.
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use smallvec::SmallVec;
+
+/// Inline storage for typical callchain depths.
+type InlineCallchain = SmallVec<[u64; 16]>;
+
+/// Copies per measurement.
+const ITERATIONS: u32 = 1_000_000;
+
+/// Average time of one call of `copy`.
+fn measure<T>(copy: impl Fn(&[u64]) -> T, raw: &[u64]) -> Duration {
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        black_box(copy(black_box(raw)));
+    }
+    start.elapsed() / ITERATIONS
+}
+
+/// Total time of copying and keeping all callchains, like a recorded profile.
+fn measure_retained<T>(copy: impl Fn(&[u64]) -> T, raw: &[u64]) -> Duration {
+    let start = Instant::now();
+    let retained: Vec<T> = (0..ITERATIONS).map(|_| copy(black_box(raw))).collect();
+    black_box(&retained);
+    start.elapsed()
+}
+
+fn main() {
+    let raw = [0x5555_0000_1234_u64; 128];
+    for depth in [8, 16, 32, 64] {
+        let raw = &raw[..depth];
+        println!(
+            "depth {depth:>2}: transient Vec {:>5?} inline {:>5?}, \
+             retained Vec {:>9.1?} inline {:>9.1?}",
+            measure(<[u64]>::to_vec, raw),
+            measure(InlineCallchain::from_slice, raw),
+            measure_retained(<[u64]>::to_vec, raw),
+            measure_retained(InlineCallchain::from_slice, raw),
+        );
+    }
+}
@@ -0,0 +1,14 @@
+//! Decode arbitrary bytes as every record type, run with
+//! `cargo +nightly fuzz run parse_record` from `tauphi-core`.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use tauphi_core::pool::SamplePool;
+use tauphi_core::record::parse_record;
+
+fuzz_target!(|data: &[u8]| {
+    let Some((&record_type, payload)) = data.split_first() else {
+        return;
+    };
+    let _ = parse_record(u32::from(record_type).into(), payload, &SamplePool::default());
+});
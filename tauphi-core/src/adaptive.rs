@@ -0,0 +1,110 @@
+//! Adaptive control of the sampling frequency.
+use crate::sampling::Record;
+
+/// Lowers the sampling frequency when too many samples are lost.
+///
+/// Feed it all records via [FrequencyController::observe()] and call
+/// [FrequencyController::adjust()] periodically. Once the loss is gone for
+/// a while, the frequency is raised back towards the requested one.
+#[derive(Debug)]
+pub struct FrequencyController {
+    /// Frequency requested by the user, never exceeded.
+    max_frequency: usize,
+    /// Current sampling frequency.
+    frequency: usize,
+    /// Tolerated ratio of lost samples, from 0 to 1.
+    max_loss: f64,
+    /// Samples received in the current period.
+    samples: u64,
+    /// Samples lost in the current period.
+    lost: u64,
+    /// Whether the kernel throttled the sampling in the current period.
+    throttled: bool,
+    /// Number of consecutive periods without any loss.
+    calm_periods: u32,
+}
+
+impl FrequencyController {
+    /// Create a new controller.
+    ///
+    /// # Arguments
+    /// * `frequency` Requested number of samples per second.
+    /// * `max_loss` Tolerated ratio of lost samples, from 0 to 1.
+    pub fn new(frequency: usize, max_loss: f64) -> FrequencyController {
+        FrequencyController {
+            max_frequency: frequency,
+            frequency,
+            max_loss,
+            samples: 0,
+            lost: 0,
+            throttled: false,
+            calm_periods: 0,
+        }
+    }
+
+    /// Current sampling frequency.
+    pub fn frequency(&self) -> usize {
+        self.frequency
+    }
+
+    /// Account the record to the current period.
+    pub fn observe(&mut self, record: &Record) {
+        match record {
+            Record::Sample(_) => self.samples += 1,
+            Record::Lost(lost) => self.lost += lost,
+            Record::Throttle(_) => self.throttled = true,
+            _ => (),
+        }
+    }
+
+    /// Close the current period and decide the frequency for the next one.
+    ///
+    /// Returns the new frequency if it should change.
+    pub fn adjust(&mut self) -> Option<usize> {
+        let total = self.samples + self.lost;
+        let loss = if total == 0 {
+            0.0
+        } else {
+            self.lost as f64 / total as f64
+        };
+        let throttled = self.throttled;
+        self.samples = 0;
+        self.lost = 0;
+        self.throttled = false;
+
+        let frequency = if loss > self.max_loss || throttled {
+            self.calm_periods = 0;
+            1.max(self.frequency / 2)
+        } else if loss == 0.0 && self.frequency < self.max_frequency {
+            self.calm_periods += 1;
+            if self.calm_periods < Self::RESTORE_AFTER_PERIODS {
+                return None;
+            }
+            self.calm_periods = 0;
+            self.max_frequency.min(self.frequency * 2)
+        } else {
+            self.calm_periods = 0;
+            return None;
+        };
+        (frequency != self.frequency).then(|| {
+            self.frequency = frequency;
+            frequency
+        })
+    }
+
+    /// Number of calm periods before the frequency is raised again.
+    const RESTORE_AFTER_PERIODS: u32 = 5;
+}
+
+#[test]
+fn frequency_controller_test() {
+    let mut controller = FrequencyController::new(1000, 0.01);
+    controller.observe(&Record::Lost(10));
+    controller.observe(&Record::Sample(Default::default()));
+    assert_eq!(controller.adjust(), Some(500));
+    for _ in 1..FrequencyController::RESTORE_AFTER_PERIODS {
+        assert_eq!(controller.adjust(), None);
+    }
+    assert_eq!(controller.adjust(), Some(1000));
+    assert_eq!(controller.adjust(), None);
+}
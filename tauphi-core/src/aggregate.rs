@@ -0,0 +1,409 @@
+//! Aggregation of samples by their call stacks.
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Seek, Write};
+use std::mem;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::{env, process};
+
+use crate::error::TauphiError;
+use crate::sampling::Sample;
+use crate::sink::SampleSink;
+
+/// Callchain entries from this value up are context markers such as
+/// `PERF_CONTEXT_KERNEL`, not addresses.
+pub(crate) const PERF_CONTEXT_MAX: u64 = -4095_i64 as u64;
+
+/// Index of the root node in [StackTrie], it has no frame.
+pub const ROOT: usize = 0;
+
+/// Spill files of [SpillingStackTrie] from which they are merged into one.
+const MAX_SPILLS: usize = 16;
+
+/// Samples aggregated by their call stacks into a trie of frames.
+///
+/// Consumes samples one by one, its size grows with the number of unique
+/// stacks instead of the number of samples.
+#[derive(Debug)]
+pub struct StackTrie {
+    nodes: Vec<StackNode>,
+    /// Child of a node by its frame.
+    children: HashMap<(usize, u64), usize>,
+}
+
+/// A frame on a particular call stack.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StackNode {
+    /// Instruction pointer of the frame.
+    pub frame: u64,
+    /// Index of the calling frame's node.
+    pub parent: usize,
+    /// Samples with this frame on the top of the stack.
+    pub self_samples: u64,
+    /// Samples with this frame anywhere on the stack.
+    pub total_samples: u64,
+}
+
+impl Default for StackTrie {
+    fn default() -> Self {
+        StackTrie {
+            nodes: vec![StackNode {
+                frame: 0,
+                parent: ROOT,
+                self_samples: 0,
+                total_samples: 0,
+            }],
+            children: HashMap::new(),
+        }
+    }
+}
+
+impl StackTrie {
+    /// Add `weight` samples of the callchain, returns the index of its top.
+    ///
+    /// # Arguments
+    /// * `callchain` Frames from the top of the stack, as recorded by perf.
+    /// * `weight` Number of samples.
+    pub fn add(&mut self, callchain: &[u64], weight: u64) -> usize {
+        let mut node = ROOT;
+        self.nodes[ROOT].total_samples += weight;
+        for &frame in callchain.iter().rev() {
+            if frame >= PERF_CONTEXT_MAX {
+                continue;
+            }
+            let parent = node;
+            let next_index = self.nodes.len();
+            node = *self.children.entry((parent, frame)).or_insert(next_index);
+            if node == next_index {
+                self.nodes.push(StackNode {
+                    frame,
+                    parent,
+                    self_samples: 0,
+                    total_samples: 0,
+                });
+            }
+            self.nodes[node].total_samples += weight;
+        }
+        self.nodes[node].self_samples += weight;
+        node
+    }
+
+    /// Add the sample, falling back to its IP without a callchain.
+    pub fn add_sample(&mut self, sample: &Sample) -> usize {
+        if sample.callchain.is_empty() {
+            self.add(&[sample.ip], 1)
+        } else {
+            self.add(&sample.callchain, 1)
+        }
+    }
+
+    /// Node at the index, see [StackTrie::add()].
+    pub fn node(&self, index: usize) -> &StackNode {
+        &self.nodes[index]
+    }
+
+    /// Number of all added samples.
+    pub fn total_samples(&self) -> u64 {
+        self.nodes[ROOT].total_samples
+    }
+
+    /// Frames from the bottom of the stack up to the node.
+    pub fn stack(&self, mut index: usize) -> Vec<u64> {
+        let mut stack = Vec::new();
+        while index != ROOT {
+            stack.push(self.nodes[index].frame);
+            index = self.nodes[index].parent;
+        }
+        stack.reverse();
+        stack
+    }
+
+    /// Unique stacks with their number of samples, from the bottom frame.
+    pub fn folded(&self) -> impl Iterator<Item = (Vec<u64>, u64)> + '_ {
+        self.nodes
+            .iter()
+            .enumerate()
+            .filter(|(_, node)| node.self_samples > 0)
+            .map(|(index, node)| (self.stack(index), node.self_samples))
+    }
+
+    /// Write the stacks in the folded format of flamegraph tools,
+    /// e.g. `0x1234;0x5678 42`.
+    pub fn write_folded(&self, mut writer: impl Write) -> Result<(), TauphiError> {
+        for (stack, samples) in self.folded() {
+            writeln!(writer, "{} {samples}", fold(&stack))?;
+        }
+        Ok(())
+    }
+
+    /// Approximate number of bytes the trie occupies.
+    pub fn memory_size(&self) -> usize {
+        // Hash maps keep one control byte per bucket.
+        self.nodes.capacity() * mem::size_of::<StackNode>()
+            + self.children.capacity() * (mem::size_of::<((usize, u64), usize)>() + 1)
+    }
+}
+
+/// Frames of the stack in the folded format, e.g. `0x1234;0x5678`.
+fn fold(stack: &[u64]) -> String {
+    let frames: Vec<_> = stack.iter().map(|frame| format!("{frame:#x}")).collect();
+    frames.join(";")
+}
+
+impl SampleSink for StackTrie {
+    fn consume(&mut self, sample: &Sample) -> Result<(), TauphiError> {
+        self.add_sample(sample);
+        Ok(())
+    }
+}
+
+/// A [StackTrie] limited in memory, spills partial aggregates over the
+/// budget into temporary files.
+///
+/// The spilled stacks are merged when writing the folded stacks or once
+/// there are more than [MAX_SPILLS] files, which are removed once dropped.
+#[derive(Debug)]
+pub struct SpillingStackTrie {
+    trie: StackTrie,
+    /// Maximum bytes of the trie, see [StackTrie::memory_size()].
+    budget: usize,
+    /// Partial aggregates, folded stacks sorted by the stack.
+    spills: Vec<File>,
+}
+
+impl SpillingStackTrie {
+    /// Aggregate in at most `budget` bytes of memory.
+    pub fn new(budget: usize) -> SpillingStackTrie {
+        SpillingStackTrie {
+            trie: StackTrie::default(),
+            budget,
+            spills: Vec::new(),
+        }
+    }
+
+    /// Add the sample, see [StackTrie::add_sample()].
+    pub fn add_sample(&mut self, sample: &Sample) -> Result<(), TauphiError> {
+        self.trie.add_sample(sample);
+        self.limit_memory()
+    }
+
+    /// Add `weight` samples of the callchain, see [StackTrie::add()].
+    pub fn add(&mut self, callchain: &[u64], weight: u64) -> Result<(), TauphiError> {
+        self.trie.add(callchain, weight);
+        self.limit_memory()
+    }
+
+    /// Spill the trie once it is over the budget.
+    fn limit_memory(&mut self) -> Result<(), TauphiError> {
+        if self.trie.memory_size() > self.budget {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    /// Number of files with partial aggregates.
+    pub fn spills(&self) -> usize {
+        self.spills.len()
+    }
+
+    /// Write the merged stacks in the folded format, see
+    /// [StackTrie::write_folded()].
+    pub fn write_folded(&mut self, mut writer: impl Write) -> Result<(), TauphiError> {
+        self.for_each_folded(|stack, samples| Ok(writeln!(writer, "{stack} {samples}")?))
+    }
+
+    /// Pass each merged stack in the folded format with its samples to `f`.
+    ///
+    /// Stacks come sorted only if any were spilled.
+    pub fn for_each_folded(
+        &mut self,
+        mut f: impl FnMut(&str, u64) -> Result<(), TauphiError>,
+    ) -> Result<(), TauphiError> {
+        if self.spills.is_empty() {
+            for (stack, samples) in self.trie.folded() {
+                f(&fold(&stack), samples)?;
+            }
+            return Ok(());
+        }
+        self.spill()?;
+        merge_spills(mem::take(&mut self.spills), f)
+    }
+
+    /// Move the trie's stacks into a new temporary file, merges all files
+    /// once there are too many.
+    fn spill(&mut self) -> Result<(), TauphiError> {
+        let mut stacks: Vec<_> = self
+            .trie
+            .folded()
+            .map(|(stack, samples)| (fold(&stack), samples))
+            .collect();
+        stacks.sort_unstable();
+        let file = write_spill(|writer| {
+            for (stack, samples) in stacks {
+                writeln!(writer, "{stack} {samples}")?;
+            }
+            Ok(())
+        })?;
+        self.spills.push(file);
+        self.trie = StackTrie::default();
+        if self.spills.len() > MAX_SPILLS {
+            let spills = mem::take(&mut self.spills);
+            let merged = write_spill(|writer| {
+                merge_spills(spills, |stack, samples| {
+                    Ok(writeln!(writer, "{stack} {samples}")?)
+                })
+            })?;
+            self.spills.push(merged);
+        }
+        Ok(())
+    }
+}
+
+impl SampleSink for SpillingStackTrie {
+    fn consume(&mut self, sample: &Sample) -> Result<(), TauphiError> {
+        self.add_sample(sample)
+    }
+}
+
+/// New spill file with the stacks written by `write`, ready to be read.
+fn write_spill(
+    write: impl FnOnce(&mut BufWriter<&mut File>) -> Result<(), TauphiError>,
+) -> Result<File, TauphiError> {
+    let mut file = temp_file()?;
+    let mut writer = BufWriter::new(&mut file);
+    write(&mut writer)?;
+    writer.flush()?;
+    drop(writer);
+    file.rewind()?;
+    Ok(file)
+}
+
+/// Pass each stack of the sorted spills with its samples summed up to `f`,
+/// sorted by the stack.
+fn merge_spills(
+    spills: Vec<File>,
+    mut f: impl FnMut(&str, u64) -> Result<(), TauphiError>,
+) -> Result<(), TauphiError> {
+    let mut spills: Vec<_> = spills
+        .into_iter()
+        .map(|file| BufReader::new(file).lines())
+        .collect();
+    // Equal stacks come one after another.
+    let mut heads = BinaryHeap::new();
+    for (index, spill) in spills.iter_mut().enumerate() {
+        if let Some((stack, samples)) = read_folded(spill)? {
+            heads.push(Reverse((stack, samples, index)));
+        }
+    }
+    let mut current: Option<(String, u64)> = None;
+    while let Some(Reverse((stack, samples, index))) = heads.pop() {
+        if let Some((next, next_samples)) = read_folded(&mut spills[index])? {
+            heads.push(Reverse((next, next_samples, index)));
+        }
+        match &mut current {
+            Some((current, total)) if *current == stack => *total += samples,
+            _ => {
+                if let Some((stack, total)) = current.replace((stack, samples)) {
+                    f(&stack, total)?;
+                }
+            }
+        }
+    }
+    if let Some((stack, total)) = current {
+        f(&stack, total)?;
+    }
+    Ok(())
+}
+
+/// Next stack and its samples of a spill file.
+fn read_folded(
+    lines: &mut impl Iterator<Item = io::Result<String>>,
+) -> io::Result<Option<(String, u64)>> {
+    let Some(line) = lines.next().transpose()? else {
+        return Ok(None);
+    };
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "corrupted spill file");
+    let (stack, samples) = line.rsplit_once(' ').ok_or_else(invalid)?;
+    let samples = samples.parse().map_err(|_| invalid())?;
+    Ok(Some((stack.to_owned(), samples)))
+}
+
+/// Open a new temporary file, removed once closed.
+fn temp_file() -> io::Result<File> {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let name = format!(
+        "tauphi-{}-{}.folded",
+        process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    );
+    let path = env::temp_dir().join(name);
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(&path)?;
+    // Open files outlive their removal.
+    fs::remove_file(&path)?;
+    Ok(file)
+}
+
+#[test]
+fn stack_trie_test() {
+    let mut trie = StackTrie::default();
+    // Top of the stack first, with a kernel context marker.
+    let leaf = trie.add(&[0x30, 0x20, 0x10, -128_i64 as u64], 2);
+    trie.add(&[0x20, 0x10], 1);
+    trie.add(&[0x40, 0x10], 1);
+    assert_eq!(trie.total_samples(), 4);
+    assert_eq!(trie.stack(leaf), vec![0x10, 0x20, 0x30]);
+    let folded: Vec<_> = trie.folded().collect();
+    assert_eq!(
+        folded,
+        vec![
+            (vec![0x10, 0x20], 1),
+            (vec![0x10, 0x20, 0x30], 2),
+            (vec![0x10, 0x40], 1)
+        ]
+    );
+    let mut output = Vec::new();
+    trie.write_folded(&mut output).unwrap();
+    assert!(String::from_utf8(output)
+        .unwrap()
+        .starts_with("0x10;0x20 1\n"));
+}
+
+#[test]
+fn spilling_stack_trie_test() {
+    // Every sample goes to its own spill.
+    let mut trie = SpillingStackTrie::new(0);
+    for callchain in [vec![0x20, 0x10], vec![0x30, 0x10], vec![0x20, 0x10]] {
+        let sample = Sample {
+            callchain,
+            ..Default::default()
+        };
+        trie.add_sample(&sample).unwrap();
+    }
+    assert_eq!(trie.spills(), 3);
+    let mut output = Vec::new();
+    trie.write_folded(&mut output).unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "0x10;0x20 2\n0x10;0x30 1\n"
+    );
+}
+
+#[test]
+fn spill_compaction_test() {
+    let mut trie = SpillingStackTrie::new(0);
+    for index in 0..3 * MAX_SPILLS as u64 {
+        trie.add(&[0x20 + index % 2, 0x10], 1).unwrap();
+        assert!(trie.spills() <= MAX_SPILLS);
+    }
+    let mut output = Vec::new();
+    trie.write_folded(&mut output).unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        format!("0x10;0x20 {0}\n0x10;0x21 {0}\n", 3 * MAX_SPILLS / 2)
+    );
+}
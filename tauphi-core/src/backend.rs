@@ -0,0 +1,142 @@
+//! Sources of records for [Sampler](crate::sampling::Sampler).
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use crate::error::TauphiError;
+use crate::pool::SamplePool;
+use crate::sampling::Record;
+
+/// Source of sampled records, perf events of the kernel in production.
+///
+/// The event is running when passed to
+/// [Sampler::with_backend()](crate::sampling::Sampler::with_backend()).
+pub trait SamplerBackend {
+    /// Take the next record if there is one, never blocks.
+    ///
+    /// Callchains of samples should come from `pool`.
+    fn read_record(&self, pool: &SamplePool) -> Option<Record>;
+
+    /// Resume the event after [SamplerBackend::stop()].
+    fn start(&self) -> Result<(), TauphiError>;
+
+    /// Stop generating new records, the collected ones can still be read.
+    fn stop(&self) -> Result<(), TauphiError>;
+
+    /// Change the number of samples per second to generate.
+    fn set_frequency(&self, frequency: usize) -> Result<(), TauphiError>;
+
+    /// Occupancy of the buffer by unread records.
+    fn buffer_usage(&self) -> BufferUsage;
+
+    /// Whether the sampled process exited, no new records will come.
+    fn has_exited(&self) -> bool;
+
+    /// Sleep until new records are signalled, the sampled process exits or
+    /// the timeout passes. Returns false on timeout.
+    fn wait(&self, timeout: Duration) -> bool;
+}
+
+/// How much of a sampler's buffer is taken, see [SamplerBackend::buffer_usage()].
+///
+/// In bytes for perf events, other backends may use other units.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BufferUsage {
+    /// Unread records.
+    pub pending: usize,
+    /// Capacity of the buffer, zero if it has none.
+    pub size: usize,
+}
+
+impl BufferUsage {
+    /// Ratio of the buffer occupied by unread records, from 0 to 1.
+    pub fn fill(&self) -> f64 {
+        if self.size == 0 {
+            return 0.0;
+        }
+        self.pending as f64 / self.size as f64
+    }
+}
+
+/// Replays scripted records, for tests without perf events.
+///
+/// Behaves like a process which exits after its last record.
+///
+/// # Examples
+/// ```
+/// use tauphi_core::backend::MockBackend;
+/// use tauphi_core::sampling::{Record, Sample, Sampler, SamplerOptions};
+/// let backend = MockBackend::new([Record::Lost(2), Record::Sample(Sample::default())]);
+/// let sampler = Sampler::with_backend(backend, &SamplerOptions::new(10));
+/// assert!(sampler.get_sample().is_some());
+/// assert_eq!(sampler.stats().lost, 2);
+/// // The buffer was full before the first read.
+/// assert_eq!(sampler.stats().buffer_peak, 1.0);
+/// ```
+#[derive(Debug, Default)]
+pub struct MockBackend {
+    records: RefCell<VecDeque<Record>>,
+    /// Number of records the buffer holds, for [SamplerBackend::buffer_usage()].
+    capacity: usize,
+    running: Cell<bool>,
+    frequency: Cell<Option<usize>>,
+}
+
+impl MockBackend {
+    /// Replay the records in order.
+    pub fn new(records: impl IntoIterator<Item = Record>) -> MockBackend {
+        let records: VecDeque<_> = records.into_iter().collect();
+        MockBackend {
+            capacity: records.len(),
+            records: RefCell::new(records),
+            running: Cell::new(true),
+            frequency: Cell::new(None),
+        }
+    }
+
+    /// Whether the event is running, i.e. not stopped.
+    pub fn is_running(&self) -> bool {
+        self.running.get()
+    }
+
+    /// Last frequency set via [SamplerBackend::set_frequency()].
+    pub fn frequency(&self) -> Option<usize> {
+        self.frequency.get()
+    }
+}
+
+impl SamplerBackend for MockBackend {
+    fn read_record(&self, _pool: &SamplePool) -> Option<Record> {
+        self.records.borrow_mut().pop_front()
+    }
+
+    fn start(&self) -> Result<(), TauphiError> {
+        self.running.set(true);
+        Ok(())
+    }
+
+    fn stop(&self) -> Result<(), TauphiError> {
+        self.running.set(false);
+        Ok(())
+    }
+
+    fn set_frequency(&self, frequency: usize) -> Result<(), TauphiError> {
+        self.frequency.set(Some(frequency));
+        Ok(())
+    }
+
+    fn buffer_usage(&self) -> BufferUsage {
+        BufferUsage {
+            pending: self.records.borrow().len(),
+            size: self.capacity,
+        }
+    }
+
+    fn has_exited(&self) -> bool {
+        self.records.borrow().is_empty()
+    }
+
+    fn wait(&self, _timeout: Duration) -> bool {
+        true
+    }
+}
@@ -0,0 +1,97 @@
+//! Connecting samplers of bpf-output events to user BPF programs.
+//!
+//! A BPF program writes its records with `bpf_perf_event_output()` into a
+//! `BPF_MAP_TYPE_PERF_EVENT_ARRAY` map, indexed by CPU. Pinning the map lets
+//! tauphi put its own samplers there, each record then arrives as a sample
+//! of [Event::BpfOutput](crate::sampling::Event::BpfOutput).
+use std::ffi::CString;
+use std::io;
+use std::mem;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::path::Path;
+
+use crate::error::TauphiError;
+
+const BPF_MAP_UPDATE_ELEM: libc::c_long = 2;
+const BPF_OBJ_GET: libc::c_long = 7;
+
+/// `bpf_attr` for `BPF_OBJ_GET`.
+#[repr(C)]
+#[derive(Default)]
+struct ObjGetAttr {
+    pathname: u64,
+    bpf_fd: u32,
+    file_flags: u32,
+}
+
+/// `bpf_attr` for `BPF_MAP_UPDATE_ELEM`.
+#[repr(C)]
+#[derive(Default)]
+struct MapUpdateAttr {
+    map_fd: u32,
+    _pad: u32,
+    key: u64,
+    value: u64,
+    flags: u64,
+}
+
+/// Pinned `BPF_MAP_TYPE_PERF_EVENT_ARRAY` map of a BPF program.
+#[derive(Debug)]
+pub struct PerfEventArray {
+    fd: OwnedFd,
+}
+
+impl PerfEventArray {
+    /// Open the map pinned in the BPF filesystem.
+    ///
+    /// # Arguments
+    /// * `path` - Path of the pinned map, e.g. `/sys/fs/bpf/events`.
+    pub fn open(path: &Path) -> Result<PerfEventArray, TauphiError> {
+        let pathname = CString::new(path.as_os_str().as_encoded_bytes())
+            .map_err(|_| TauphiError::InvalidArgument(format!("{path:?} contains NUL")))?;
+        let attr = ObjGetAttr {
+            pathname: pathname.as_ptr() as u64,
+            ..Default::default()
+        };
+        let fd = bpf(BPF_OBJ_GET, &attr)?;
+        // SAFETY: The kernel returned a new descriptor owned by nobody else.
+        Ok(PerfEventArray {
+            fd: unsafe { OwnedFd::from_raw_fd(fd) },
+        })
+    }
+
+    /// Let the BPF program write records of `cpu` into `sampler`.
+    ///
+    /// # Arguments
+    /// * `cpu` - Key of the sampler, the sampler must sample only this CPU.
+    /// * `sampler` - Sampler of [Event::BpfOutput](crate::sampling::Event::BpfOutput).
+    pub fn insert(&self, cpu: u32, sampler: &impl AsRawFd) -> Result<(), TauphiError> {
+        let value = sampler.as_raw_fd() as u32;
+        let attr = MapUpdateAttr {
+            map_fd: self.fd.as_raw_fd() as u32,
+            key: &cpu as *const u32 as u64,
+            value: &value as *const u32 as u64,
+            ..Default::default()
+        };
+        bpf(BPF_MAP_UPDATE_ELEM, &attr)?;
+        Ok(())
+    }
+}
+
+/// Call the `bpf` syscall, returns its non-negative result.
+fn bpf<T>(command: libc::c_long, attr: &T) -> Result<libc::c_int, TauphiError> {
+    // SAFETY: `attr` is a valid `bpf_attr` prefix for `command`, the kernel
+    // zero-extends shorter ones.
+    let result = unsafe {
+        libc::syscall(
+            libc::SYS_bpf,
+            command,
+            attr as *const T,
+            mem::size_of::<T>() as libc::c_uint,
+        )
+    };
+    if result < 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    Ok(result as libc::c_int)
+}
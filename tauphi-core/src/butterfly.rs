@@ -0,0 +1,154 @@
+//! Direct callers and callees of a function, the butterfly view of gprof.
+//!
+//! Built from the callchains of samples. Each sample passing through the
+//! function counts once to its direct caller and once to its direct callee,
+//! or to the function itself if it was sampled in it. Recursive calls count
+//! at their innermost frame.
+use std::collections::HashMap;
+use std::slice;
+
+use crate::aggregate::PERF_CONTEXT_MAX;
+use crate::sampling::Sample;
+use crate::symbols::{Frame, SharedSymbolizer};
+
+/// Callers and callees of a function, see the module documentation.
+#[derive(Debug)]
+pub struct Butterfly {
+    /// Name of the function, see [Frame::is_function()].
+    function: String,
+    symbolizer: SharedSymbolizer,
+    /// All samples, also of other functions.
+    samples: u64,
+    /// Samples passing through the function.
+    through: u64,
+    callers: HashMap<Frame, u64>,
+    /// `None` for samples in the function itself.
+    callees: HashMap<Option<Frame>, u64>,
+}
+
+impl Butterfly {
+    /// Collect callers and callees of the function of the given name,
+    /// names frames by the symbolizer.
+    pub fn new(function: &str, symbolizer: SharedSymbolizer) -> Butterfly {
+        Butterfly {
+            function: function.to_owned(),
+            symbolizer,
+            samples: 0,
+            through: 0,
+            callers: HashMap::new(),
+            callees: HashMap::new(),
+        }
+    }
+
+    /// Account the sample if it passes through the function.
+    pub fn add_sample(&mut self, sample: &Sample) {
+        self.samples += 1;
+        let callchain = if sample.callchain.is_empty() {
+            slice::from_ref(&sample.ip)
+        } else {
+            &sample.callchain
+        };
+        let mut frames = callchain.iter().scan(true, |after_context, &frame| {
+            if frame >= PERF_CONTEXT_MAX {
+                *after_context = true;
+                return Some(None);
+            }
+            // Only the first address of each context is sampled, the rest
+            // are return addresses, just after the calls.
+            let ip = if *after_context {
+                frame
+            } else {
+                frame.saturating_sub(1)
+            };
+            *after_context = false;
+            Some(Some(ip))
+        });
+        let mut symbolizer = self.symbolizer.borrow_mut();
+        let mut callee = None;
+        while let Some(ip) = frames.by_ref().flatten().next() {
+            let frame = symbolizer.frame(sample.pid, ip);
+            if !frame.is_function(&self.function) {
+                callee = Some(frame);
+                continue;
+            }
+            self.through += 1;
+            *self.callees.entry(callee).or_default() += 1;
+            if let Some(ip) = frames.flatten().next() {
+                *self
+                    .callers
+                    .entry(symbolizer.frame(sample.pid, ip))
+                    .or_default() += 1;
+            }
+            return;
+        }
+    }
+
+    /// Name of the function.
+    pub fn function(&self) -> &str {
+        &self.function
+    }
+
+    /// All samples and those passing through the function.
+    pub fn samples(&self) -> (u64, u64) {
+        (self.samples, self.through)
+    }
+
+    /// Direct callers with their samples, the most sampled first.
+    pub fn callers(&self) -> Vec<(&Frame, u64)> {
+        let mut callers: Vec<_> = self
+            .callers
+            .iter()
+            .map(|(frame, &samples)| (frame, samples))
+            .collect();
+        callers.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+        callers
+    }
+
+    /// Direct callees with their samples, the most sampled first. `None`
+    /// stands for samples in the function itself.
+    pub fn callees(&self) -> Vec<(Option<&Frame>, u64)> {
+        let mut callees: Vec<_> = self
+            .callees
+            .iter()
+            .map(|(frame, &samples)| (frame.as_ref(), samples))
+            .collect();
+        callees.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        callees
+    }
+}
+
+#[test]
+fn butterfly_test() {
+    #[inline(never)]
+    fn outer() {}
+    #[inline(never)]
+    fn middle() {}
+
+    let address = |function: fn()| function as usize as u64;
+    let pid = std::process::id();
+    // Matched by the end of the path of its mangled symbol.
+    let mut butterfly = Butterfly::new("butterfly_test::middle", SharedSymbolizer::default());
+    let sample = |callchain: Vec<u64>| Sample {
+        pid,
+        callchain,
+        ..Default::default()
+    };
+    // Return addresses point past the calls, at least one byte in.
+    let (outer, middle) = (address(outer) + 1, address(middle) + 1);
+    let user = -512_i64 as u64;
+    butterfly.add_sample(&sample(vec![user, middle - 1, outer]));
+    butterfly.add_sample(&sample(vec![user, 1, middle, outer]));
+    butterfly.add_sample(&sample(vec![user, outer - 1]));
+
+    assert_eq!(butterfly.samples(), (3, 2));
+    let callers = butterfly.callers();
+    assert_eq!(callers.len(), 1);
+    assert!(callers[0].0.to_string().contains("outer"));
+    assert_eq!(callers[0].1, 2);
+    let callees: Vec<_> = butterfly
+        .callees()
+        .into_iter()
+        .map(|(frame, samples)| (frame.map(Frame::to_string), samples))
+        .collect();
+    assert_eq!(callees, [(None, 1), (Some("[unknown]".to_owned()), 1)]);
+}
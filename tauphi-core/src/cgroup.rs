@@ -0,0 +1,263 @@
+//! Resolution of cgroups and containers to sample, and of the cgroups
+//! sampled processes run in.
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Arc;
+
+use crate::error::TauphiError;
+use crate::sampling::Record;
+
+/// Container runtimes asked to translate container names to IDs.
+const RUNTIMES: [&str; 2] = ["docker", "podman"];
+
+/// Client of the Kubernetes container runtime interface (CRI).
+const CRICTL: &str = "crictl";
+
+/// Container of a Kubernetes pod.
+#[derive(Debug, PartialEq)]
+pub struct PodContainer {
+    /// Namespace of the pod.
+    pub namespace: String,
+    /// Name of the pod.
+    pub pod: String,
+    /// ID of the container.
+    pub id: String,
+    /// Cgroup of the container.
+    pub cgroup: PathBuf,
+}
+
+/// Cgroup v2 paths of sampled processes, read from `/proc/<pid>/cgroup`
+/// on their first lookup.
+///
+/// Runtimes create the cgroup of a container before moving its processes
+/// into it, the cached paths are therefore forgotten on [Record::Cgroup],
+/// see [SamplerOptions::cgroup_events](crate::sampling::SamplerOptions::cgroup_events).
+#[derive(Debug, Default)]
+pub struct CgroupCache {
+    /// `None` for processes which exited before they were read.
+    paths: HashMap<u32, Option<Arc<str>>>,
+}
+
+impl CgroupCache {
+    /// Invalidate the cached paths on records of new cgroups.
+    pub fn observe(&mut self, record: &Record) {
+        if let Record::Cgroup(event) = record {
+            tracing::debug!(id = event.id, "new cgroup {}", event.path);
+            self.paths.clear();
+        }
+    }
+
+    /// Path of the cgroup of the process relative to the cgroup v2 root,
+    /// e.g. `/system.slice/docker-<id>.scope`.
+    pub fn path(&mut self, pid: u32) -> Option<Arc<str>> {
+        self.paths
+            .entry(pid)
+            .or_insert_with(|| {
+                fs::read_to_string(format!("/proc/{pid}/cgroup"))
+                    .ok()
+                    .and_then(|cgroups| parse_cgroup_path(&cgroups).map(Arc::from))
+            })
+            .clone()
+    }
+}
+
+/// Cgroup v2 path in the contents of `/proc/<pid>/cgroup`, its line is
+/// `0::<path>`.
+fn parse_cgroup_path(cgroups: &str) -> Option<&str> {
+    cgroups.lines().find_map(|line| line.strip_prefix("0::"))
+}
+
+/// Mount point of the cgroup v2 hierarchy.
+pub fn cgroup2_root() -> Result<PathBuf, TauphiError> {
+    fs::read_to_string("/proc/self/mounts")
+        .map_err(TauphiError::file("/proc/self/mounts"))?
+        .lines()
+        .find_map(|mount| {
+            let mut fields = mount.split_whitespace().skip(1);
+            match (fields.next(), fields.next()) {
+                (Some(path), Some("cgroup2")) => Some(PathBuf::from(path)),
+                _ => None,
+            }
+        })
+        .ok_or_else(|| TauphiError::TargetNotFound("a mounted cgroup v2 hierarchy".to_owned()))
+}
+
+/// Resolve the cgroup directory, relative paths start at the cgroup v2 root.
+pub fn resolve_cgroup(path: &str) -> Result<PathBuf, TauphiError> {
+    let path = Path::new(path);
+    let path = if path.is_absolute() {
+        path.to_owned()
+    } else {
+        cgroup2_root()?.join(path)
+    };
+    if path.is_dir() {
+        Ok(path)
+    } else {
+        Err(TauphiError::TargetNotFound(format!(
+            "cgroup {}",
+            path.display()
+        )))
+    }
+}
+
+/// Find the cgroup of a docker, containerd or podman container.
+///
+/// IDs, also abbreviated, are looked up directly in the cgroup hierarchy
+/// where runtimes name the container's cgroup after its full ID,
+/// e.g. `docker-<id>.scope`. Names are first translated by the runtimes.
+pub fn resolve_container(name_or_id: &str) -> Result<PathBuf, TauphiError> {
+    let not_found = || TauphiError::TargetNotFound(format!("container '{name_or_id}'"));
+    let id = if is_container_id(name_or_id) {
+        name_or_id.to_owned()
+    } else {
+        RUNTIMES
+            .iter()
+            .find_map(|runtime| inspect_id(runtime, name_or_id))
+            .ok_or_else(not_found)?
+    };
+    find_cgroup(&cgroup2_root()?, &id)?.ok_or_else(not_found)
+}
+
+/// Find containers of Kubernetes pods running on this node.
+///
+/// The pods are listed through the CRI by `crictl`, optionally filtered by
+/// their name and namespace.
+pub fn resolve_pods(
+    namespace: Option<&str>,
+    pod: Option<&str>,
+) -> Result<Vec<PodContainer>, TauphiError> {
+    let mut args = vec!["pods", "--verbose"];
+    if let Some(namespace) = namespace {
+        args.extend(["--namespace", namespace]);
+    }
+    if let Some(pod) = pod {
+        args.extend(["--name", pod]);
+    }
+    let root = cgroup2_root()?;
+    let mut containers = Vec::new();
+    for (sandbox, namespace, pod) in parse_pods(&crictl(&args)?) {
+        for id in crictl(&["ps", "--quiet", "--pod", &sandbox])?.split_whitespace() {
+            if let Some(cgroup) = find_cgroup(&root, id)? {
+                containers.push(PodContainer {
+                    namespace: namespace.clone(),
+                    pod: pod.clone(),
+                    id: id.to_owned(),
+                    cgroup,
+                });
+            }
+        }
+    }
+    if containers.is_empty() {
+        return Err(TauphiError::TargetNotFound(format!(
+            "pod '{}' in namespace '{}'",
+            pod.unwrap_or("*"),
+            namespace.unwrap_or("*")
+        )));
+    }
+    Ok(containers)
+}
+
+/// Run `crictl` and return its output.
+fn crictl(args: &[&str]) -> Result<String, TauphiError> {
+    let output = Command::new(CRICTL).args(args).output()?;
+    if !output.status.success() {
+        return Err(TauphiError::TargetNotFound(format!(
+            "pods, {CRICTL} failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Parse (sandbox ID, namespace, name) of pods listed by `crictl pods -v`.
+fn parse_pods(listing: &str) -> Vec<(String, String, String)> {
+    let mut pods = Vec::new();
+    let (mut id, mut namespace, mut name) = (None, None, None);
+    // Pods are separated by empty lines, fields are `Key: value`.
+    for line in listing.lines().chain([""]) {
+        match line.split_once(": ") {
+            Some(("ID", value)) => id = Some(value.trim().to_owned()),
+            Some(("Namespace", value)) => namespace = Some(value.trim().to_owned()),
+            Some(("Name", value)) => name = Some(value.trim().to_owned()),
+            _ if line.trim().is_empty() => {
+                if let (Some(id), Some(namespace), Some(name)) =
+                    (id.take(), namespace.take(), name.take())
+                {
+                    pods.push((id, namespace, name));
+                }
+            }
+            _ => (),
+        }
+    }
+    pods
+}
+
+/// Whether the string looks like an (abbreviated) container ID.
+fn is_container_id(name_or_id: &str) -> bool {
+    name_or_id.len() >= 12 && name_or_id.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Ask the runtime for the ID of the named container.
+fn inspect_id(runtime: &str, name: &str) -> Option<String> {
+    let output = Command::new(runtime)
+        .args(["inspect", "--format", "{{.Id}}", name])
+        .output()
+        .ok()?;
+    let id = String::from_utf8(output.stdout).ok()?.trim().to_owned();
+    (output.status.success() && is_container_id(&id)).then_some(id)
+}
+
+/// Depth-first search for a cgroup named after the container ID.
+fn find_cgroup(dir: &Path, id: &str) -> Result<Option<PathBuf>, TauphiError> {
+    for entry in fs::read_dir(dir).map_err(TauphiError::file(dir))? {
+        let entry = entry?;
+        if !entry.file_type()?.is_dir() {
+            continue;
+        }
+        if entry.file_name().to_string_lossy().contains(id) {
+            return Ok(Some(entry.path()));
+        }
+        if let Some(found) = find_cgroup(&entry.path(), id)? {
+            return Ok(Some(found));
+        }
+    }
+    Ok(None)
+}
+
+#[test]
+fn cgroup_cache_test() {
+    use crate::sampling::CgroupEvent;
+
+    let hybrid = "12:cpuset:/\n1:name=systemd:/user.slice\n0::/user.slice/session-2.scope\n";
+    assert_eq!(
+        parse_cgroup_path(hybrid),
+        Some("/user.slice/session-2.scope")
+    );
+    assert_eq!(parse_cgroup_path("1:name=systemd:/\n"), None);
+
+    let mut cache = CgroupCache::default();
+    let own = cache.path(std::process::id()).unwrap();
+    assert!(own.starts_with('/'));
+    assert!(cache.path(u32::MAX).is_none());
+    cache.observe(&Record::Cgroup(CgroupEvent {
+        id: 1,
+        path: "/new".to_owned(),
+    }));
+    assert!(cache.paths.is_empty());
+    assert_eq!(cache.path(std::process::id()), Some(own));
+}
+
+#[test]
+fn parse_pods_test() {
+    let listing = "ID: 1a2b\nName: web\nUID: 42\nNamespace: prod\nAttempt: 0\n\n\
+                   ID: 3c4d\nName: db\nNamespace: prod\nLabels:\n\tapp -> db\n";
+    assert_eq!(
+        parse_pods(listing),
+        vec![
+            ("1a2b".to_owned(), "prod".to_owned(), "web".to_owned()),
+            ("3c4d".to_owned(), "prod".to_owned(), "db".to_owned()),
+        ]
+    );
+}
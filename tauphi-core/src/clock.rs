@@ -0,0 +1,64 @@
+//! Conversion of sample timestamps to wall-clock time.
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::sampling::Sample;
+
+/// Anchor of the monotonic sample timestamps in the wall-clock time.
+///
+/// Captured once at the start of the sampling, later changes of the system
+/// time, e.g. by NTP, are not reflected.
+#[derive(Debug, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct WallClock {
+    /// Nanoseconds from the Unix epoch to the zero of the monotonic clock.
+    pub offset: u64,
+}
+
+impl WallClock {
+    /// Measure the current offset between the monotonic and the real time.
+    pub fn capture() -> WallClock {
+        let before = monotonic_now();
+        let realtime = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as u64;
+        let after = monotonic_now();
+        // Assume the real time was read in the middle.
+        let monotonic = before + (after - before) / 2;
+        WallClock {
+            offset: realtime.saturating_sub(monotonic),
+        }
+    }
+
+    /// Wall-clock time of a monotonic timestamp in nanoseconds.
+    pub fn to_wall_time(&self, time: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_nanos(self.offset + time)
+    }
+}
+
+impl Sample {
+    /// Wall-clock time at which the sample was taken.
+    pub fn wall_time(&self, clock: &WallClock) -> SystemTime {
+        clock.to_wall_time(self.time)
+    }
+}
+
+/// Current time of the monotonic clock used for sample timestamps, in ns.
+pub fn monotonic_now() -> u64 {
+    let mut now = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut now) };
+    now.tv_sec as u64 * 1_000_000_000 + now.tv_nsec as u64
+}
+
+#[test]
+fn wall_clock_test() {
+    let clock = WallClock::capture();
+    let wall_time = clock.to_wall_time(monotonic_now());
+    let error = SystemTime::now()
+        .duration_since(wall_time)
+        .unwrap_or_else(|err| err.duration());
+    assert!(error < Duration::from_secs(1));
+}
@@ -0,0 +1,225 @@
+//! CPU usage of containers and pods, for hosts shared by many tenants.
+//!
+//! Processes are assigned to containers by their cgroup v2 path, which
+//! runtimes name after the container ID, e.g. `docker-<id>.scope` or
+//! `cri-containerd-<id>.scope`. Kubernetes nests the containers of a pod
+//! under a `pod<uid>` cgroup.
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::sync::Arc;
+
+use crate::cgroup::CgroupCache;
+use crate::sampling::{Record, Sample};
+
+/// Length of the full container IDs in cgroup names.
+const CONTAINER_ID_LEN: usize = 64;
+/// Length of abbreviated container IDs as shown by the runtimes.
+const SHORT_ID_LEN: usize = 12;
+
+/// Where a process runs, see [workload_of()].
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Workload {
+    /// A container, of a Kubernetes pod with the UID if any.
+    Container { pod: Option<String>, id: String },
+    /// Outside of any container.
+    Host,
+}
+
+impl fmt::Display for Workload {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Workload::Container { pod: Some(pod), id } => {
+                write!(f, "pod {pod} container {}", &id[..SHORT_ID_LEN])
+            }
+            Workload::Container { pod: None, id } => {
+                write!(f, "container {}", &id[..SHORT_ID_LEN])
+            }
+            Workload::Host => write!(f, "host"),
+        }
+    }
+}
+
+/// Workload of the cgroup v2 path from `/proc/<pid>/cgroup`, e.g.
+/// `/kubepods.slice/kubepods-pod<uid>.slice/cri-containerd-<id>.scope`.
+pub fn workload_of(cgroup: &str) -> Workload {
+    let mut pod = None;
+    for component in cgroup.split('/') {
+        // systemd escapes the dashes of the UID as underscores.
+        if let Some(uid) = component
+            .strip_suffix(".slice")
+            .unwrap_or(component)
+            .rsplit_once("pod")
+            .map(|(_, uid)| uid.replace('_', "-"))
+            .filter(|uid| uid.len() == 36)
+        {
+            pod = Some(uid);
+        }
+        let id = component
+            .strip_suffix(".scope")
+            .unwrap_or(component)
+            .rsplit(['-', ':'])
+            .next()
+            .filter(|id| id.len() == CONTAINER_ID_LEN)
+            .filter(|id| id.chars().all(|c| c.is_ascii_hexdigit()));
+        if let Some(id) = id {
+            return Workload::Container {
+                pod,
+                id: id.to_owned(),
+            };
+        }
+    }
+    Workload::Host
+}
+
+/// Samples of a process within its [Workload].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProcessUsage {
+    pub pid: u32,
+    /// Command name, `None` if the process exited before it was read.
+    pub command: Option<String>,
+    pub samples: u64,
+}
+
+/// Samples of a [Workload] and of its processes, the most sampled first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WorkloadUsage<'a> {
+    pub workload: &'a Workload,
+    pub samples: u64,
+    pub processes: Vec<&'a ProcessUsage>,
+}
+
+/// Groups samples by the [Workload] of their process.
+///
+/// Feed it also [Record::Cgroup] via [ContainerTable::observe()], processes
+/// moved into new containers are then assigned to them.
+#[derive(Debug, Default)]
+pub struct ContainerTable {
+    /// Processes of each workload by their PID.
+    workloads: HashMap<Workload, HashMap<u32, ProcessUsage>>,
+    cgroups: CgroupCache,
+    /// Workload of each seen cgroup path.
+    paths: HashMap<Arc<str>, Workload>,
+}
+
+impl ContainerTable {
+    /// Account samples, see [ContainerTable::add_sample()], and invalidate
+    /// cgroups of processes on [Record::Cgroup].
+    pub fn observe(&mut self, record: &Record) {
+        match record {
+            Record::Sample(sample) => self.add_sample(sample),
+            _ => self.cgroups.observe(record),
+        }
+    }
+
+    /// Account the sample to its process and workload, reads the cgroup
+    /// and the command of the process on its first sample.
+    pub fn add_sample(&mut self, sample: &Sample) {
+        let pid = sample.pid;
+        let workload = match self.cgroups.path(pid) {
+            Some(path) => self
+                .paths
+                .entry(path.clone())
+                .or_insert_with(|| workload_of(&path))
+                .clone(),
+            None => Workload::Host,
+        };
+        let process = self
+            .workloads
+            .entry(workload)
+            .or_default()
+            .entry(pid)
+            .or_insert_with(|| ProcessUsage {
+                pid,
+                command: fs::read_to_string(format!("/proc/{pid}/comm"))
+                    .ok()
+                    .map(|comm| comm.trim_end().to_owned()),
+                samples: 0,
+            });
+        process.samples += 1;
+    }
+
+    /// Workloads with their processes, the most sampled first.
+    pub fn workloads(&self) -> Vec<WorkloadUsage<'_>> {
+        let mut workloads: Vec<_> = self
+            .workloads
+            .iter()
+            .map(|(workload, processes)| {
+                let mut processes: Vec<_> = processes.values().collect();
+                processes.sort_by_key(|process| (u64::MAX - process.samples, process.pid));
+                WorkloadUsage {
+                    workload,
+                    samples: processes.iter().map(|process| process.samples).sum(),
+                    processes,
+                }
+            })
+            .collect();
+        workloads.sort_by(|a, b| {
+            b.samples
+                .cmp(&a.samples)
+                .then_with(|| a.workload.cmp(b.workload))
+        });
+        workloads
+    }
+}
+
+#[test]
+fn workload_of_test() {
+    let id = "3c4d5e6f7a8b9c0d1e2f3a4b5c6d7e8f9a0b1c2d3e4f5a6b7c8d9e0f1a2b3c4d";
+    let pod = "5f3a2b1c-0d9e-8f7a-6b5c-4d3e2f1a0b9c";
+    let container = |pod: Option<&str>| Workload::Container {
+        pod: pod.map(str::to_owned),
+        id: id.to_owned(),
+    };
+    assert_eq!(
+        workload_of(&format!("/system.slice/docker-{id}.scope")),
+        container(None)
+    );
+    assert_eq!(workload_of(&format!("/docker/{id}")), container(None));
+    let systemd_pod = pod.replace('-', "_");
+    assert_eq!(
+        workload_of(&format!(
+            "/kubepods.slice/kubepods-burstable.slice/\
+             kubepods-burstable-pod{systemd_pod}.slice/cri-containerd-{id}.scope"
+        )),
+        container(Some(pod))
+    );
+    assert_eq!(
+        workload_of(&format!("/kubepods/besteffort/pod{pod}/{id}")),
+        container(Some(pod))
+    );
+    assert_eq!(
+        workload_of("/user.slice/user-1000.slice/session-2.scope"),
+        Workload::Host
+    );
+    assert_eq!(workload_of("/"), Workload::Host);
+    assert_eq!(
+        container(Some(pod)).to_string(),
+        format!("pod {pod} container 3c4d5e6f7a8b")
+    );
+}
+
+#[test]
+fn container_table_test() {
+    let mut table = ContainerTable::default();
+    let sample = |pid| Sample {
+        pid,
+        ..Default::default()
+    };
+    // Processes which do not exist are accounted to the host.
+    for pid in [u32::MAX, u32::MAX - 1, u32::MAX] {
+        table.add_sample(&sample(pid));
+    }
+    let workloads = table.workloads();
+    assert_eq!(workloads.len(), 1);
+    assert_eq!(
+        (workloads[0].workload, workloads[0].samples),
+        (&Workload::Host, 3)
+    );
+    let pids: Vec<_> = workloads[0]
+        .processes
+        .iter()
+        .map(|process| (process.pid, process.samples))
+        .collect();
+    assert_eq!(pids, [(u32::MAX, 2), (u32::MAX - 1, 1)]);
+}
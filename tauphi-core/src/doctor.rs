@@ -0,0 +1,184 @@
+//! Checks of the environment for sampling, with fixes of common problems.
+use std::fs;
+use std::process;
+
+use crate::sampling::{mlock_limit_kb, num_cpus, Sampler, SamplerOptions};
+
+/// Outcome of a [Check].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Ok,
+    /// Sampling works, but with limitations.
+    Warning,
+    /// Sampling does not work.
+    Error,
+}
+
+/// Result of checking one aspect of the environment.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Check {
+    /// What was checked, e.g. `perf_event_paranoid`.
+    pub name: &'static str,
+    pub status: Status,
+    /// What was found.
+    pub detail: String,
+    /// How to resolve a warning or an error.
+    pub fix: Option<String>,
+}
+
+impl Check {
+    fn new(name: &'static str, status: Status, detail: impl Into<String>) -> Check {
+        Check {
+            name,
+            status,
+            detail: detail.into(),
+            fix: None,
+        }
+    }
+
+    fn with_fix(mut self, fix: impl Into<String>) -> Check {
+        self.fix = Some(fix.into());
+        self
+    }
+}
+
+/// Buffer space per CPU below which high frequencies lose samples.
+const MIN_LOCKED_KB_PER_CPU: u64 = 64;
+
+/// Check everything sampling depends on.
+pub fn run_checks() -> Vec<Check> {
+    let privileged = unsafe { libc::geteuid() } == 0;
+    let read = |path: &str| fs::read_to_string(path).map(|content| content.trim().to_owned());
+    let paranoid = match read("/proc/sys/kernel/perf_event_paranoid") {
+        Ok(level) => match level.parse() {
+            Ok(level) => check_paranoid(level, privileged),
+            Err(_) => Check::new("perf_event_paranoid", Status::Warning, level),
+        },
+        Err(_) => Check::new(
+            "perf_event_paranoid",
+            Status::Error,
+            "/proc/sys/kernel/perf_event_paranoid is missing",
+        )
+        .with_fix("Use a kernel built with CONFIG_PERF_EVENTS."),
+    };
+    let mlock = mlock_limit_kb().map_or_else(
+        || Check::new("perf_event_mlock_kb", Status::Warning, "unknown"),
+        |kb| check_mlock(kb as u64, num_cpus() as u64, privileged),
+    );
+    let kallsyms = match fs::read_to_string("/proc/kallsyms") {
+        Ok(kallsyms) => check_kallsyms(kallsyms.lines().next().unwrap_or_default()),
+        Err(err) => Check::new("kallsyms", Status::Warning, format!("unreadable: {err}"))
+            .with_fix("Kernel frames cannot be named without /proc/kallsyms."),
+    };
+    let clock = match read("/sys/devices/system/clocksource/clocksource0/current_clocksource") {
+        Ok(source) if source == "tsc" => Check::new("clocksource", Status::Ok, source),
+        Ok(source) => Check::new(
+            "clocksource",
+            Status::Warning,
+            format!("{source}, reading timestamps is slower than with tsc"),
+        )
+        .with_fix("Check `dmesg` for an unstable TSC, e.g. on VMs without invariant TSC."),
+        Err(_) => Check::new("clocksource", Status::Warning, "unknown"),
+    };
+    vec![
+        check_open(),
+        paranoid,
+        mlock,
+        kallsyms,
+        check_debug_info(),
+        clock,
+    ]
+}
+
+/// Whether perf events can be opened at all, for own user-space code.
+fn check_open() -> Check {
+    let options = SamplerOptions {
+        user_only: true,
+        ..SamplerOptions::new(1)
+    };
+    match Sampler::with_options(-1, process::id() as i32, &options) {
+        Ok(_) => Check::new("perf_event_open", Status::Ok, "available"),
+        Err(err) => Check::new("perf_event_open", Status::Error, format!("failed: {err}"))
+            .with_fix(
+                "Use a kernel built with CONFIG_PERF_EVENTS and allow the perf_event_open \
+                 syscall in seccomp profiles, e.g. of containers.",
+            ),
+    }
+}
+
+/// What the paranoid level allows to sample.
+fn check_paranoid(level: i32, privileged: bool) -> Check {
+    let name = "perf_event_paranoid";
+    let check = |status, detail: &str| Check::new(name, status, format!("{level}, {detail}"));
+    let lower = "Run as root or lower it with `sysctl kernel.perf_event_paranoid=1`.";
+    match level {
+        _ if privileged => check(Status::Ok, "ignored for root"),
+        ..=1 => check(Status::Ok, "CPUs and kernel code can be sampled"),
+        2 => check(
+            Status::Warning,
+            "only user space of own processes can be sampled, use --user-only",
+        )
+        .with_fix(lower),
+        _ => check(Status::Error, "sampling is disabled for unprivileged users").with_fix(lower),
+    }
+}
+
+/// Whether the buffers of all CPUs fit into the locked memory limit.
+fn check_mlock(limit_kb: u64, cpus: u64, privileged: bool) -> Check {
+    let per_cpu = limit_kb / cpus.max(1);
+    let detail = format!("{limit_kb} KiB, {per_cpu} KiB per CPU when sampling all {cpus}");
+    if privileged || per_cpu >= MIN_LOCKED_KB_PER_CPU {
+        return Check::new("perf_event_mlock_kb", Status::Ok, detail);
+    }
+    Check::new("perf_event_mlock_kb", Status::Warning, detail).with_fix(format!(
+        "Sampling all CPUs at high frequencies may fail or lose samples, raise it with \
+         `sysctl kernel.perf_event_mlock_kb={}`.",
+        MIN_LOCKED_KB_PER_CPU * cpus
+    ))
+}
+
+/// Whether kernel addresses are visible, judged from a line of
+/// `/proc/kallsyms`.
+fn check_kallsyms(line: &str) -> Check {
+    let address = line.split_whitespace().next().unwrap_or_default();
+    if !address.is_empty() && address.bytes().any(|digit| digit != b'0') {
+        return Check::new("kallsyms", Status::Ok, "kernel addresses are visible");
+    }
+    Check::new("kallsyms", Status::Warning, "kernel addresses are hidden")
+        .with_fix("Run as root or allow them with `sysctl kernel.kptr_restrict=0`.")
+}
+
+/// Whether separate debug info of system libraries is installed.
+fn check_debug_info() -> Check {
+    let installed = fs::read_dir("/usr/lib/debug/.build-id")
+        .map(|mut entries| entries.next().is_some())
+        .unwrap_or(false);
+    if installed {
+        return Check::new("debug info", Status::Ok, "found in /usr/lib/debug");
+    }
+    Check::new(
+        "debug info",
+        Status::Warning,
+        "no separate debug info in /usr/lib/debug, stripped libraries lack function names",
+    )
+    .with_fix("Install debug symbol packages, e.g. libc6-dbg or glibc-debuginfo.")
+}
+
+#[test]
+fn check_paranoid_test() {
+    assert_eq!(check_paranoid(1, false).status, Status::Ok);
+    assert_eq!(check_paranoid(2, false).status, Status::Warning);
+    assert_eq!(check_paranoid(4, false).status, Status::Error);
+    assert_eq!(check_paranoid(4, true).status, Status::Ok);
+    assert_eq!(check_mlock(516, 16, false).status, Status::Warning);
+    assert_eq!(check_mlock(516, 4, false).status, Status::Ok);
+}
+
+#[test]
+fn check_kallsyms_test() {
+    let visible = check_kallsyms("ffffffff81000000 T _stext");
+    assert_eq!(visible.status, Status::Ok);
+    let hidden = check_kallsyms("0000000000000000 T _stext");
+    assert_eq!(hidden.status, Status::Warning);
+    assert!(hidden.fix.is_some());
+}
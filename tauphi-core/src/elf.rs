@@ -0,0 +1,147 @@
+//! Headers of 64-bit ELF files in the native byte order, as needed for
+//! build IDs and symbol tables of mapped files.
+use std::fs::File;
+use std::os::unix::fs::FileExt;
+
+/// Smallest valid size of program header entries.
+const PROGRAM_HEADER_SIZE: u64 = 56;
+/// Smallest valid size of section header entries.
+const SECTION_HEADER_SIZE: u64 = 64;
+
+/// An entry of the program header table, a segment of the file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProgramHeader {
+    /// `p_type`, e.g. `PT_LOAD`.
+    pub kind: u32,
+    /// Position of the segment in the file.
+    pub offset: u64,
+    /// Virtual address of the segment once loaded.
+    pub address: u64,
+    /// Bytes of the segment in the file.
+    pub size: u64,
+}
+
+/// An entry of the section header table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SectionHeader {
+    /// `sh_type`, e.g. `SHT_SYMTAB`.
+    pub kind: u32,
+    /// Position of the section in the file.
+    pub offset: u64,
+    /// Bytes of the section in the file.
+    pub size: u64,
+    /// Index of the related section, e.g. the strings of a symbol table.
+    pub link: u32,
+    /// Size of the section's entries if it is a table.
+    pub entry_size: u64,
+}
+
+/// An opened ELF file, see [ElfFile::open()].
+#[derive(Debug)]
+pub struct ElfFile {
+    file: File,
+    header: [u8; 64],
+}
+
+impl ElfFile {
+    /// Open the file, `None` if it is no 64-bit ELF file of the native
+    /// byte order.
+    pub fn open(path: &str) -> Option<ElfFile> {
+        let file = File::open(path).ok()?;
+        let mut header = [0; 64];
+        file.read_exact_at(&mut header, 0).ok()?;
+        // Class 2 is 64-bit, encoding 1 little endian.
+        let native_encoding = if cfg!(target_endian = "little") { 1 } else { 2 };
+        if header[..4] != *b"\x7fELF" || header[4] != 2 || header[5] != native_encoding {
+            return None;
+        }
+        Some(ElfFile { file, header })
+    }
+
+    /// Entries of the program header table.
+    pub fn program_headers(&self) -> Option<Vec<ProgramHeader>> {
+        let entries = self.table(0x20, 0x36, 0x38, PROGRAM_HEADER_SIZE)?;
+        Some(
+            entries
+                .iter()
+                .map(|entry| ProgramHeader {
+                    kind: u32_at(entry, 0),
+                    offset: u64_at(entry, 8),
+                    address: u64_at(entry, 16),
+                    size: u64_at(entry, 32),
+                })
+                .collect(),
+        )
+    }
+
+    /// Entries of the section header table.
+    pub fn section_headers(&self) -> Option<Vec<SectionHeader>> {
+        let entries = self.table(0x28, 0x3a, 0x3c, SECTION_HEADER_SIZE)?;
+        Some(
+            entries
+                .iter()
+                .map(|entry| SectionHeader {
+                    kind: u32_at(entry, 4),
+                    offset: u64_at(entry, 24),
+                    size: u64_at(entry, 32),
+                    link: u32_at(entry, 40),
+                    entry_size: u64_at(entry, 56),
+                })
+                .collect(),
+        )
+    }
+
+    /// Read `size` bytes at the offset, `None` if larger than `max_size`.
+    pub fn read(&self, offset: u64, size: u64, max_size: u64) -> Option<Vec<u8>> {
+        if size > max_size {
+            return None;
+        }
+        let mut bytes = vec![0; size as usize];
+        self.file.read_exact_at(&mut bytes, offset).ok()?;
+        Some(bytes)
+    }
+
+    /// Entries of a header table, each cut to `min_size` bytes. The fields
+    /// are the positions of its offset, entry size and number of entries
+    /// in the ELF header.
+    fn table(
+        &self,
+        offset_at: usize,
+        size_at: usize,
+        count_at: usize,
+        min_size: u64,
+    ) -> Option<Vec<Vec<u8>>> {
+        let offset = u64_at(&self.header, offset_at);
+        let entry_size = u16_at(&self.header, size_at) as u64;
+        let count = u16_at(&self.header, count_at) as u64;
+        if count > 0 && entry_size < min_size {
+            return None;
+        }
+        (0..count)
+            .map(|index| self.read(offset + index * entry_size, min_size, min_size))
+            .collect()
+    }
+}
+
+pub(crate) fn u16_at(bytes: &[u8], at: usize) -> u16 {
+    u16::from_ne_bytes([bytes[at], bytes[at + 1]])
+}
+
+pub(crate) fn u32_at(bytes: &[u8], at: usize) -> u32 {
+    u32::from_ne_bytes(bytes[at..at + 4].try_into().unwrap())
+}
+
+pub(crate) fn u64_at(bytes: &[u8], at: usize) -> u64 {
+    u64::from_ne_bytes(bytes[at..at + 8].try_into().unwrap())
+}
+
+#[test]
+fn elf_file_test() {
+    let elf = ElfFile::open("/proc/self/exe").unwrap();
+    let segments = elf.program_headers().unwrap();
+    // PT_LOAD segments hold the code.
+    assert!(segments.iter().any(|segment| segment.kind == 1));
+    let sections = elf.section_headers().unwrap();
+    assert!(sections.iter().any(|section| section.size > 0));
+    assert!(ElfFile::open("/proc/self/maps").is_none());
+}
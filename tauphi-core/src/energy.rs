@@ -0,0 +1,166 @@
+//! Energy measurement via the RAPL `power` PMU.
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use perf_event as pe;
+
+use crate::error::TauphiError;
+
+/// Location of the RAPL PMU exposed by the kernel.
+const POWER_PMU: &str = "/sys/bus/event_source/devices/power";
+
+/// Energy consumed by one RAPL domain, e.g. `pkg` or `ram`.
+#[derive(Debug)]
+pub struct DomainEnergy {
+    /// Name of the domain, without the `energy-` prefix.
+    pub domain: String,
+    /// Energy consumed in Joules.
+    pub joules: f64,
+}
+
+/// Counter of a single RAPL domain.
+struct DomainCounter {
+    domain: String,
+    /// Joules per one counter increment.
+    scale: f64,
+    /// One counter per CPU package.
+    handles: Vec<pe::PerfEventHandle>,
+}
+
+/// Measures energy consumed by the machine since its creation.
+///
+/// RAPL counters are system-wide, they cannot be limited to a process.
+pub struct EnergyMeter {
+    counters: Vec<DomainCounter>,
+}
+
+impl EnergyMeter {
+    /// Open and start counters of all available RAPL domains.
+    pub fn start() -> Result<EnergyMeter, TauphiError> {
+        let pmu = Path::new(POWER_PMU);
+        let pmu_type: u32 = parse_sysfs(&pmu.join("type"))?;
+        // One CPU per package.
+        let cpumask = pmu.join("cpumask");
+        let cpus = parse_cpu_list(
+            fs::read_to_string(&cpumask)
+                .map_err(TauphiError::file(&cpumask))?
+                .trim(),
+        )?;
+
+        let events = pmu.join("events");
+        let mut counters = Vec::new();
+        for entry in fs::read_dir(&events).map_err(TauphiError::file(&events))? {
+            let file_name = entry?.file_name();
+            let Some(event) = file_name.to_str() else {
+                continue;
+            };
+            // Skip the .scale and .unit companions.
+            let Some(domain) = event.strip_prefix("energy-").filter(|d| !d.contains('.')) else {
+                continue;
+            };
+            let path = events.join(event);
+            let config =
+                parse_event_config(&fs::read_to_string(&path).map_err(TauphiError::file(&path))?)?;
+            let scale = parse_sysfs(&events.join(format!("{event}.scale")))?;
+            let handles = cpus
+                .iter()
+                .map(|&cpu| {
+                    let handle = pe::PerfEventHandle::new_counter(cpu, -1, pmu_type, config)?;
+                    handle.start(true)?;
+                    Ok(handle)
+                })
+                .collect::<Result<Vec<_>, pe::error::PerfError>>()?;
+            counters.push(DomainCounter {
+                domain: domain.to_owned(),
+                scale,
+                handles,
+            });
+        }
+        counters.sort_by(|a, b| a.domain.cmp(&b.domain));
+        Ok(EnergyMeter { counters })
+    }
+
+    /// Energy consumed by each domain since the start.
+    pub fn read(&self) -> Result<Vec<DomainEnergy>, TauphiError> {
+        self.counters
+            .iter()
+            .map(|counter| {
+                let mut total = 0;
+                for handle in &counter.handles {
+                    total += handle.read_counter()?;
+                }
+                Ok(DomainEnergy {
+                    domain: counter.domain.clone(),
+                    joules: total as f64 * counter.scale,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Split `joules` among processes by their share of the samples.
+///
+/// Only meaningful for CPU time sampling, returns (pid, Joules) pairs,
+/// the most consuming process first.
+pub fn attribute_by_samples(joules: f64, samples_per_pid: &HashMap<u32, u64>) -> Vec<(u32, f64)> {
+    let total: u64 = samples_per_pid.values().sum();
+    if total == 0 {
+        return Vec::new();
+    }
+    let mut shares: Vec<_> = samples_per_pid
+        .iter()
+        .map(|(&pid, &samples)| (pid, joules * samples as f64 / total as f64))
+        .collect();
+    shares.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+    shares
+}
+
+/// Parse a single value stored in a sysfs file.
+fn parse_sysfs<T: std::str::FromStr>(path: &Path) -> Result<T, TauphiError> {
+    fs::read_to_string(path)
+        .map_err(TauphiError::file(path))?
+        .trim()
+        .parse()
+        .map_err(|_| invalid_data(path.display()))
+}
+
+/// Parse an event description such as `event=0x02`.
+fn parse_event_config(description: &str) -> Result<u64, TauphiError> {
+    description
+        .trim()
+        .split(',')
+        .find_map(|term| term.strip_prefix("event="))
+        .and_then(|value| u64::from_str_radix(value.trim_start_matches("0x"), 16).ok())
+        .ok_or_else(|| invalid_data(description))
+}
+
+/// Parse a CPU list such as `0,2-3`.
+fn parse_cpu_list(list: &str) -> Result<Vec<i32>, TauphiError> {
+    let mut cpus = Vec::new();
+    for range in list.split(',').filter(|range| !range.is_empty()) {
+        let (first, last) = range.split_once('-').unwrap_or((range, range));
+        match (first.parse::<i32>(), last.parse::<i32>()) {
+            (Ok(first), Ok(last)) => cpus.extend(first..=last),
+            _ => return Err(invalid_data(list)),
+        }
+    }
+    Ok(cpus)
+}
+
+fn invalid_data(what: impl std::fmt::Display) -> TauphiError {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Unexpected RAPL data: {what}"),
+    )
+    .into()
+}
+
+#[test]
+fn parse_power_pmu_test() {
+    assert_eq!(parse_event_config("event=0x02\n").unwrap(), 2);
+    assert_eq!(parse_cpu_list("0,2-3").unwrap(), vec![0, 2, 3]);
+    let shares = attribute_by_samples(10.0, &HashMap::from([(1, 1), (2, 3)]));
+    assert_eq!(shares, vec![(2, 7.5), (1, 2.5)]);
+}
@@ -0,0 +1,41 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use thiserror::Error;
+
+use perf_event as pe;
+
+#[derive(Error, Debug)]
+pub enum TauphiError {
+    #[error(transparent)]
+    Perf(#[from] pe::error::PerfError),
+    /// Opening a sampler of the target, e.g. `PID 42`, failed.
+    #[error("Could not sample {target}: {source}")]
+    Sampler {
+        target: String,
+        #[source]
+        source: pe::error::PerfError,
+    },
+    #[error("Could not access {}: {source}", .path.display())]
+    File {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error("IO error")]
+    IO(#[from] io::Error),
+    #[error("Invalid command line: {0}.")]
+    InvalidArgument(String),
+    #[error("Could not find {0}.")]
+    TargetNotFound(String),
+    #[error("Malformed perf record: {0}.")]
+    MalformedRecord(String),
+}
+
+impl TauphiError {
+    /// Wrap an error of accessing the file, for [Result::map_err()].
+    pub(crate) fn file(path: impl AsRef<Path>) -> impl FnOnce(io::Error) -> TauphiError {
+        let path = path.as_ref().to_owned();
+        move |source| TauphiError::File { path, source }
+    }
+}
@@ -0,0 +1,189 @@
+//! Event selection in the syntax of perf, e.g. `cycles:u`,
+//! `cache-misses/period=10000/` or `sched:sched_switch`.
+use std::fs;
+use std::str::FromStr;
+
+use crate::error::TauphiError;
+use crate::sampling::{Event, SamplerOptions};
+
+/// Modifiers perf accepts after the colon, only `u`, `k` and `p` are supported.
+const MODIFIERS: &str = "ukhpPGHSDIWe";
+
+/// Mount points of tracefs, the first one with the tracepoint is used.
+const TRACEFS: [&str; 2] = ["/sys/kernel/tracing", "/sys/kernel/debug/tracing"];
+
+/// Event with its modifiers and terms, parsed from the perf syntax
+/// `<event>[/<term>=<value>,.../][:<modifiers>]`.
+///
+/// Supported terms are `period` and `freq`, supported modifiers `u` for
+/// user space only, `k` for the kernel only and up to three `p` for the
+/// precise level.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EventSpec {
+    /// The sampled event.
+    pub event: Event,
+    /// Whether only user space is sampled, `:u`.
+    pub user_only: bool,
+    /// Whether only the kernel is sampled, `:k`.
+    pub kernel_only: bool,
+    /// Fixed sampling period, `period=<N>`.
+    pub period: Option<u64>,
+    /// Sampling frequency, `freq=<N>`.
+    pub frequency: Option<usize>,
+    /// Requested skid of the samples, one per `p`, see
+    /// [SamplerOptions::precise].
+    pub precise: u8,
+}
+
+impl EventSpec {
+    /// Set the event and its configuration in the options.
+    pub fn apply(&self, options: &mut SamplerOptions) {
+        options.event = self.event;
+        options.user_only |= self.user_only;
+        options.kernel_only |= self.kernel_only;
+        if self.period.is_some() {
+            options.period = self.period;
+        }
+        if let Some(frequency) = self.frequency {
+            options.frequency = frequency;
+        }
+        options.precise = options.precise.max(self.precise);
+    }
+
+    /// Parse the event, resolving tracepoints by `tracepoint_id`.
+    fn parse_with(
+        spec: &str,
+        tracepoint_id: impl Fn(&str, &str) -> Result<u64, TauphiError>,
+    ) -> Result<EventSpec, TauphiError> {
+        let invalid = |reason: &str| TauphiError::InvalidArgument(format!("{reason} in '{spec}'"));
+        let (name, terms, modifiers) = match spec.split_once('/') {
+            Some((name, rest)) => {
+                let (terms, suffix) = rest
+                    .rsplit_once('/')
+                    .ok_or_else(|| invalid("unterminated terms"))?;
+                let modifiers = match suffix {
+                    "" => "",
+                    _ => suffix
+                        .strip_prefix(':')
+                        .ok_or_else(|| invalid("unexpected characters after terms"))?,
+                };
+                (name, terms, modifiers)
+            }
+            // The last part is either modifiers or the tracepoint's name.
+            None => match spec.rsplit_once(':') {
+                Some((name, modifiers))
+                    if !modifiers.is_empty()
+                        && modifiers
+                            .chars()
+                            .all(|modifier| MODIFIERS.contains(modifier)) =>
+                {
+                    (name, "", modifiers)
+                }
+                _ => (spec, "", ""),
+            },
+        };
+
+        let event = match name.split_once(':') {
+            Some((subsystem, tracepoint)) => {
+                Event::Tracepoint(tracepoint_id(subsystem, tracepoint)?)
+            }
+            None => name.parse()?,
+        };
+        let mut parsed = EventSpec {
+            event,
+            user_only: false,
+            kernel_only: false,
+            period: None,
+            frequency: None,
+            precise: 0,
+        };
+        for term in terms.split(',').filter(|term| !term.is_empty()) {
+            let (key, value) = term
+                .split_once('=')
+                .ok_or_else(|| invalid(&format!("term '{term}' without a value")))?;
+            let invalid_value = || invalid(&format!("invalid value of '{key}'"));
+            match key {
+                "period" => parsed.period = Some(value.parse().map_err(|_| invalid_value())?),
+                "freq" => parsed.frequency = Some(value.parse().map_err(|_| invalid_value())?),
+                _ => return Err(invalid(&format!("unsupported term '{key}'"))),
+            }
+        }
+        let (user, kernel) = (modifiers.contains('u'), modifiers.contains('k'));
+        if let Some(modifier) = modifiers
+            .chars()
+            .find(|&modifier| !"ukp".contains(modifier))
+        {
+            return Err(invalid(&format!("unsupported modifier '{modifier}'")));
+        }
+        parsed.precise = match modifiers.matches('p').count() {
+            precise @ 0..=3 => precise as u8,
+            _ => return Err(invalid("more than three 'p' modifiers")),
+        };
+        // Both is the same as none.
+        parsed.user_only = user && !kernel;
+        parsed.kernel_only = kernel && !user;
+        Ok(parsed)
+    }
+}
+
+impl FromStr for EventSpec {
+    type Err = TauphiError;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        EventSpec::parse_with(spec, tracepoint_id)
+    }
+}
+
+/// ID of the tracepoint, e.g. of `sched` and `sched_switch`, from tracefs.
+pub fn tracepoint_id(subsystem: &str, name: &str) -> Result<u64, TauphiError> {
+    let not_found = || TauphiError::TargetNotFound(format!("tracepoint '{subsystem}:{name}'"));
+    // Keep the names from escaping the events directory.
+    if [subsystem, name]
+        .iter()
+        .any(|part| part.is_empty() || part.contains(['/', '.']))
+    {
+        return Err(not_found());
+    }
+    TRACEFS
+        .iter()
+        .find_map(|tracefs| {
+            fs::read_to_string(format!("{tracefs}/events/{subsystem}/{name}/id")).ok()
+        })
+        .and_then(|id| id.trim().parse().ok())
+        .ok_or_else(not_found)
+}
+
+#[test]
+fn parse_event_spec_test() {
+    let parse = |spec| {
+        EventSpec::parse_with(spec, |subsystem, name| match (subsystem, name) {
+            ("sched", "sched_switch") => Ok(316),
+            _ => Err(TauphiError::TargetNotFound(name.to_owned())),
+        })
+    };
+    let cycles = parse("cycles:u").unwrap();
+    assert_eq!((cycles.event, cycles.user_only), (Event::Cycles, true));
+    let misses = parse("cache-misses/period=10000/").unwrap();
+    assert_eq!(
+        (misses.event, misses.period),
+        (Event::CacheMisses, Some(10000))
+    );
+    let switches = parse("sched:sched_switch").unwrap();
+    assert_eq!(switches.event, Event::Tracepoint(316));
+    let kernel = parse("sched:sched_switch:k").unwrap();
+    assert!(kernel.kernel_only && !kernel.user_only);
+    let both = parse("task-clock/freq=99/:uk").unwrap();
+    assert_eq!(both.frequency, Some(99));
+    assert!(!both.user_only && !both.kernel_only);
+
+    let precise = parse("cycles:upp").unwrap();
+    assert_eq!((precise.precise, precise.user_only), (2, true));
+
+    assert!(parse("cycles:pppp").is_err());
+    assert!(parse("cycles:P").is_err());
+    assert!(parse("cycles/period=x/").is_err());
+    assert!(parse("cycles/config=1/").is_err());
+    assert!(parse("cycles/period=1").is_err());
+    assert!(parse("sched:missing").is_err());
+    assert!(parse("unknown").is_err());
+}
@@ -0,0 +1,584 @@
+//! Output formats of samples.
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, HashMap};
+use std::io::Write;
+use std::slice;
+use std::time::Duration;
+
+use crate::aggregate::{SpillingStackTrie, PERF_CONTEXT_MAX};
+use crate::error::TauphiError;
+use crate::sampling::{Event, Sample};
+use crate::sink::{DebugSink, SampleSink};
+use crate::symbols::{Frame, SharedSymbolizer};
+use crate::timeline::TimelineSink;
+
+/// Functions through which programs and threads start, by their symbols.
+const STARTUP_FUNCTIONS: &[&str] = &[
+    "_start",
+    "__libc_start_main",
+    "__libc_start_call_main",
+    "start_thread",
+    "clone",
+    "clone3",
+    "__clone",
+    "__clone3",
+    "thread_start",
+];
+
+/// Parts of the mangled names of Rust's startup and thread entry shims,
+/// e.g. `std::rt::lang_start` and the closures passed through it.
+const STARTUP_PATHS: &[&str] = &[
+    "3std2rt",
+    "__rust_begin_short_backtrace",
+    "3std9panicking3try",
+    "3std5panic12catch_unwind",
+    "4core3ops8function6FnOnce9call_once",
+    "3std6thread7Builder16spawn_unchecked_",
+    "6Thread3new12thread_start",
+];
+
+/// An output format of samples.
+pub trait Exporter {
+    /// Name of the format used to select it, e.g. `csv`.
+    fn name(&self) -> &str;
+
+    /// Start exporting samples into the writer.
+    fn sink(&self, writer: Box<dyn Write>) -> Box<dyn SampleSink>;
+}
+
+/// Available output formats keyed by their name.
+#[derive(Default)]
+pub struct ExporterRegistry {
+    exporters: BTreeMap<String, Box<dyn Exporter>>,
+}
+
+impl ExporterRegistry {
+    /// Registry of the formats built into tauphi.
+    pub fn with_builtin() -> ExporterRegistry {
+        let mut registry = ExporterRegistry::default();
+        registry.register(Box::new(DebugExporter));
+        registry.register(Box::new(CsvExporter));
+        registry.register(Box::new(EventTableExporter));
+        registry.register(Box::new(FoldedExporter::default()));
+        registry.register(Box::new(TimelineExporter));
+        registry
+    }
+
+    /// Add the format, replacing an existing one of the same name.
+    pub fn register(&mut self, exporter: Box<dyn Exporter>) {
+        self.exporters.insert(exporter.name().to_owned(), exporter);
+    }
+
+    /// Format of the given name.
+    pub fn get(&self, name: &str) -> Option<&dyn Exporter> {
+        self.exporters.get(name).map(Box::as_ref)
+    }
+
+    /// Names of all formats, sorted.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.exporters.keys().map(String::as_str)
+    }
+}
+
+/// Numbered samples in their debug representation, see [DebugSink].
+struct DebugExporter;
+
+impl Exporter for DebugExporter {
+    fn name(&self) -> &str {
+        "debug"
+    }
+
+    fn sink(&self, writer: Box<dyn Write>) -> Box<dyn SampleSink> {
+        Box::new(DebugSink::new(writer))
+    }
+}
+
+/// One sample per line, see [CsvSink].
+struct CsvExporter;
+
+impl Exporter for CsvExporter {
+    fn name(&self) -> &str {
+        "csv"
+    }
+
+    fn sink(&self, writer: Box<dyn Write>) -> Box<dyn SampleSink> {
+        Box::new(CsvSink::new(writer))
+    }
+}
+
+/// Samples per IP and event, see [EventTableSink].
+struct EventTableExporter;
+
+impl Exporter for EventTableExporter {
+    fn name(&self) -> &str {
+        "events"
+    }
+
+    fn sink(&self, writer: Box<dyn Write>) -> Box<dyn SampleSink> {
+        Box::new(EventTableSink::new(writer))
+    }
+}
+
+/// Samples per process and second, see [TimelineSink].
+struct TimelineExporter;
+
+impl Exporter for TimelineExporter {
+    fn name(&self) -> &str {
+        "timeline"
+    }
+
+    fn sink(&self, writer: Box<dyn Write>) -> Box<dyn SampleSink> {
+        Box::new(TimelineSink::new(writer, Duration::from_secs(1)))
+    }
+}
+
+/// Unique stacks with their sample counts, see [FoldedSink].
+#[derive(Default)]
+pub struct FoldedExporter {
+    memory_budget: Option<usize>,
+    pruning: Pruning,
+    symbolizer: SharedSymbolizer,
+}
+
+impl FoldedExporter {
+    /// Name frames by the symbolizer, see [FoldedSink::with_symbolizer()].
+    pub fn with_symbolizer(mut self, symbolizer: SharedSymbolizer) -> FoldedExporter {
+        self.symbolizer = symbolizer;
+        self
+    }
+
+    /// Leave out stacks, see [FoldedSink::with_pruning()].
+    pub fn with_pruning(mut self, pruning: Pruning) -> FoldedExporter {
+        self.pruning = pruning;
+        self
+    }
+
+    /// Limit the memory of the aggregated stacks to `bytes`, see
+    /// [FoldedSink::with_memory_budget()].
+    pub fn with_memory_budget(mut self, bytes: usize) -> FoldedExporter {
+        self.memory_budget = Some(bytes);
+        self
+    }
+}
+
+impl Exporter for FoldedExporter {
+    fn name(&self) -> &str {
+        "folded"
+    }
+
+    fn sink(&self, writer: Box<dyn Write>) -> Box<dyn SampleSink> {
+        let sink = FoldedSink::new(writer)
+            .with_pruning(self.pruning)
+            .with_symbolizer(self.symbolizer.clone());
+        match self.memory_budget {
+            Some(bytes) => Box::new(sink.with_memory_budget(bytes)),
+            None => Box::new(sink),
+        }
+    }
+}
+
+/// Which stacks [FoldedSink] leaves out, keeps huge profiles renderable.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Pruning {
+    /// Leave out stacks with a smaller share of all samples, from 0 to 1.
+    pub min_share: Option<f64>,
+    /// Keep only this many stacks, those with the most samples.
+    pub top: Option<usize>,
+    /// Cut stacks to this many frames nearest to the sampled instruction.
+    pub max_frames: Option<usize>,
+    /// Strip startup frames such as `_start` from the roots of stacks.
+    pub trim_startup: bool,
+}
+
+/// Aggregates samples into a [StackTrie](crate::aggregate::StackTrie) and
+/// writes the folded stacks when finished.
+pub struct FoldedSink<W: Write> {
+    writer: W,
+    trie: SpillingStackTrie,
+    pruning: Pruning,
+    /// Names the root frames for [Pruning::trim_startup].
+    symbolizer: SharedSymbolizer,
+    /// Number of consumed samples.
+    samples: u64,
+}
+
+impl<W: Write> FoldedSink<W> {
+    /// Write the stacks to the writer.
+    pub fn new(writer: W) -> FoldedSink<W> {
+        FoldedSink {
+            writer,
+            trie: SpillingStackTrie::new(usize::MAX),
+            pruning: Pruning::default(),
+            symbolizer: SharedSymbolizer::default(),
+            samples: 0,
+        }
+    }
+
+    /// Leave out stacks when writing them.
+    ///
+    /// Shares are of all samples, including those of left out stacks.
+    pub fn with_pruning(mut self, pruning: Pruning) -> FoldedSink<W> {
+        self.pruning = pruning;
+        self
+    }
+
+    /// Name frames by the symbolizer, e.g. one shared with the reports,
+    /// instead of reading the symbols anew.
+    pub fn with_symbolizer(mut self, symbolizer: SharedSymbolizer) -> FoldedSink<W> {
+        self.symbolizer = symbolizer;
+        self
+    }
+
+    /// Keep at most `bytes` of aggregated stacks in memory, the rest is
+    /// spilled to temporary files, see [SpillingStackTrie].
+    pub fn with_memory_budget(mut self, bytes: usize) -> FoldedSink<W> {
+        self.trie = SpillingStackTrie::new(bytes);
+        self
+    }
+}
+
+impl<W: Write> FoldedSink<W> {
+    /// Start of the startup frames at the root of the callchain, its
+    /// length if there are none. The innermost frame is always kept.
+    fn startup_frames(&mut self, pid: u32, callchain: &[u64]) -> usize {
+        let Some(innermost) = callchain.iter().position(|&frame| frame < PERF_CONTEXT_MAX) else {
+            return callchain.len();
+        };
+        let mut end = callchain.len();
+        while end > innermost + 1 {
+            let frame = callchain[end - 1];
+            if frame < PERF_CONTEXT_MAX {
+                // Return addresses point just after the calls.
+                let ip = match callchain[end - 2] {
+                    previous if previous >= PERF_CONTEXT_MAX => frame,
+                    _ => frame.saturating_sub(1),
+                };
+                let Frame::Function { name, .. } = self.symbolizer.borrow_mut().frame(pid, ip)
+                else {
+                    break;
+                };
+                if !is_startup_function(&name) {
+                    break;
+                }
+            }
+            end -= 1;
+        }
+        end
+    }
+}
+
+/// Whether the function only starts the program or a thread, see
+/// [Pruning::trim_startup].
+fn is_startup_function(name: &str) -> bool {
+    STARTUP_FUNCTIONS.contains(&name) || STARTUP_PATHS.iter().any(|path| name.contains(path))
+}
+
+impl<W: Write> SampleSink for FoldedSink<W> {
+    fn consume(&mut self, sample: &Sample) -> Result<(), TauphiError> {
+        self.samples += 1;
+        let callchain = if sample.callchain.is_empty() {
+            slice::from_ref(&sample.ip)
+        } else {
+            &sample.callchain
+        };
+        let mut end = callchain.len();
+        if self.pruning.trim_startup {
+            end = self.startup_frames(sample.pid, callchain);
+        }
+        if let Some(max_frames) = self.pruning.max_frames {
+            // Context markers are not frames.
+            let mut frames = 0;
+            end = callchain
+                .iter()
+                .position(|&frame| {
+                    frames += usize::from(frame < PERF_CONTEXT_MAX);
+                    frames > max_frames
+                })
+                .map_or(end, |cut| cut.min(end));
+        }
+        self.trie.add(&callchain[..end], 1)
+    }
+
+    fn finish(&mut self) -> Result<(), TauphiError> {
+        let min_samples = self.pruning.min_share.unwrap_or(0.0) * self.samples as f64;
+        let writer = &mut self.writer;
+        match self.pruning.top {
+            None => self.trie.for_each_folded(|stack, samples| {
+                if samples as f64 >= min_samples {
+                    writeln!(writer, "{stack} {samples}")?;
+                }
+                Ok(())
+            })?,
+            Some(top) => {
+                // The smallest kept stack on top, the later one on ties.
+                let mut kept = BinaryHeap::new();
+                let mut order = 0;
+                self.trie.for_each_folded(|stack, samples| {
+                    if samples as f64 >= min_samples {
+                        kept.push(Reverse((samples, Reverse(order), stack.to_owned())));
+                        if kept.len() > top {
+                            kept.pop();
+                        }
+                    }
+                    order += 1;
+                    Ok(())
+                })?;
+                let mut kept: Vec<_> = kept
+                    .into_iter()
+                    .map(|Reverse((samples, Reverse(order), stack))| (order, stack, samples))
+                    .collect();
+                kept.sort_unstable();
+                for (_, stack, samples) in kept {
+                    writeln!(writer, "{stack} {samples}")?;
+                }
+            }
+        }
+        Ok(self.writer.flush()?)
+    }
+}
+
+/// Counts samples of each IP by their event and writes them as
+/// comma-separated values when finished, with one column per event.
+///
+/// Compares the events side by side, e.g. cycles and cache misses of the
+/// same code. Rows are sorted by the first seen event.
+pub struct EventTableSink<W: Write> {
+    writer: W,
+    /// Events of the columns, in the order they were first seen.
+    events: Vec<Event>,
+    /// Samples of each IP, indexed like [EventTableSink::events].
+    counts: HashMap<u64, Vec<u64>>,
+}
+
+impl<W: Write> EventTableSink<W> {
+    /// Write the table to the writer.
+    pub fn new(writer: W) -> EventTableSink<W> {
+        EventTableSink {
+            writer,
+            events: Vec::new(),
+            counts: HashMap::new(),
+        }
+    }
+}
+
+impl<W: Write> SampleSink for EventTableSink<W> {
+    fn consume(&mut self, sample: &Sample) -> Result<(), TauphiError> {
+        let column = match self.events.iter().position(|&event| event == sample.event) {
+            Some(column) => column,
+            None => {
+                self.events.push(sample.event);
+                self.events.len() - 1
+            }
+        };
+        let counts = self.counts.entry(sample.ip).or_default();
+        if counts.len() <= column {
+            counts.resize(column + 1, 0);
+        }
+        counts[column] += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), TauphiError> {
+        let names: Vec<_> = self.events.iter().map(|event| event.name()).collect();
+        writeln!(self.writer, "ip,{}", names.join(","))?;
+        let mut rows: Vec<_> = self.counts.iter().collect();
+        rows.sort_unstable_by_key(|&(ip, counts)| (Reverse(counts[0]), *ip));
+        for (ip, counts) in rows {
+            let columns: Vec<_> = (0..self.events.len())
+                .map(|column| counts.get(column).copied().unwrap_or(0).to_string())
+                .collect();
+            writeln!(self.writer, "{ip:#x},{}", columns.join(","))?;
+        }
+        Ok(self.writer.flush()?)
+    }
+}
+
+/// Writes samples as comma-separated values with a header.
+///
+/// Addresses of the callchain are separated by semicolons.
+pub struct CsvSink<W: Write> {
+    writer: W,
+    /// Whether the header was written already.
+    header: bool,
+}
+
+impl<W: Write> CsvSink<W> {
+    /// Write the samples to the writer.
+    pub fn new(writer: W) -> CsvSink<W> {
+        CsvSink {
+            writer,
+            header: false,
+        }
+    }
+}
+
+impl<W: Write> SampleSink for CsvSink<W> {
+    fn consume(&mut self, sample: &Sample) -> Result<(), TauphiError> {
+        if !self.header {
+            writeln!(self.writer, "time,cpu,pid,tid,ip,callchain")?;
+            self.header = true;
+        }
+        let callchain: Vec<_> = sample
+            .callchain
+            .iter()
+            .map(|ip| format!("{ip:#x}"))
+            .collect();
+        writeln!(
+            self.writer,
+            "{},{},{},{},{:#x},{}",
+            sample.time,
+            sample.cpu,
+            sample.pid,
+            sample.tid,
+            sample.ip,
+            callchain.join(";")
+        )?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), TauphiError> {
+        Ok(self.writer.flush()?)
+    }
+}
+
+#[test]
+fn folded_pruning_test() {
+    let mut output = Vec::new();
+    let mut sink = FoldedSink::new(&mut output).with_pruning(Pruning {
+        min_share: Some(0.15),
+        top: Some(2),
+        max_frames: Some(2),
+        ..Default::default()
+    });
+    let callchains = [
+        [1, 2, 3],
+        [1, 2, 4],
+        [u64::MAX, 1, 2],
+        [5, 6, 7],
+        [5, 6, 7],
+        [10, 11, 12],
+        [10, 11, 12],
+        [8, 9, 9],
+    ];
+    for callchain in callchains {
+        let sample = Sample {
+            callchain: callchain.to_vec(),
+            ..Default::default()
+        };
+        sink.consume(&sample).unwrap();
+    }
+    sink.finish().unwrap();
+    // 0x8 is below the share, 0xa ties with 0x5 but came later.
+    let output = String::from_utf8(output).unwrap();
+    let mut lines: Vec<_> = output.lines().collect();
+    lines.sort_unstable();
+    assert_eq!(lines, ["0x2;0x1 3", "0x6;0x5 2"]);
+}
+
+#[test]
+fn event_table_sink_test() {
+    let mut output = Vec::new();
+    let mut sink = EventTableSink::new(&mut output);
+    let samples = [
+        (0x10, Event::Cycles),
+        (0x20, Event::Cycles),
+        (0x20, Event::Cycles),
+        (0x10, Event::CacheMisses),
+        (0x30, Event::CacheMisses),
+    ];
+    for (ip, event) in samples {
+        let sample = Sample {
+            ip,
+            event,
+            ..Default::default()
+        };
+        sink.consume(&sample).unwrap();
+    }
+    sink.finish().unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "ip,cycles,cache-misses\n0x20,2,0\n0x10,1,1\n0x30,0,1\n"
+    );
+}
+
+#[test]
+fn csv_sink_test() {
+    let mut output = Vec::new();
+    let mut sink = CsvSink::new(&mut output);
+    let sample = Sample {
+        ip: 0x10,
+        pid: 1,
+        tid: 2,
+        time: 3,
+        cpu: 4,
+        callchain: vec![0x10, 0x20],
+        ..Default::default()
+    };
+    sink.consume(&sample).unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "time,cpu,pid,tid,ip,callchain\n3,4,1,2,0x10,0x10;0x20\n"
+    );
+    let registry = ExporterRegistry::with_builtin();
+    assert_eq!(
+        registry.names().collect::<Vec<_>>(),
+        ["csv", "debug", "events", "folded", "timeline"]
+    );
+}
+
+#[test]
+fn folded_trim_startup_test() {
+    #[inline(never)]
+    fn leaf() {}
+
+    // The entry point of the test binary is _start.
+    let exe = std::fs::read("/proc/self/exe").unwrap();
+    let entry = u64::from_ne_bytes(exe[0x18..0x20].try_into().unwrap());
+    let position_independent = u16::from_ne_bytes([exe[0x10], exe[0x11]]) == 3;
+    let path = std::fs::read_link("/proc/self/exe").unwrap();
+    let maps = std::fs::read_to_string("/proc/self/maps").unwrap();
+    let base = crate::maps::parse_maps(&maps)
+        .into_iter()
+        .find(|mapping| mapping.offset == 0 && mapping.path.as_deref() == path.to_str())
+        .unwrap()
+        .addresses
+        .start;
+    let start = if position_independent {
+        base + entry
+    } else {
+        entry
+    };
+    let leaf = leaf as fn() as usize as u64;
+    assert!(is_startup_function("__libc_start_main"));
+    assert!(is_startup_function(
+        "_ZN3std2rt10lang_start17h0123456789abcdefE"
+    ));
+    assert!(!is_startup_function("main"));
+
+    let mut output = Vec::new();
+    let mut sink = FoldedSink::new(&mut output).with_pruning(Pruning {
+        trim_startup: true,
+        ..Default::default()
+    });
+    let sample = |callchain: Vec<u64>| Sample {
+        pid: std::process::id(),
+        callchain,
+        ..Default::default()
+    };
+    sink.consume(&sample(vec![leaf, start + 1])).unwrap();
+    // Unknown frames are kept, and so are stacks of startup frames only.
+    sink.consume(&sample(vec![leaf, 1, start + 1])).unwrap();
+    let user = -512_i64 as u64;
+    sink.consume(&sample(vec![user, start])).unwrap();
+    sink.finish().unwrap();
+    let output = String::from_utf8(output).unwrap();
+    let mut lines: Vec<_> = output.lines().map(str::to_owned).collect();
+    lines.sort_unstable();
+    let mut expected = [
+        format!("{leaf:#x} 1"),
+        format!("0x1;{leaf:#x} 1"),
+        format!("{start:#x} 1"),
+    ];
+    expected.sort_unstable();
+    assert_eq!(lines, expected);
+}
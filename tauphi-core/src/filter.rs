@@ -0,0 +1,308 @@
+//! Filtering of samples by the process they belong to or the code they hit.
+use std::collections::HashMap;
+use std::fs;
+use std::ops::Range;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+use crate::error::TauphiError;
+use crate::maps::parse_maps;
+use crate::sampling::Sample;
+
+/// `PF_KTHREAD` flag of kernel threads in `/proc/<pid>/stat`.
+const PF_KTHREAD: u64 = 0x0020_0000;
+
+/// Ancestors checked by [SampleFilter::without_process_tree()], a reused
+/// PID could form a cycle.
+const MAX_TREE_DEPTH: usize = 64;
+
+/// Decides which samples are kept, the rest is dropped.
+#[derive(Debug, Default)]
+pub struct SampleFilter {
+    /// Keep only processes of the user with this ID.
+    uid: Option<u32>,
+    /// Drop samples of the idle task.
+    drop_idle: bool,
+    /// Drop samples of kernel threads.
+    drop_kernel_threads: bool,
+    /// Keep only samples with the IP in one of these ranges or [Self::dsos].
+    address_ranges: Vec<Range<u64>>,
+    /// Keep only samples with the IP in one of these files or
+    /// [Self::address_ranges].
+    dsos: Vec<String>,
+    /// Drop samples of this process and its descendants.
+    excluded_tree: Option<u32>,
+    /// Samples dropped because of [Self::excluded_tree].
+    excluded_samples: u64,
+    /// Cached processes, [None] for already exited ones.
+    ///
+    /// A reused PID keeps the information of its first process.
+    processes: HashMap<u32, Option<Process>>,
+    /// Cached address ranges of [Self::dsos] in each process.
+    dso_ranges: HashMap<u32, Vec<Range<u64>>>,
+}
+
+/// Information about a sampled process.
+#[derive(Debug, Clone, Copy)]
+struct Process {
+    /// ID of the owning user.
+    uid: u32,
+    /// Whether this is a kernel thread.
+    kernel_thread: bool,
+    /// ID of the parent process, 0 for the roots.
+    ppid: u32,
+}
+
+impl SampleFilter {
+    /// Keep only samples of processes owned by the user.
+    pub fn with_uid(mut self, uid: u32) -> SampleFilter {
+        self.uid = Some(uid);
+        self
+    }
+
+    /// Drop samples of the idle task, i.e. of CPUs with nothing to run.
+    pub fn without_idle(mut self) -> SampleFilter {
+        self.drop_idle = true;
+        self
+    }
+
+    /// Drop samples of kernel threads such as `kworker` or `ksoftirqd`.
+    pub fn without_kernel_threads(mut self) -> SampleFilter {
+        self.drop_kernel_threads = true;
+        self
+    }
+
+    /// Keep only samples with the IP in the range, e.g. of a JIT code cache.
+    ///
+    /// Samples in any of the ranges or DSOs given by
+    /// [SampleFilter::with_dso()] are kept.
+    pub fn with_address_range(mut self, range: Range<u64>) -> SampleFilter {
+        self.address_ranges.push(range);
+        self
+    }
+
+    /// Keep only samples with the IP in the executable or shared library,
+    /// e.g. `libmylib.so`, also matches versions such as `libmylib.so.1`.
+    ///
+    /// Mappings of a process are read on its first sample, libraries loaded
+    /// after that are not recognized.
+    pub fn with_dso(mut self, name: impl Into<String>) -> SampleFilter {
+        self.dsos.push(name.into());
+        self
+    }
+
+    /// Drop samples of the process and all its descendants, e.g. of tauphi
+    /// itself in system-wide profiles.
+    ///
+    /// Descendants are recognized while they run, samples of already exited
+    /// ones are kept.
+    pub fn without_process_tree(mut self, pid: u32) -> SampleFilter {
+        self.excluded_tree = Some(pid);
+        self
+    }
+
+    /// Number of samples dropped by [SampleFilter::without_process_tree()].
+    pub fn excluded_samples(&self) -> u64 {
+        self.excluded_samples
+    }
+
+    /// Whether the sample should be kept.
+    pub fn accepts(&mut self, sample: &Sample) -> bool {
+        self.accepts_process(sample) && self.accepts_location(sample)
+    }
+
+    /// Whether the sample's IP is in the requested code.
+    fn accepts_location(&mut self, sample: &Sample) -> bool {
+        if self.address_ranges.is_empty() && self.dsos.is_empty() {
+            return true;
+        }
+        if self
+            .address_ranges
+            .iter()
+            .any(|range| range.contains(&sample.ip))
+        {
+            return true;
+        }
+        if self.dsos.is_empty() {
+            return false;
+        }
+        let dsos = &self.dsos;
+        self.dso_ranges
+            .entry(sample.pid)
+            .or_insert_with(|| {
+                fs::read_to_string(format!("/proc/{}/maps", sample.pid))
+                    .map(|maps| find_mappings(&maps, dsos))
+                    .unwrap_or_default()
+            })
+            .iter()
+            .any(|range| range.contains(&sample.ip))
+    }
+
+    /// Whether the sample's process is kept.
+    fn accepts_process(&mut self, sample: &Sample) -> bool {
+        // The idle task has PID 0 and no entry in /proc.
+        if sample.pid == 0 {
+            return !self.drop_idle && self.uid.is_none();
+        }
+        if let Some(root) = self.excluded_tree {
+            if self.in_tree(sample.pid, root) {
+                self.excluded_samples += 1;
+                return false;
+            }
+        }
+        if self.uid.is_none() && !self.drop_kernel_threads {
+            return true;
+        }
+        let Some(process) = self.process(sample.pid) else {
+            // Information about exited processes is lost.
+            return self.uid.is_none();
+        };
+        self.uid.is_none_or(|uid| process.uid == uid)
+            && !(self.drop_kernel_threads && process.kernel_thread)
+    }
+
+    /// Whether the process is `root` or one of its descendants.
+    fn in_tree(&mut self, mut pid: u32, root: u32) -> bool {
+        for _ in 0..MAX_TREE_DEPTH {
+            if pid == root {
+                return true;
+            }
+            match self.process(pid) {
+                Some(process) if process.ppid != 0 => pid = process.ppid,
+                _ => return false,
+            }
+        }
+        false
+    }
+
+    /// Information about the process.
+    fn process(&mut self, pid: u32) -> Option<Process> {
+        *self.processes.entry(pid).or_insert_with(|| {
+            let uid = fs::metadata(format!("/proc/{pid}")).ok()?.uid();
+            let stat = fs::read_to_string(format!("/proc/{pid}/stat")).ok()?;
+            let (ppid, flags) = parse_stat(&stat)?;
+            Some(Process {
+                uid,
+                kernel_thread: flags & PF_KTHREAD != 0,
+                ppid,
+            })
+        })
+    }
+}
+
+/// Parse the parent's ID and process flags from the contents of
+/// `/proc/<pid>/stat`.
+fn parse_stat(stat: &str) -> Option<(u32, u64)> {
+    // The command name in parentheses can contain spaces, skip it.
+    let (_, fields) = stat.rsplit_once(')')?;
+    // state ppid pgrp session tty_nr tpgid flags ...
+    let mut fields = fields.split_whitespace();
+    let ppid = fields.nth(1)?.parse().ok()?;
+    let flags = fields.nth(4)?.parse().ok()?;
+    Some((ppid, flags))
+}
+
+/// Address ranges of code of files with the names in the contents of
+/// `/proc/<pid>/maps`.
+fn find_mappings(maps: &str, names: &[String]) -> Vec<Range<u64>> {
+    let matches = |path: &str| {
+        let file_name = Path::new(path).file_name().and_then(|name| name.to_str());
+        names.iter().any(|name| {
+            path == name
+                || file_name.is_some_and(|file_name| {
+                    file_name == name
+                        || file_name
+                            .strip_prefix(name.as_str())
+                            .is_some_and(|version| version.starts_with('.'))
+                })
+        })
+    };
+    parse_maps(maps)
+        .into_iter()
+        .filter(|mapping| mapping.executable && mapping.path.as_deref().is_some_and(matches))
+        .map(|mapping| mapping.addresses)
+        .collect()
+}
+
+/// Resolve a user name or a numeric ID to the user ID.
+pub fn resolve_user(user: &str) -> Result<u32, TauphiError> {
+    if let Ok(uid) = user.parse() {
+        return Ok(uid);
+    }
+    let passwd = fs::read_to_string("/etc/passwd").map_err(TauphiError::file("/etc/passwd"))?;
+    find_uid(&passwd, user).ok_or_else(|| TauphiError::TargetNotFound(format!("user '{user}'")))
+}
+
+/// Find the user ID in the contents of `/etc/passwd`.
+fn find_uid(passwd: &str, user: &str) -> Option<u32> {
+    passwd.lines().find_map(|line| {
+        // name:password:UID:GID:...
+        let mut fields = line.split(':');
+        let name = fields.next()?;
+        let uid = fields.nth(1)?;
+        (name == user).then(|| uid.parse().ok()).flatten()
+    })
+}
+
+#[test]
+fn find_uid_test() {
+    let passwd = "root:x:0:0:root:/root:/bin/bash\nci:x:1001:1001::/home/ci:/bin/sh\n";
+    assert_eq!(find_uid(passwd, "ci"), Some(1001));
+    assert_eq!(find_uid(passwd, "root"), Some(0));
+    assert_eq!(find_uid(passwd, "nobody"), None);
+}
+
+#[test]
+fn parse_stat_test() {
+    let stat = "2 (kthreadd) S 0 0 0 0 -1 2129984 0 0 0 0";
+    assert_eq!(
+        parse_stat(stat).map(|(_, flags)| flags & PF_KTHREAD != 0),
+        Some(true)
+    );
+    let stat = "42 (a b) c) R 1 42 42 0 -1 4194304 0 0";
+    assert_eq!(parse_stat(stat), Some((1, 4194304)));
+}
+
+#[test]
+fn without_process_tree_test() {
+    let own = std::process::id();
+    let mut filter = SampleFilter::default().without_process_tree(own);
+    let sample = |pid| Sample {
+        pid,
+        ..Default::default()
+    };
+    assert!(!filter.accepts(&sample(own)));
+    // The parent is outside of the tree.
+    let parent = filter.process(own).unwrap().ppid;
+    assert!(filter.accepts(&sample(parent)));
+    assert_eq!(filter.excluded_samples(), 1);
+}
+
+#[test]
+fn find_mappings_test() {
+    let maps = "\
+55d0c1a00000-55d0c1a21000 r-xp 00000000 08:01 1234 /usr/bin/app
+55d0c1c21000-55d0c1c23000 r--p 00021000 08:01 1234 /usr/bin/app
+7f10a0000000-7f10a0100000 r-xp 00000000 08:01 2345 /opt/lib/libmylib.so.1
+7f10a0200000-7f10a0300000 r-xp 00000000 08:01 3456 /opt/lib/libmylib.solver.so
+7ffd00000000-7ffd00021000 rw-p 00000000 00:00 0 [stack]
+7ffd00100000-7ffd00101000 rw-p 00000000 00:00 0
+";
+    let names = ["libmylib.so".to_owned(), "/usr/bin/app".to_owned()];
+    assert_eq!(
+        find_mappings(maps, &names),
+        [
+            0x55d0c1a00000..0x55d0c1a21000,
+            0x7f10a0000000..0x7f10a0100000
+        ]
+    );
+
+    let mut filter = SampleFilter::default().with_address_range(0x1000..0x2000);
+    let sample = |ip| Sample {
+        ip,
+        pid: 1,
+        ..Default::default()
+    };
+    assert!(filter.accepts(&sample(0x1000)));
+    assert!(!filter.accepts(&sample(0x2000)));
+}
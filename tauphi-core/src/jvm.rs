@@ -0,0 +1,65 @@
+//! Detection of sampled Java virtual machines.
+//!
+//! Code compiled by HotSpot's JIT is not in any file, it is named only in
+//! `/tmp/perf-<PID>.map` on request. Without frame pointers, callchains also
+//! stop at the first compiled frame. Both need flags of the JVM, tauphi can
+//! only point them out.
+use std::fs;
+
+/// Advice for sampling the process if it is a JVM lacking the above.
+pub fn advise(pid: i32) -> Option<String> {
+    let maps = fs::read_to_string(format!("/proc/{pid}/maps")).ok()?;
+    let cmdline = fs::read(format!("/proc/{pid}/cmdline")).ok()?;
+    // The map is written into /tmp of the JVM, which might be in a container.
+    let has_perf_map = fs::metadata(format!("/proc/{pid}/root/tmp/perf-{pid}.map")).is_ok();
+    advice(pid, &maps, &String::from_utf8_lossy(&cmdline), has_perf_map)
+}
+
+fn advice(pid: i32, maps: &str, cmdline: &str, has_perf_map: bool) -> Option<String> {
+    let is_jvm = maps.lines().any(|line| line.ends_with("/libjvm.so"));
+    if !is_jvm {
+        return None;
+    }
+    let mut fixes = Vec::new();
+    if !has_perf_map {
+        fixes.push(format!(
+            "run 'jcmd {pid} Compiler.perfmap' (JDK 17+) before the sampling ends or start it \
+             with -XX:+UnlockDiagnosticVMOptions -XX:+DumpPerfMapAtExit to name JIT-compiled \
+             frames"
+        ));
+    }
+    // Arguments are separated by NUL.
+    if !cmdline
+        .split('\0')
+        .any(|arg| arg == "-XX:+PreserveFramePointer")
+    {
+        fixes
+            .push("start it with -XX:+PreserveFramePointer to walk JIT-compiled frames".to_owned());
+    }
+    if fixes.is_empty() {
+        return None;
+    }
+    Some(format!("Process {pid} is a JVM, {}.", fixes.join("; ")))
+}
+
+#[test]
+fn advice_test() {
+    let maps = "7f0000000000-7f0000100000 r-xp 00000000 08:01 42 /usr/lib/jvm/lib/server/libjvm.so";
+    let flags = "java\0-XX:+PreserveFramePointer\0-jar\0app.jar\0";
+    assert_eq!(advice(1, maps, flags, true), None);
+    assert!(advice(1, maps, flags, false)
+        .unwrap()
+        .contains("jcmd 1 Compiler.perfmap"));
+    assert!(advice(1, maps, "java\0-jar\0app.jar\0", true)
+        .unwrap()
+        .contains("-XX:+PreserveFramePointer"));
+    assert_eq!(
+        advice(
+            1,
+            "00400000-00452000 r-xp 0 08:01 7 /usr/bin/python3",
+            "",
+            false
+        ),
+        None
+    );
+}
@@ -0,0 +1,41 @@
+//! Sampling profiler of Linux processes based on perf events.
+//!
+//! [session::ProfileSession] collects a whole profile in the background,
+//! [sampling] offers streams of individual samples.
+pub mod adaptive;
+pub mod aggregate;
+pub mod backend;
+pub mod bpf;
+pub mod butterfly;
+pub mod cgroup;
+pub mod clock;
+pub mod containers;
+pub mod doctor;
+pub mod elf;
+pub mod energy;
+pub mod error;
+pub mod event_spec;
+pub mod export;
+pub mod filter;
+pub mod jvm;
+pub mod maps;
+pub mod metadata;
+pub mod overhead;
+pub mod phase;
+#[cfg(feature = "async-tokio")]
+pub mod pipeline;
+pub mod pool;
+pub mod processes;
+pub mod progress;
+pub mod record;
+pub mod replay;
+pub mod rotate;
+pub mod sampling;
+#[cfg(feature = "async-tokio")]
+pub mod session;
+pub mod sink;
+pub mod stat;
+pub mod switches;
+pub mod symbols;
+pub mod timeline;
+pub mod watch;
@@ -0,0 +1,251 @@
+//! Snapshots of memory mappings of sampled processes.
+//!
+//! `/proc/<pid>/maps` is gone once a process exits, so samples of
+//! short-lived processes can be attributed to files only if their mappings
+//! were read early. [MapsCache] reads them on the first record of a process
+//! and keeps the build IDs of the mapped files, which identify them even if
+//! they are replaced or deleted later.
+//!
+//! Executable memory without a file is usually code of a JIT compiler, it
+//! can be named only by the compiler's perf map, see [AnonymousJit].
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::ops::Range;
+use std::sync::Arc;
+
+use crate::elf::{u32_at, ElfFile};
+use crate::sampling::Record;
+
+/// `PT_NOTE` program header of ELF files.
+const PT_NOTE: u32 = 4;
+/// `NT_GNU_BUILD_ID` note of ELF files.
+const NT_GNU_BUILD_ID: u32 = 3;
+/// Larger notes are not build IDs, avoids reading garbage.
+const MAX_NOTES_SIZE: u64 = 64 * 1024;
+
+/// A mapped region of a process, a line of `/proc/<pid>/maps`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mapping {
+    pub addresses: Range<u64>,
+    /// Offset of the region in the file.
+    pub offset: u64,
+    /// Device and inode of the file, identifies it across processes.
+    pub inode: (String, u64),
+    /// Mapped file, `None` for anonymous memory. Pseudo files such as
+    /// `[stack]` are kept.
+    pub path: Option<String>,
+    /// GNU build ID of executable ELF files, if they have one.
+    pub build_id: Option<Vec<u8>>,
+    /// Whether the region holds code, only such regions are sampled.
+    pub executable: bool,
+}
+
+impl Mapping {
+    /// Whether the region is executable memory without a file, e.g. of a
+    /// JIT compiler.
+    pub fn is_anonymous_executable(&self) -> bool {
+        self.executable && self.path.is_none()
+    }
+}
+
+/// Anonymous executable memory of a process without a perf map, its code
+/// cannot be named.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AnonymousJit {
+    pub pid: u32,
+    /// Bytes of all such regions of the process.
+    pub size: u64,
+}
+
+impl fmt::Display for AnonymousJit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "anonymous JIT (pid {}, {} KiB)",
+            self.pid,
+            self.size >> 10
+        )
+    }
+}
+
+/// Parse the contents of `/proc/<pid>/maps`, skips malformed lines.
+pub fn parse_maps(maps: &str) -> Vec<Mapping> {
+    maps.lines()
+        .filter_map(|line| {
+            // start-end perms offset dev inode path
+            let mut fields = line.split_whitespace();
+            let (start, end) = fields.next()?.split_once('-')?;
+            let perms = fields.next()?;
+            let offset = u64::from_str_radix(fields.next()?, 16).ok()?;
+            let device = fields.next()?.to_owned();
+            let inode = fields.next()?.parse().ok()?;
+            Some(Mapping {
+                addresses: u64::from_str_radix(start, 16).ok()?
+                    ..u64::from_str_radix(end, 16).ok()?,
+                offset,
+                inode: (device, inode),
+                path: fields.next().map(str::to_owned),
+                build_id: None,
+                executable: perms.contains('x'),
+            })
+        })
+        .collect()
+}
+
+/// Mappings of processes, snapshotted while they ran.
+#[derive(Debug, Default)]
+pub struct MapsCache {
+    /// `None` for processes which exited before they were read.
+    processes: HashMap<u32, Option<Arc<[Mapping]>>>,
+    /// Build IDs by [Mapping::inode], shared by all processes.
+    build_ids: HashMap<(String, u64), Option<Vec<u8>>>,
+}
+
+impl MapsCache {
+    /// Snapshot the mappings of processes on their first sample or creation.
+    pub fn observe(&mut self, record: &Record) {
+        match record {
+            Record::Sample(sample) => {
+                self.snapshot(sample.pid);
+            }
+            Record::Fork(event) if event.is_process() => {
+                self.snapshot(event.pid);
+            }
+            _ => (),
+        }
+    }
+
+    /// Mappings of the process, read now if seen for the first time.
+    ///
+    /// Later loaded libraries are not in the snapshot.
+    pub fn snapshot(&mut self, pid: u32) -> Option<Arc<[Mapping]>> {
+        if let Some(mappings) = self.processes.get(&pid) {
+            return mappings.clone();
+        }
+        let mappings = fs::read_to_string(format!("/proc/{pid}/maps"))
+            .ok()
+            .map(|maps| {
+                let mut mappings = parse_maps(&maps);
+                // Samples never point into data, its files are not read.
+                for mapping in mappings.iter_mut().filter(|mapping| mapping.executable) {
+                    mapping.build_id = self.build_id(pid, mapping);
+                }
+                mappings.into()
+            });
+        self.processes.insert(pid, mappings.clone());
+        mappings
+    }
+
+    /// Anonymous executable memory of the process, `None` if it has none
+    /// or the compiler writes a perf map naming it.
+    ///
+    /// The mappings are snapshotted if not yet, see [MapsCache::snapshot()].
+    pub fn anonymous_jit(&mut self, pid: u32) -> Option<AnonymousJit> {
+        let size = self
+            .snapshot(pid)?
+            .iter()
+            .filter(|mapping| mapping.is_anonymous_executable())
+            .map(|mapping| mapping.addresses.end - mapping.addresses.start)
+            .sum();
+        // The map is written into /tmp of the process, which might be in a
+        // container.
+        let has_perf_map = fs::metadata(format!("/proc/{pid}/root/tmp/perf-{pid}.map")).is_ok();
+        (size > 0 && !has_perf_map).then_some(AnonymousJit { pid, size })
+    }
+
+    /// Build ID of the mapped file, read through the process's root for
+    /// processes in containers.
+    fn build_id(&mut self, pid: u32, mapping: &Mapping) -> Option<Vec<u8>> {
+        let path = mapping.path.as_ref().filter(|path| path.starts_with('/'))?;
+        self.build_ids
+            .entry(mapping.inode.clone())
+            .or_insert_with(|| read_build_id(&format!("/proc/{pid}/root{path}")))
+            .clone()
+    }
+}
+
+/// Read the GNU build ID of a 64-bit ELF file.
+pub fn read_build_id(path: &str) -> Option<Vec<u8>> {
+    let elf = ElfFile::open(path)?;
+    for segment in elf.program_headers()? {
+        if segment.kind != PT_NOTE {
+            continue;
+        }
+        let Some(notes) = elf.read(segment.offset, segment.size, MAX_NOTES_SIZE) else {
+            continue;
+        };
+        if let Some(build_id) = find_build_id(&notes) {
+            return Some(build_id);
+        }
+    }
+    None
+}
+
+/// Find the GNU build ID in the contents of a `PT_NOTE` segment.
+fn find_build_id(mut notes: &[u8]) -> Option<Vec<u8>> {
+    let padded = |size: u32| (size as usize).next_multiple_of(4);
+    while notes.len() >= 12 {
+        let (name_size, desc_size, kind) = (u32_at(notes, 0), u32_at(notes, 4), u32_at(notes, 8));
+        let name_end = 12 + padded(name_size);
+        let desc_end = name_end.checked_add(padded(desc_size))?;
+        if desc_end > notes.len() {
+            return None;
+        }
+        if kind == NT_GNU_BUILD_ID && notes[12..12 + name_size as usize] == *b"GNU\0" {
+            return Some(notes[name_end..name_end + desc_size as usize].to_vec());
+        }
+        notes = &notes[desc_end..];
+    }
+    None
+}
+
+#[test]
+fn parse_maps_test() {
+    let maps = "\
+55d0c1a00000-55d0c1a21000 r-xp 00002000 08:01 1234 /usr/bin/app
+7ffd00100000-7ffd00101000 rw-p 00000000 00:00 0
+7f10b0000000-7f10b0400000 rwxp 00000000 00:00 0
+garbage
+";
+    let mappings = parse_maps(maps);
+    assert_eq!(mappings.len(), 3);
+    assert_eq!(mappings[0].addresses, 0x55d0c1a00000..0x55d0c1a21000);
+    assert_eq!(mappings[0].offset, 0x2000);
+    assert_eq!(mappings[0].inode, ("08:01".to_owned(), 1234));
+    assert_eq!(mappings[0].path.as_deref(), Some("/usr/bin/app"));
+    assert_eq!(mappings[1].path, None);
+    assert!(mappings[0].executable && !mappings[0].is_anonymous_executable());
+    assert!(!mappings[1].executable && !mappings[1].is_anonymous_executable());
+    assert!(mappings[2].is_anonymous_executable());
+    let jit = AnonymousJit {
+        pid: 7,
+        size: 0x400000,
+    };
+    assert_eq!(jit.to_string(), "anonymous JIT (pid 7, 4096 KiB)");
+}
+
+#[test]
+fn find_build_id_test() {
+    let mut notes = Vec::new();
+    // An unrelated note with an unpadded name.
+    for field in [5_u32, 0, 1] {
+        notes.extend(field.to_ne_bytes());
+    }
+    notes.extend(*b"stapsdt\0");
+    for field in [4_u32, 3, NT_GNU_BUILD_ID] {
+        notes.extend(field.to_ne_bytes());
+    }
+    notes.extend(*b"GNU\0\xab\xcd\xef\0");
+    assert_eq!(find_build_id(&notes), Some(vec![0xab, 0xcd, 0xef]));
+    assert_eq!(find_build_id(&notes[..notes.len() - 4]), None);
+}
+
+#[test]
+fn snapshot_test() {
+    let mut cache = MapsCache::default();
+    let mappings = cache.snapshot(std::process::id()).unwrap();
+    // The test binary is built with a build ID by default.
+    assert!(mappings.iter().any(|mapping| mapping.build_id.is_some()));
+    assert!(cache.snapshot(u32::MAX).is_none());
+}
@@ -0,0 +1,164 @@
+//! Description of the sampled machine and configuration stored with
+//! recordings, keeps archived profiles interpretable.
+use std::fmt;
+use std::fs;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::maps::MapsCache;
+use crate::sampling::{num_cpus, SamplerOptions};
+
+/// Where, when and how samples were recorded.
+///
+/// Values that could not be read are left empty.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RecordingMetadata {
+    /// Name of the sampled machine.
+    pub hostname: String,
+    /// Release of the running kernel, e.g. `6.1.0-13-amd64`.
+    pub kernel: String,
+    /// Model name of the first CPU.
+    pub cpu_model: String,
+    /// Number of online CPUs.
+    pub cpu_count: usize,
+    /// Version of tauphi that made the recording.
+    pub version: String,
+    /// Name of the event triggering the samples.
+    pub event: String,
+    /// Requested number of samples per second.
+    pub frequency: usize,
+    /// Command lines of the sampled processes, empty when sampling CPUs.
+    pub cmdlines: Vec<String>,
+    /// Start of the recording in nanoseconds since the Unix epoch.
+    pub start_time: u64,
+    /// Mapped files of the sampled processes with a build ID, identify
+    /// the debug files for symbolizing the recording elsewhere.
+    pub dsos: Vec<DsoBuildId>,
+}
+
+/// GNU build ID of a mapped executable file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DsoBuildId {
+    /// Path of the file as mapped by the process.
+    pub path: String,
+    /// The build ID in lowercase hexadecimal, as debuginfod names it.
+    pub build_id: String,
+}
+
+impl RecordingMetadata {
+    /// Describe sampling of the processes with the options, starting now.
+    ///
+    /// # Arguments
+    /// * `options` - Configuration of the samplers.
+    /// * `pids` - Sampled processes, empty when sampling CPUs.
+    pub fn capture(options: &SamplerOptions, pids: &[i32]) -> RecordingMetadata {
+        let read = |path: &str| {
+            fs::read_to_string(path)
+                .map(|content| content.trim().to_owned())
+                .unwrap_or_default()
+        };
+        RecordingMetadata {
+            hostname: read("/proc/sys/kernel/hostname"),
+            kernel: read("/proc/sys/kernel/osrelease"),
+            cpu_model: parse_cpu_model(&read("/proc/cpuinfo")).unwrap_or_default(),
+            cpu_count: num_cpus(),
+            version: env!("CARGO_PKG_VERSION").to_owned(),
+            event: options.event.name().to_owned(),
+            frequency: options.frequency,
+            cmdlines: pids
+                .iter()
+                .map(|pid| read_cmdline(*pid).unwrap_or_default())
+                .collect(),
+            start_time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_nanos() as u64,
+            dsos: read_build_ids(pids),
+        }
+    }
+}
+
+/// One `key: value` line per field.
+impl fmt::Display for RecordingMetadata {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "hostname: {}", self.hostname)?;
+        writeln!(f, "kernel: {}", self.kernel)?;
+        writeln!(f, "cpu: {} x {}", self.cpu_count, self.cpu_model)?;
+        writeln!(f, "tauphi: {}", self.version)?;
+        writeln!(f, "event: {} at {} Hz", self.event, self.frequency)?;
+        for cmdline in &self.cmdlines {
+            writeln!(f, "target: {cmdline}")?;
+        }
+        for dso in &self.dsos {
+            writeln!(f, "dso: {} {}", dso.build_id, dso.path)?;
+        }
+        write!(f, "start: {} ns since epoch", self.start_time)
+    }
+}
+
+/// Model name of the first CPU listed in `/proc/cpuinfo`.
+fn parse_cpu_model(cpuinfo: &str) -> Option<String> {
+    cpuinfo.lines().find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        (key.trim() == "model name").then(|| value.trim().to_owned())
+    })
+}
+
+/// Command line of the process with its arguments separated by spaces.
+fn read_cmdline(pid: i32) -> Option<String> {
+    let cmdline = fs::read(format!("/proc/{pid}/cmdline")).ok()?;
+    let args: Vec<_> = cmdline
+        .split(|&byte| byte == 0)
+        .filter(|arg| !arg.is_empty())
+        .map(String::from_utf8_lossy)
+        .collect();
+    Some(args.join(" "))
+}
+
+/// Build IDs of the files mapped by the processes, each file once.
+fn read_build_ids(pids: &[i32]) -> Vec<DsoBuildId> {
+    let mut maps = MapsCache::default();
+    let mut dsos: Vec<_> = pids
+        .iter()
+        .filter_map(|&pid| maps.snapshot(pid as u32))
+        .flat_map(|mappings| mappings.to_vec())
+        .filter_map(|mapping| {
+            Some(DsoBuildId {
+                path: mapping.path?,
+                build_id: mapping
+                    .build_id?
+                    .iter()
+                    .map(|byte| format!("{byte:02x}"))
+                    .collect(),
+            })
+        })
+        .collect();
+    dsos.sort_by(|a, b| a.path.cmp(&b.path));
+    dsos.dedup();
+    dsos
+}
+
+#[test]
+fn parse_cpu_model_test() {
+    let cpuinfo = "processor\t: 0\nvendor_id\t: GenuineIntel\n\
+                   model name\t: Intel(R) Core(TM) i7-8565U CPU @ 1.80GHz\n";
+    assert_eq!(
+        parse_cpu_model(cpuinfo).as_deref(),
+        Some("Intel(R) Core(TM) i7-8565U CPU @ 1.80GHz")
+    );
+    assert_eq!(parse_cpu_model("processor\t: 0\n"), None);
+}
+
+#[test]
+fn read_build_ids_test() {
+    let dsos = read_build_ids(&[std::process::id() as i32]);
+    // The test binary is built with a build ID by default.
+    let exe = std::env::current_exe().unwrap();
+    let test = dsos
+        .iter()
+        .find(|dso| std::path::Path::new(&dso.path) == exe)
+        .unwrap();
+    assert!(test.build_id.chars().all(|c| c.is_ascii_hexdigit()));
+    assert!(read_build_ids(&[-1]).is_empty());
+}
@@ -0,0 +1,108 @@
+//! Measurement of tauphi's own influence on the sampled system.
+use std::process;
+use std::time::{Duration, Instant};
+
+use crate::sampling::Record;
+
+/// Tracks CPU time spent by this process and samples that hit it.
+///
+/// Feed it all records via [OverheadMeter::observe()], samples of this
+/// process show how much sampling perturbs system-wide profiles.
+#[derive(Debug)]
+pub struct OverheadMeter {
+    started: Instant,
+    /// CPU time of this process at the start.
+    cpu_time: Duration,
+    pid: u32,
+    samples: u64,
+    own_samples: u64,
+}
+
+/// Overhead measured by [OverheadMeter::report()].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Overhead {
+    /// Time since the meter started.
+    pub wall_time: Duration,
+    /// User and system CPU time of this process since the meter started.
+    pub cpu_time: Duration,
+    /// All observed samples.
+    pub samples: u64,
+    /// Observed samples of this process.
+    pub own_samples: u64,
+}
+
+impl Overhead {
+    /// Share of one CPU used by this process, from 0 to 1 per used CPU.
+    pub fn cpu_share(&self) -> f64 {
+        if self.wall_time.is_zero() {
+            return 0.0;
+        }
+        self.cpu_time.as_secs_f64() / self.wall_time.as_secs_f64()
+    }
+
+    /// Share of the samples which hit this process, from 0 to 1.
+    pub fn perturbation(&self) -> f64 {
+        if self.samples == 0 {
+            return 0.0;
+        }
+        self.own_samples as f64 / self.samples as f64
+    }
+}
+
+impl OverheadMeter {
+    /// Start measuring from now.
+    pub fn start() -> OverheadMeter {
+        OverheadMeter {
+            started: Instant::now(),
+            cpu_time: own_cpu_time(),
+            pid: process::id(),
+            samples: 0,
+            own_samples: 0,
+        }
+    }
+
+    /// Account the record.
+    pub fn observe(&mut self, record: &Record) {
+        if let Record::Sample(sample) = record {
+            self.samples += 1;
+            if sample.pid == self.pid {
+                self.own_samples += 1;
+            }
+        }
+    }
+
+    /// Overhead since the start.
+    pub fn report(&self) -> Overhead {
+        Overhead {
+            wall_time: self.started.elapsed(),
+            cpu_time: own_cpu_time().saturating_sub(self.cpu_time),
+            samples: self.samples,
+            own_samples: self.own_samples,
+        }
+    }
+}
+
+/// User and system CPU time consumed by all threads of this process.
+fn own_cpu_time() -> Duration {
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    unsafe { libc::getrusage(libc::RUSAGE_SELF, &mut usage) };
+    let time = |time: libc::timeval| Duration::new(time.tv_sec as u64, time.tv_usec as u32 * 1000);
+    time(usage.ru_utime) + time(usage.ru_stime)
+}
+
+#[test]
+fn overhead_test() {
+    use crate::sampling::Sample;
+
+    let mut meter = OverheadMeter::start();
+    let own = Sample {
+        pid: process::id(),
+        ..Default::default()
+    };
+    meter.observe(&Record::Sample(own));
+    meter.observe(&Record::Sample(Sample::default()));
+    meter.observe(&Record::Lost(3));
+    let overhead = meter.report();
+    assert_eq!((overhead.samples, overhead.own_samples), (2, 1));
+    assert_eq!(overhead.perturbation(), 0.5);
+}
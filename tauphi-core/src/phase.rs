@@ -0,0 +1,178 @@
+//! Named phases of a sampling, e.g. a warm-up followed by the steady state.
+use std::ffi::CString;
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufRead, BufReader};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::FileTypeExt;
+use std::path::{Path, PathBuf};
+use std::thread;
+
+use crate::clock::monotonic_now;
+use crate::error::TauphiError;
+use crate::sampling::Sample;
+
+/// Start of a phase, lasts until the start of the next one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Phase {
+    /// Name given by the marker.
+    pub name: String,
+    /// Monotonic time in nanoseconds, comparable with [Sample::time].
+    pub start: u64,
+}
+
+/// Splits samples into phases by their time.
+#[derive(Debug, Clone)]
+pub struct PhaseTracker {
+    /// Phases ordered by their start.
+    phases: Vec<Phase>,
+    /// Samples of each phase.
+    samples: Vec<u64>,
+}
+
+impl PhaseTracker {
+    /// Start with a single phase of the given name from `start` on.
+    pub fn new(name: impl Into<String>, start: u64) -> PhaseTracker {
+        PhaseTracker {
+            phases: vec![Phase {
+                name: name.into(),
+                start,
+            }],
+            samples: vec![0],
+        }
+    }
+
+    /// Start a new phase at the given time.
+    ///
+    /// Markers usually arrive in order, late ones are inserted by their time.
+    pub fn mark(&mut self, name: impl Into<String>, start: u64) {
+        let index = self.phases.partition_point(|phase| phase.start <= start);
+        let phase = Phase {
+            name: name.into(),
+            start,
+        };
+        self.phases.insert(index, phase);
+        self.samples.insert(index, 0);
+    }
+
+    /// Count the sample into its phase, earlier samples into the first one.
+    pub fn add_sample(&mut self, sample: &Sample) {
+        let index = self
+            .phases
+            .partition_point(|phase| phase.start <= sample.time)
+            .max(1);
+        self.samples[index - 1] += 1;
+    }
+
+    /// Phases with their samples, ordered by their start.
+    pub fn phases(&self) -> impl Iterator<Item = (&Phase, u64)> {
+        self.phases.iter().zip(self.samples.iter().copied())
+    }
+}
+
+/// Named pipe delivering phase markers, one name per line.
+///
+/// Lets the sampled program or the user mark phases with e.g.
+/// `echo steady > /tmp/markers`. The pipe is removed when dropped if it
+/// did not exist before.
+#[derive(Debug)]
+pub struct MarkerFifo {
+    path: PathBuf,
+    /// Whether the pipe was created by this instance.
+    created: bool,
+}
+
+impl MarkerFifo {
+    /// Create the named pipe unless it exists and pass each received marker
+    /// to `on_marker` from a background thread.
+    pub fn open(
+        path: impl Into<PathBuf>,
+        mut on_marker: impl FnMut(Phase) + Send + 'static,
+    ) -> Result<MarkerFifo, TauphiError> {
+        let path = path.into();
+        let created = match fs::metadata(&path) {
+            Ok(metadata) if metadata.file_type().is_fifo() => false,
+            Ok(_) => {
+                return Err(TauphiError::InvalidArgument(format!(
+                    "{} is not a named pipe",
+                    path.display()
+                )))
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                make_fifo(&path)?;
+                true
+            }
+            Err(err) => return Err(err.into()),
+        };
+        // Also opened for writing, so the pipe never ends when writers close.
+        let fifo = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&path)
+            .map_err(TauphiError::file(&path))?;
+        thread::spawn(move || {
+            for line in BufReader::new(fifo).lines() {
+                let Ok(line) = line else { break };
+                let name = line.trim();
+                if !name.is_empty() {
+                    on_marker(Phase {
+                        name: name.to_owned(),
+                        start: monotonic_now(),
+                    });
+                }
+            }
+        });
+        Ok(MarkerFifo { path, created })
+    }
+}
+
+impl Drop for MarkerFifo {
+    fn drop(&mut self) {
+        if self.created {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Create a named pipe accessible only by the user.
+fn make_fifo(path: &Path) -> io::Result<()> {
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    if unsafe { libc::mkfifo(path.as_ptr(), 0o600) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[test]
+fn phase_tracker_test() {
+    let mut tracker = PhaseTracker::new("start", 100);
+    tracker.mark("steady", 300);
+    // A late marker.
+    tracker.mark("warm-up", 200);
+    for time in [50, 150, 250, 250, 350] {
+        tracker.add_sample(&Sample {
+            time,
+            ..Default::default()
+        });
+    }
+    let phases: Vec<_> = tracker
+        .phases()
+        .map(|(phase, samples)| (phase.name.as_str(), samples))
+        .collect();
+    assert_eq!(phases, [("start", 2), ("warm-up", 2), ("steady", 1)]);
+}
+
+#[test]
+fn marker_fifo_test() {
+    use std::io::Write;
+    use std::sync::mpsc;
+
+    let path = std::env::temp_dir().join(format!("tauphi-markers-{}", std::process::id()));
+    let (sender, markers) = mpsc::channel();
+    let fifo = MarkerFifo::open(&path, move |phase| sender.send(phase).unwrap()).unwrap();
+    let mut writer = OpenOptions::new().write(true).open(&path).unwrap();
+    writer.write_all(b"warm-up\n\nsteady\n").unwrap();
+    assert_eq!(markers.recv().unwrap().name, "warm-up");
+    assert_eq!(markers.recv().unwrap().name, "steady");
+    drop(fifo);
+    assert!(!path.exists());
+}
@@ -0,0 +1,316 @@
+//! Concurrent stages between the samplers and the consumer of records.
+//!
+//! Each sampler is read by its own thread into a bounded queue, so a slow
+//! consumer does not delay emptying the ring buffers of the other samplers.
+//! Records leave the pipeline with raw addresses. The consumer names them
+//! through [Symbolizer](crate::symbols::Symbolizer) where a report needs
+//! it.
+use std::panic;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::{mpsc, watch};
+
+use crate::backend::SamplerBackend;
+use crate::error::TauphiError;
+use crate::sampling::{Record, Sampler, SessionStats};
+
+/// Throughput of the pipeline stages, see [Pipeline::stats()].
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct PipelineStats {
+    /// Records put into the queue by the readers.
+    pub read: u64,
+    /// Number of times a reader had to wait for a full queue.
+    pub stalls: u64,
+    /// Records taken from the queue by the consumer.
+    pub consumed: u64,
+    /// Highest number of records waiting in the queue.
+    pub queue_peak: usize,
+    /// Highest ratio of a sampler's buffer occupied by unread records,
+    /// from 0 to 1.
+    pub buffer_peak: f64,
+}
+
+/// Requests from the pipeline to its readers.
+#[derive(Debug, Default, Clone, Copy)]
+struct Control {
+    /// New sampling frequency, if changed.
+    frequency: Option<usize>,
+    stopped: bool,
+}
+
+/// Counters shared by the readers.
+#[derive(Debug, Default)]
+struct ReadMetrics {
+    read: AtomicU64,
+    stalls: AtomicU64,
+    queue_peak: AtomicUsize,
+    /// [PipelineStats::buffer_peak] in millionths.
+    buffer_peak: AtomicU64,
+}
+
+/// Records of several samplers, each read by a background thread.
+///
+/// Like [MultiSampler](crate::sampling::MultiSampler), records are not
+/// ordered by their timestamp across samplers.
+///
+/// # Examples
+/// ```no_run
+/// async fn async_main() {
+///     use tauphi_core::pipeline::Pipeline;
+///     use tauphi_core::sampling::Sampler;
+///     let samplers = (0..4)
+///         .map(|cpu| Sampler::new_cpu(cpu, 99).expect("Failed to start the sampling."))
+///         .collect();
+///     let mut pipeline = Pipeline::spawn(samplers, 1024);
+///     while let Some(record) = pipeline.recv().await.unwrap() {
+///         println!("{:#?}", record);
+///     }
+///     println!("{:?}", pipeline.finish().await);
+/// }
+/// ```
+pub struct Pipeline {
+    records: mpsc::Receiver<Result<Record, TauphiError>>,
+    control: watch::Sender<Control>,
+    readers: Vec<JoinHandle<()>>,
+    /// Final statistics of each sampler, sent by its reader.
+    stats: mpsc::UnboundedReceiver<SessionStats>,
+    metrics: Arc<ReadMetrics>,
+    consumed: u64,
+}
+
+impl Pipeline {
+    /// How often idle readers check for changed frequency or a stop.
+    const CONTROL_PERIOD: Duration = Duration::from_millis(100);
+
+    /// Start reading the samplers in the background.
+    ///
+    /// # Arguments
+    /// * `samplers` - Samplers to read, each by its own thread.
+    /// * `capacity` - Number of records the queue holds before the readers
+    ///   wait for the consumer, must not be zero.
+    pub fn spawn<B>(samplers: Vec<Sampler<B>>, capacity: usize) -> Pipeline
+    where
+        B: SamplerBackend + Send + 'static,
+    {
+        let (sender, records) = mpsc::channel(capacity);
+        let (control, controlled) = watch::channel(Control::default());
+        let (stats_sender, stats) = mpsc::unbounded_channel();
+        let metrics = Arc::new(ReadMetrics::default());
+        let readers = samplers
+            .into_iter()
+            .enumerate()
+            .map(|(index, sampler)| {
+                let reader = Reader {
+                    sampler,
+                    records: sender.clone(),
+                    control: controlled.clone(),
+                    metrics: metrics.clone(),
+                };
+                let stats = stats_sender.clone();
+                thread::spawn(move || {
+                    let _span = tracing::debug_span!("reader", index = index).entered();
+                    let _ = stats.send(reader.run());
+                })
+            })
+            .collect();
+        Pipeline {
+            records,
+            control,
+            readers,
+            stats,
+            metrics,
+            consumed: 0,
+        }
+    }
+
+    /// Return the next record from any of the samplers.
+    ///
+    /// Returns `None` once all sampled processes exited or, after
+    /// [Pipeline::stop()], once the buffered records were returned.
+    pub async fn recv(&mut self) -> Result<Option<Record>, TauphiError> {
+        match self.records.recv().await {
+            Some(record) => {
+                self.consumed += 1;
+                record.map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Change the sampling frequency of all samplers.
+    ///
+    /// Failures are returned by [Pipeline::recv()].
+    pub fn set_frequency(&self, frequency: usize) {
+        tracing::info!(frequency = frequency, "changing the sampling frequency");
+        self.control
+            .send_modify(|control| control.frequency = Some(frequency));
+    }
+
+    /// Stop collecting new samples, the buffered ones are still returned.
+    pub fn stop(&self) {
+        self.control.send_modify(|control| control.stopped = true);
+    }
+
+    /// Throughput of the stages so far.
+    pub fn stats(&self) -> PipelineStats {
+        PipelineStats {
+            read: self.metrics.read.load(Ordering::Relaxed),
+            stalls: self.metrics.stalls.load(Ordering::Relaxed),
+            consumed: self.consumed,
+            queue_peak: self.metrics.queue_peak.load(Ordering::Relaxed),
+            buffer_peak: self.metrics.buffer_peak.load(Ordering::Relaxed) as f64 / 1e6,
+        }
+    }
+
+    /// Discard the queued records, wait for the readers and return
+    /// the combined statistics of all samplers.
+    pub async fn finish(mut self) -> SessionStats {
+        self.stop();
+        // Readers waiting for space in the queue give up.
+        drop(self.records);
+        let mut stats = SessionStats::default();
+        while let Some(reader_stats) = self.stats.recv().await {
+            stats.merge(&reader_stats);
+        }
+        // All readers are done, only propagates their panics.
+        for reader in self.readers {
+            reader
+                .join()
+                .unwrap_or_else(|err| panic::resume_unwind(err));
+        }
+        stats
+    }
+}
+
+/// Moves records of one sampler into the queue.
+struct Reader<B: SamplerBackend> {
+    sampler: Sampler<B>,
+    records: mpsc::Sender<Result<Record, TauphiError>>,
+    control: watch::Receiver<Control>,
+    metrics: Arc<ReadMetrics>,
+}
+
+impl<B: SamplerBackend> Reader<B> {
+    /// Read until the sampler is exhausted, stopped or the queue is closed.
+    fn run(mut self) -> SessionStats {
+        loop {
+            // A dropped pipeline stops the reader too.
+            let request = match self.control.has_changed() {
+                Ok(false) => None,
+                Ok(true) => Some(*self.control.borrow_and_update()),
+                Err(_) => Some(Control {
+                    frequency: None,
+                    stopped: true,
+                }),
+            };
+            if let Some(request) = request {
+                if request.stopped {
+                    match self.sampler.stop() {
+                        Ok(()) => self.drain(),
+                        Err(err) => {
+                            self.send(Err(err));
+                        }
+                    }
+                    break;
+                }
+                if let Some(frequency) = request.frequency {
+                    if let Err(err) = self.sampler.set_frequency(frequency) {
+                        if !self.send(Err(err)) {
+                            break;
+                        }
+                    }
+                }
+            }
+
+            let fill = self.sampler.buffer_usage().fill();
+            self.metrics
+                .buffer_peak
+                .fetch_max((fill * 1e6) as u64, Ordering::Relaxed);
+            if let Some(record) = self.sampler.get_record() {
+                if !self.send(Ok(record)) {
+                    break;
+                }
+            } else if self.sampler.has_exited() {
+                tracing::debug!("sampled process exited");
+                // Records written before the exit might have just arrived.
+                self.drain();
+                break;
+            } else {
+                self.sampler.wait(Pipeline::CONTROL_PERIOD);
+            }
+        }
+        let stats = self.sampler.stats();
+        tracing::debug!(
+            samples = stats.samples,
+            lost = stats.lost,
+            malformed = stats.malformed,
+            "reader finished"
+        );
+        stats
+    }
+
+    /// Pass the already collected records.
+    fn drain(&self) {
+        while let Some(record) = self.sampler.get_record() {
+            if !self.send(Ok(record)) {
+                return;
+            }
+        }
+    }
+
+    /// Queue the record, returns false once the queue is closed.
+    fn send(&self, record: Result<Record, TauphiError>) -> bool {
+        match self.records.try_send(record) {
+            Ok(()) => (),
+            Err(TrySendError::Full(record)) => {
+                self.metrics.stalls.fetch_add(1, Ordering::Relaxed);
+                tracing::trace!("queue full, waiting for the consumer");
+                if self.records.blocking_send(record).is_err() {
+                    return false;
+                }
+            }
+            Err(TrySendError::Closed(_)) => return false,
+        }
+        self.metrics.read.fetch_add(1, Ordering::Relaxed);
+        let queued = self.records.max_capacity() - self.records.capacity();
+        self.metrics.queue_peak.fetch_max(queued, Ordering::Relaxed);
+        true
+    }
+}
+
+#[test]
+fn pipeline_test() {
+    use crate::backend::MockBackend;
+    use crate::sampling::{Sample, SamplerOptions};
+
+    let samplers = (0..3)
+        .map(|cpu| {
+            let samples = (0..10).map(|time| {
+                Record::Sample(Sample {
+                    cpu,
+                    time,
+                    ..Default::default()
+                })
+            });
+            Sampler::with_backend(MockBackend::new(samples), &SamplerOptions::new(10))
+        })
+        .collect();
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let (records, stats) = runtime.block_on(async {
+        // Small queue to make the readers wait.
+        let mut pipeline = Pipeline::spawn(samplers, 2);
+        let mut records = 0;
+        while pipeline.recv().await.unwrap().is_some() {
+            records += 1;
+        }
+        assert_eq!(pipeline.stats().read, 30);
+        assert_eq!(pipeline.stats().buffer_peak, 1.0);
+        (records, pipeline.finish().await)
+    });
+    assert_eq!(records, 30);
+    assert_eq!(stats.samples, 30);
+}
@@ -0,0 +1,115 @@
+//! Recycling of sample buffers.
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex};
+
+use crate::sampling::Sample;
+
+/// Recycles callchain buffers of samples, avoiding an allocation per sample.
+///
+/// Samplers take buffers from the pool set in
+/// [crate::sampling::SamplerOptions::pool], consumers give them back by
+/// [SamplePool::recycle()] or by dropping a [PooledSample]. In the steady
+/// state, no sample allocates.
+#[derive(Debug, Clone, Default)]
+pub struct SamplePool {
+    buffers: Arc<Mutex<Vec<Vec<u64>>>>,
+    /// Maximum number of kept buffers, 0 disables the pooling.
+    capacity: usize,
+}
+
+impl SamplePool {
+    /// Create a pool keeping at most `capacity` unused buffers.
+    pub fn new(capacity: usize) -> SamplePool {
+        SamplePool {
+            buffers: Arc::new(Mutex::new(Vec::with_capacity(capacity))),
+            capacity,
+        }
+    }
+
+    /// Empty buffer for a new callchain.
+    pub(crate) fn take(&self) -> Vec<u64> {
+        if self.capacity == 0 {
+            return Vec::new();
+        }
+        self.buffers.lock().unwrap().pop().unwrap_or_default()
+    }
+
+    /// Return the sample's buffers to the pool.
+    pub fn recycle(&self, sample: Sample) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut callchain = sample.callchain;
+        callchain.clear();
+        let mut buffers = self.buffers.lock().unwrap();
+        if buffers.len() < self.capacity {
+            buffers.push(callchain);
+        }
+    }
+
+    /// Wrap the sample so it is recycled when dropped.
+    pub fn guard(&self, sample: Sample) -> PooledSample {
+        PooledSample {
+            sample: Some(sample),
+            pool: self.clone(),
+        }
+    }
+
+    /// Number of unused buffers in the pool.
+    pub fn available(&self) -> usize {
+        self.buffers.lock().unwrap().len()
+    }
+}
+
+/// A sample returned to its [SamplePool] when dropped.
+#[derive(Debug)]
+pub struct PooledSample {
+    /// Always present until dropped.
+    sample: Option<Sample>,
+    pool: SamplePool,
+}
+
+impl PooledSample {
+    /// Take the sample out of the pool's management.
+    pub fn into_inner(mut self) -> Sample {
+        self.sample.take().unwrap()
+    }
+}
+
+impl Deref for PooledSample {
+    type Target = Sample;
+
+    fn deref(&self) -> &Sample {
+        self.sample.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for PooledSample {
+    fn deref_mut(&mut self) -> &mut Sample {
+        self.sample.as_mut().unwrap()
+    }
+}
+
+impl Drop for PooledSample {
+    fn drop(&mut self) {
+        if let Some(sample) = self.sample.take() {
+            self.pool.recycle(sample);
+        }
+    }
+}
+
+#[test]
+fn sample_pool_test() {
+    let pool = SamplePool::new(1);
+    let mut sample = Sample::default();
+    sample.callchain.extend_from_slice(&[1, 2, 3]);
+    drop(pool.guard(sample));
+    assert_eq!(pool.available(), 1);
+    // The capacity is kept, the content is not.
+    let buffer = pool.take();
+    assert!(buffer.is_empty() && buffer.capacity() >= 3);
+    assert_eq!(pool.available(), 0);
+    pool.recycle(Sample::default());
+    pool.recycle(Sample::default());
+    assert_eq!(pool.available(), 1);
+}
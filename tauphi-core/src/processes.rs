@@ -0,0 +1,170 @@
+//! Lifetimes of sampled processes, from FORK, EXIT and COMM records.
+//!
+//! Identifies also short-lived processes which are gone by the time the
+//! report is written, see [SamplerOptions::task_events](crate::sampling::SamplerOptions::task_events).
+use std::collections::HashMap;
+use std::fs;
+
+use crate::sampling::{Record, Sample};
+
+/// A process seen during the sampling.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProcessInfo {
+    pub pid: u32,
+    /// Parent process, known only for processes created during the sampling.
+    pub ppid: Option<u32>,
+    /// Last command name, `None` if the process exited before it was read.
+    pub command: Option<String>,
+    /// Monotonic time of the creation in nanoseconds, if during the sampling.
+    pub start: Option<u64>,
+    /// Monotonic time of the exit in nanoseconds, if during the sampling.
+    pub end: Option<u64>,
+    /// Number of samples of the process.
+    pub samples: u64,
+}
+
+/// Collects [ProcessInfo] of all processes with records.
+///
+/// Feed it all records via [ProcessTable::observe()].
+#[derive(Debug, Default)]
+pub struct ProcessTable {
+    processes: HashMap<u32, ProcessInfo>,
+}
+
+impl ProcessTable {
+    /// Account the record to its process.
+    ///
+    /// Records of threads other than the main one are ignored, except for
+    /// samples.
+    pub fn observe(&mut self, record: &Record) {
+        match record {
+            Record::Sample(sample) => self.add_sample(sample),
+            Record::Fork(event) if event.is_process() => {
+                // A forked process runs the parent's command until exec().
+                let command = self
+                    .processes
+                    .get(&event.ppid)
+                    .and_then(|parent| parent.command.clone());
+                let process = self.process(event.pid);
+                process.ppid = Some(event.ppid);
+                process.start = Some(event.time);
+                process.command = process.command.take().or(command);
+            }
+            Record::Exit(event) if event.is_process() => {
+                self.process(event.pid).end = Some(event.time);
+            }
+            Record::Comm(event) if event.pid == event.tid => {
+                self.process(event.pid).command = Some(event.comm.clone());
+            }
+            _ => (),
+        }
+    }
+
+    /// Count the sample of the process, reads the command of processes
+    /// without COMM records on their first sample, e.g. of those which
+    /// started before the sampling.
+    pub fn add_sample(&mut self, sample: &Sample) {
+        let process = self.process(sample.pid);
+        process.samples += 1;
+        if process.command.is_none() && process.samples == 1 {
+            process.command = fs::read_to_string(format!("/proc/{}/comm", sample.pid))
+                .ok()
+                .map(|comm| comm.trim_end().to_owned());
+        }
+    }
+
+    /// Processes with samples, the most sampled first.
+    pub fn processes(&self) -> Vec<&ProcessInfo> {
+        let mut processes: Vec<_> = self
+            .processes
+            .values()
+            .filter(|process| process.samples > 0)
+            .collect();
+        processes.sort_by_key(|process| (u64::MAX - process.samples, process.pid));
+        processes
+    }
+
+    fn process(&mut self, pid: u32) -> &mut ProcessInfo {
+        self.processes.entry(pid).or_insert_with(|| ProcessInfo {
+            pid,
+            ..Default::default()
+        })
+    }
+}
+
+#[test]
+fn process_table_test() {
+    use crate::sampling::{CommEvent, TaskEvent};
+
+    let mut table = ProcessTable::default();
+    let task = |pid, ppid, time| TaskEvent {
+        pid,
+        ppid,
+        tid: pid,
+        ptid: ppid,
+        time,
+    };
+    table.observe(&Record::Comm(CommEvent {
+        pid: 10,
+        tid: 10,
+        comm: "make".to_owned(),
+    }));
+    table.observe(&Record::Fork(task(11, 10, 100)));
+    for _ in 0..2 {
+        table.observe(&Record::Sample(Sample {
+            pid: 11,
+            ..Default::default()
+        }));
+    }
+    table.observe(&Record::Comm(CommEvent {
+        pid: 11,
+        tid: 11,
+        comm: "cc1".to_owned(),
+    }));
+    table.observe(&Record::Exit(task(11, 10, 300)));
+    // Threads do not change their process.
+    table.observe(&Record::Exit(TaskEvent {
+        tid: 12,
+        ..task(10, 1, 400)
+    }));
+
+    assert_eq!(
+        table.processes(),
+        [&ProcessInfo {
+            pid: 11,
+            ppid: Some(10),
+            command: Some("cc1".to_owned()),
+            start: Some(100),
+            end: Some(300),
+            samples: 2,
+        }]
+    );
+}
+
+#[test]
+fn replayed_comm_test() {
+    use crate::replay::{RawDump, ReplayBackend};
+    use crate::sampling::{Sampler, SamplerOptions};
+
+    // A process which exited, its command is known only from the record.
+    let pid = u32::MAX;
+    let path = std::env::temp_dir().join(format!("tauphi-comm-{}.raw", std::process::id()));
+    let dump = RawDump::create(&path).unwrap();
+    let mut comm = [pid.to_ne_bytes(), pid.to_ne_bytes()].concat();
+    comm.extend(*b"cc1\0\0\0\0\0");
+    dump.write(3, &comm);
+    dump.finish().unwrap();
+
+    let options = SamplerOptions::new(1);
+    let sampler = Sampler::with_backend(ReplayBackend::open(&path).unwrap(), &options);
+    let mut table = ProcessTable::default();
+    while let Some(record) = sampler.get_record() {
+        table.observe(&record);
+    }
+    table.add_sample(&Sample {
+        pid,
+        ..Default::default()
+    });
+    assert_eq!(table.processes()[0].command.as_deref(), Some("cc1"));
+    std::fs::remove_file(path).unwrap();
+}
@@ -0,0 +1,139 @@
+//! Live statistics of a running sampling, for a periodic status line.
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::time::{Duration, Instant};
+
+use crate::sampling::Record;
+
+/// Tracks the records of a sampling to report its progress.
+///
+/// Feed it all records via [ProgressMeter::observe()] and call
+/// [ProgressMeter::progress()] periodically, rates are computed over the
+/// time since the previous call.
+#[derive(Debug)]
+pub struct ProgressMeter {
+    started: Instant,
+    samples: u64,
+    lost: u64,
+    /// Hashes of the distinct callchains seen so far.
+    stacks: HashSet<u64>,
+    /// Time, samples and lost samples of the previous report.
+    reported: (Instant, u64, u64),
+}
+
+/// Snapshot returned by [ProgressMeter::progress()].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Progress {
+    /// Time since the meter started.
+    pub elapsed: Duration,
+    /// All observed samples.
+    pub samples: u64,
+    /// Samples per second since the previous report.
+    pub sample_rate: f64,
+    /// Lost samples per second since the previous report.
+    pub loss_rate: f64,
+    /// Number of distinct callchains.
+    pub stacks: usize,
+    /// Bytes of output written so far.
+    pub output_bytes: u64,
+    /// Highest fill of the ring buffers so far, from 0 to 1.
+    pub buffer_peak: f64,
+}
+
+impl ProgressMeter {
+    /// Start measuring from now.
+    pub fn start() -> ProgressMeter {
+        let now = Instant::now();
+        ProgressMeter {
+            started: now,
+            samples: 0,
+            lost: 0,
+            stacks: HashSet::new(),
+            reported: (now, 0, 0),
+        }
+    }
+
+    /// Account the record.
+    pub fn observe(&mut self, record: &Record) {
+        match record {
+            Record::Sample(sample) => {
+                self.samples += 1;
+                let mut hasher = DefaultHasher::new();
+                sample.callchain.hash(&mut hasher);
+                self.stacks.insert(hasher.finish());
+            }
+            Record::Lost(lost) => self.lost += lost,
+            _ => (),
+        }
+    }
+
+    /// Progress since the start, with rates since the previous call.
+    ///
+    /// # Arguments
+    /// * `output_bytes` - Size of the output written so far.
+    /// * `buffer_peak` - Highest fill of the ring buffers, see
+    ///   [PipelineStats::buffer_peak](crate::pipeline::PipelineStats::buffer_peak).
+    pub fn progress(&mut self, output_bytes: u64, buffer_peak: f64) -> Progress {
+        let now = Instant::now();
+        let (since, samples, lost) = self.reported;
+        self.reported = (now, self.samples, self.lost);
+        let period = now.duration_since(since).as_secs_f64();
+        let rate = |count: u64| {
+            if period > 0.0 {
+                count as f64 / period
+            } else {
+                0.0
+            }
+        };
+        Progress {
+            elapsed: now.duration_since(self.started),
+            samples: self.samples,
+            sample_rate: rate(self.samples - samples),
+            loss_rate: rate(self.lost - lost),
+            stacks: self.stacks.len(),
+            output_bytes,
+            buffer_peak,
+        }
+    }
+}
+
+impl fmt::Display for Progress {
+    /// One line, e.g. `[ 12.0 s] 4100 samples (1000/s), lost 0/s, 120 stacks, 1.5 MiB, buffers 3 %`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "[{:5.1} s] {} samples ({:.0}/s), lost {:.0}/s, {} stacks, {:.1} MiB, buffers {:.0} %",
+            self.elapsed.as_secs_f64(),
+            self.samples,
+            self.sample_rate,
+            self.loss_rate,
+            self.stacks,
+            self.output_bytes as f64 / (1 << 20) as f64,
+            self.buffer_peak * 100.0
+        )
+    }
+}
+
+#[test]
+fn progress_test() {
+    use crate::sampling::Sample;
+
+    let mut meter = ProgressMeter::start();
+    for callchain in [vec![1, 2], vec![1, 2], vec![3]] {
+        meter.observe(&Record::Sample(Sample {
+            callchain,
+            ..Default::default()
+        }));
+    }
+    meter.observe(&Record::Lost(4));
+    let progress = meter.progress(3 << 19, 0.25);
+    assert_eq!((progress.samples, progress.stacks), (3, 2));
+    assert!(progress.to_string().contains(" 3 samples ("));
+    assert!(progress
+        .to_string()
+        .ends_with("2 stacks, 1.5 MiB, buffers 25 %"));
+    // Rates cover only the time since the previous report.
+    assert_eq!(meter.progress(0, 0.0).sample_rate, 0.0);
+}
@@ -0,0 +1,258 @@
+//! Decoding of records read from the perf_event ring buffer.
+//!
+//! Works on plain byte slices, unexpected input is reported as an error
+//! instead of being misread.
+use perf_event as pe;
+
+use crate::error::TauphiError;
+use crate::pool::SamplePool;
+use crate::sampling::{CgroupEvent, CommEvent, Event, Record, Sample, TaskEvent};
+
+/// Decode the payload of a record, without its `perf_event_header`.
+///
+/// Returns `None` for record types tauphi does not use. Samples must carry
+/// the fields the samplers request: IP, TID, time, CPU and callchain,
+/// optionally followed by raw data. Callchains of samples come from `pool`.
+pub fn parse_record(
+    record_type: pe::RecordType,
+    payload: &[u8],
+    pool: &SamplePool,
+) -> Result<Option<Record>, TauphiError> {
+    let mut cursor = Cursor {
+        bytes: payload,
+        record_type,
+    };
+    let record = match record_type {
+        pe::RecordType::Sample => {
+            let ip = cursor.u64()?;
+            let pid = cursor.u32()?;
+            let tid = cursor.u32()?;
+            let time = cursor.u64()?;
+            let cpu = cursor.u32()?;
+            let _reserved = cursor.u32()?;
+            let entries = cursor.u64()?;
+            // Checked before allocating, the count might be garbage.
+            if entries > (cursor.bytes.len() / 8) as u64 {
+                return Err(cursor.malformed(&format!("{entries} callchain entries")));
+            }
+            let mut callchain = pool.take();
+            for _ in 0..entries {
+                callchain.push(cursor.u64()?);
+            }
+            // Only samples of bpf-output events have raw data.
+            let raw = if cursor.bytes.is_empty() {
+                Vec::new()
+            } else {
+                let size = cursor.u32()?;
+                cursor.slice(size as usize)?.to_vec()
+            };
+            Record::Sample(Sample {
+                ip,
+                pid,
+                tid,
+                time,
+                cpu,
+                callchain,
+                // Known only to the sampler.
+                event: Event::default(),
+                raw,
+            })
+        }
+        pe::RecordType::Fork | pe::RecordType::Exit => {
+            let event = TaskEvent {
+                pid: cursor.u32()?,
+                ppid: cursor.u32()?,
+                tid: cursor.u32()?,
+                ptid: cursor.u32()?,
+                time: cursor.u64()?,
+            };
+            if record_type == pe::RecordType::Fork {
+                Record::Fork(event)
+            } else {
+                Record::Exit(event)
+            }
+        }
+        pe::RecordType::Comm => {
+            let pid = cursor.u32()?;
+            let tid = cursor.u32()?;
+            Record::Comm(CommEvent {
+                pid,
+                tid,
+                comm: cursor.string("command")?,
+            })
+        }
+        pe::RecordType::Cgroup => {
+            let id = cursor.u64()?;
+            Record::Cgroup(CgroupEvent {
+                id,
+                path: cursor.string("cgroup path")?,
+            })
+        }
+        pe::RecordType::Lost => {
+            let _id = cursor.u64()?;
+            Record::Lost(cursor.u64()?)
+        }
+        pe::RecordType::Throttle | pe::RecordType::Unthrottle => {
+            let time = cursor.u64()?;
+            let _id = cursor.u64()?;
+            let _stream_id = cursor.u64()?;
+            if record_type == pe::RecordType::Throttle {
+                Record::Throttle(time)
+            } else {
+                Record::Unthrottle(time)
+            }
+        }
+        _ => return Ok(None),
+    };
+    // Leftovers mean the layout is not the expected one.
+    if !cursor.bytes.is_empty() {
+        return Err(cursor.malformed(&format!("{} trailing bytes", cursor.bytes.len())));
+    }
+    Ok(Some(record))
+}
+
+/// Reads native-endian fields from the front of a payload.
+struct Cursor<'a> {
+    bytes: &'a [u8],
+    record_type: pe::RecordType,
+}
+
+impl Cursor<'_> {
+    fn u32(&mut self) -> Result<u32, TauphiError> {
+        Ok(u32::from_ne_bytes(self.take()?))
+    }
+
+    fn u64(&mut self) -> Result<u64, TauphiError> {
+        Ok(u64::from_ne_bytes(self.take()?))
+    }
+
+    fn slice(&mut self, len: usize) -> Result<&[u8], TauphiError> {
+        if self.bytes.len() < len {
+            return Err(self.malformed("truncated"));
+        }
+        let (field, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(field)
+    }
+
+    /// The rest of the payload as a string, NUL-terminated and padded to
+    /// 8 bytes.
+    fn string(&mut self, what: &str) -> Result<String, TauphiError> {
+        let padded = self.slice(self.bytes.len())?;
+        let Some(length) = padded.iter().position(|&byte| byte == 0) else {
+            return Err(self.malformed(&format!("unterminated {what}")));
+        };
+        Ok(String::from_utf8_lossy(&padded[..length]).into_owned())
+    }
+
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], TauphiError> {
+        let Some((field, rest)) = self.bytes.split_first_chunk() else {
+            return Err(self.malformed("truncated"));
+        };
+        self.bytes = rest;
+        Ok(*field)
+    }
+
+    fn malformed(&self, reason: &str) -> TauphiError {
+        TauphiError::MalformedRecord(format!("{:?} record, {reason}", self.record_type))
+    }
+}
+
+#[test]
+fn parse_sample_test() {
+    let fields: [u64; 6] = [0x1234, 2 << 32 | 1, 1000, 3, 2, 0xffff_ffff_ffff_fe00];
+    let mut payload: Vec<u8> = fields
+        .iter()
+        .flat_map(|field| field.to_ne_bytes())
+        .collect();
+    payload.extend(0x1234_u64.to_ne_bytes());
+    let pool = SamplePool::default();
+    let Some(Record::Sample(sample)) =
+        parse_record(pe::RecordType::Sample, &payload, &pool).unwrap()
+    else {
+        panic!("Expected a sample.");
+    };
+    assert_eq!((sample.ip, sample.time, sample.cpu), (0x1234, 1000, 3));
+    assert_eq!(sample.callchain, [0xffff_ffff_ffff_fe00, 0x1234]);
+
+    assert!(sample.raw.is_empty());
+
+    // Raw data of bpf-output events, padded to 8 bytes.
+    let mut raw_payload = payload.clone();
+    raw_payload.extend(4_u32.to_ne_bytes());
+    raw_payload.extend(*b"tph\0");
+    let Some(Record::Sample(sample)) =
+        parse_record(pe::RecordType::Sample, &raw_payload, &pool).unwrap()
+    else {
+        panic!("Expected a sample.");
+    };
+    assert_eq!(sample.raw, b"tph\0");
+    assert!(parse_record(
+        pe::RecordType::Sample,
+        &raw_payload[..raw_payload.len() - 1],
+        &pool
+    )
+    .is_err());
+
+    // One callchain entry missing.
+    assert!(parse_record(pe::RecordType::Sample, &payload[..payload.len() - 8], &pool).is_err());
+    assert!(parse_record(pe::RecordType::Lost, &payload, &pool).is_err());
+    assert!(parse_record(pe::RecordType::Mmap, &payload, &pool)
+        .unwrap()
+        .is_none());
+}
+
+#[test]
+fn parse_comm_test() {
+    let mut payload = Vec::new();
+    payload.extend(7_u32.to_ne_bytes());
+    payload.extend(8_u32.to_ne_bytes());
+    payload.extend(*b"make\0\0\0\0");
+    let pool = SamplePool::default();
+    let Some(Record::Comm(comm)) = parse_record(pe::RecordType::Comm, &payload, &pool).unwrap()
+    else {
+        panic!("Expected a COMM record.");
+    };
+    assert_eq!((comm.pid, comm.tid, comm.comm.as_str()), (7, 8, "make"));
+    payload.truncate(12);
+    assert!(parse_record(pe::RecordType::Comm, &payload, &pool).is_err());
+}
+
+#[test]
+fn parse_cgroup_test() {
+    let mut payload = Vec::new();
+    payload.extend(4242_u64.to_ne_bytes());
+    payload.extend(*b"/system.slice/a.service\0");
+    let pool = SamplePool::default();
+    let Some(Record::Cgroup(cgroup)) =
+        parse_record(pe::RecordType::Cgroup, &payload, &pool).unwrap()
+    else {
+        panic!("Expected a CGROUP record.");
+    };
+    assert_eq!(
+        (cgroup.id, cgroup.path.as_str()),
+        (4242, "/system.slice/a.service")
+    );
+    payload.pop();
+    assert!(parse_record(pe::RecordType::Cgroup, &payload, &pool).is_err());
+}
+
+#[test]
+fn parse_arbitrary_bytes_test() {
+    // Cheap stand-in for the fuzz target, no input may panic.
+    let pool = SamplePool::default();
+    let mut state = 0x2545_f491_4f6c_dd1d_u64;
+    let mut payload = Vec::new();
+    for _ in 0..10_000 {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        payload.push(state as u8);
+        if payload.len() > 200 {
+            payload.clear();
+        }
+        for record_type in (1..=10).chain([19]) {
+            let _ = parse_record(record_type.into(), &payload, &pool);
+        }
+    }
+}
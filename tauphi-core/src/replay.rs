@@ -0,0 +1,196 @@
+//! Dumps of raw ring-buffer records and their replay, makes decoding
+//! problems reproducible away from the machine they occurred on.
+//!
+//! A dump starts with [DUMP_MAGIC], each record follows as its native-endian
+//! `u32` type, `u32` payload size and the payload itself. Dumps are
+//! therefore replayable only on machines of the same endianness.
+use std::cell::{Cell, RefCell};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::backend::{BufferUsage, SamplerBackend};
+use crate::error::TauphiError;
+use crate::pool::SamplePool;
+use crate::record::parse_record;
+use crate::sampling::Record;
+
+/// Leading bytes of every dump, the last one is the format version.
+pub const DUMP_MAGIC: [u8; 8] = *b"TAUPHI\0\x01";
+
+/// Destination of raw records, shared by all samplers of a session,
+/// see [SamplerOptions::dump](crate::sampling::SamplerOptions::dump).
+#[derive(Debug, Clone)]
+pub struct RawDump {
+    writer: Arc<Mutex<DumpWriter>>,
+}
+
+#[derive(Debug)]
+struct DumpWriter {
+    writer: BufWriter<File>,
+    /// First failed write, later records are not written.
+    error: Option<io::Error>,
+}
+
+impl RawDump {
+    /// Create or truncate the dump file.
+    pub fn create(path: impl AsRef<Path>) -> Result<RawDump, TauphiError> {
+        let mut writer = BufWriter::new(File::create(&path).map_err(TauphiError::file(&path))?);
+        writer.write_all(&DUMP_MAGIC)?;
+        Ok(RawDump {
+            writer: Arc::new(Mutex::new(DumpWriter {
+                writer,
+                error: None,
+            })),
+        })
+    }
+
+    /// Append the record, failures are reported by [RawDump::finish()].
+    pub(crate) fn write(&self, record_type: u32, payload: &[u8]) {
+        let mut dump = self.writer.lock().unwrap();
+        if dump.error.is_some() {
+            return;
+        }
+        let header = [
+            record_type.to_ne_bytes(),
+            (payload.len() as u32).to_ne_bytes(),
+        ];
+        let written = dump
+            .writer
+            .write_all(header.as_flattened())
+            .and_then(|()| dump.writer.write_all(payload));
+        if let Err(err) = written {
+            dump.error = Some(err);
+        }
+    }
+
+    /// Flush the written records, returns the first failure if any.
+    pub fn finish(&self) -> Result<(), TauphiError> {
+        let mut dump = self.writer.lock().unwrap();
+        if let Some(err) = dump.error.take() {
+            return Err(err.into());
+        }
+        Ok(dump.writer.flush()?)
+    }
+}
+
+/// Replays a dump written via [RawDump], decoding every record anew.
+///
+/// Behaves like a process which exits after its last record.
+#[derive(Debug)]
+pub struct ReplayBackend {
+    reader: RefCell<BufReader<File>>,
+    exhausted: Cell<bool>,
+}
+
+impl ReplayBackend {
+    /// Open the dump file.
+    pub fn open(path: impl AsRef<Path>) -> Result<ReplayBackend, TauphiError> {
+        let mut reader = BufReader::new(File::open(&path).map_err(TauphiError::file(&path))?);
+        let mut magic = [0; DUMP_MAGIC.len()];
+        reader.read_exact(&mut magic)?;
+        if magic != DUMP_MAGIC {
+            return Err(TauphiError::InvalidArgument(
+                "not a tauphi raw dump".to_owned(),
+            ));
+        }
+        Ok(ReplayBackend {
+            reader: RefCell::new(reader),
+            exhausted: Cell::new(false),
+        })
+    }
+
+    /// Next raw record, `None` at the end of the dump.
+    fn read_raw(&self) -> io::Result<Option<(u32, Vec<u8>)>> {
+        let mut reader = self.reader.borrow_mut();
+        let mut header = [0; 8];
+        match reader.read_exact(&mut header) {
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            result => result?,
+        }
+        let (record_type, size) = header.split_at(4);
+        let record_type = u32::from_ne_bytes(record_type.try_into().unwrap());
+        let size = u32::from_ne_bytes(size.try_into().unwrap());
+        let mut payload = Vec::new();
+        reader
+            .by_ref()
+            .take(size.into())
+            .read_to_end(&mut payload)?;
+        if payload.len() != size as usize {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(Some((record_type, payload)))
+    }
+}
+
+impl SamplerBackend for ReplayBackend {
+    fn read_record(&self, pool: &SamplePool) -> Option<Record> {
+        while !self.exhausted.get() {
+            let (record_type, payload) = match self.read_raw() {
+                Ok(Some(raw)) => raw,
+                Ok(None) => break,
+                Err(err) => {
+                    // The rest of the dump cannot be trusted.
+                    self.exhausted.set(true);
+                    return Some(Record::Malformed(format!("truncated dump, {err}")));
+                }
+            };
+            match parse_record(record_type.into(), &payload, pool) {
+                Ok(Some(record)) => return Some(record),
+                Ok(None) => (),
+                Err(err) => return Some(Record::Malformed(err.to_string())),
+            }
+        }
+        self.exhausted.set(true);
+        None
+    }
+
+    fn start(&self) -> Result<(), TauphiError> {
+        Ok(())
+    }
+
+    fn stop(&self) -> Result<(), TauphiError> {
+        Ok(())
+    }
+
+    fn set_frequency(&self, _frequency: usize) -> Result<(), TauphiError> {
+        Ok(())
+    }
+
+    fn buffer_usage(&self) -> BufferUsage {
+        // The dump is read on demand, nothing is pending.
+        BufferUsage::default()
+    }
+
+    fn has_exited(&self) -> bool {
+        self.exhausted.get()
+    }
+
+    fn wait(&self, _timeout: Duration) -> bool {
+        true
+    }
+}
+
+#[test]
+fn dump_replay_test() {
+    let path = std::env::temp_dir().join(format!("tauphi-dump-{}.raw", std::process::id()));
+    let dump = RawDump::create(&path).unwrap();
+    let lost = [7_u64.to_ne_bytes(), 3_u64.to_ne_bytes()];
+    dump.write(2, lost.as_flattened());
+    // Truncated lost record.
+    dump.write(2, &lost[0]);
+    dump.finish().unwrap();
+
+    let replay = ReplayBackend::open(&path).unwrap();
+    let pool = SamplePool::default();
+    assert!(matches!(replay.read_record(&pool), Some(Record::Lost(3))));
+    assert!(matches!(
+        replay.read_record(&pool),
+        Some(Record::Malformed(_))
+    ));
+    assert!(replay.read_record(&pool).is_none());
+    assert!(replay.has_exited());
+    std::fs::remove_file(path).unwrap();
+}
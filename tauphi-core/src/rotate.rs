@@ -0,0 +1,221 @@
+//! Output files rotated by size or age with a bounded retention, keeps
+//! long-running sampling from filling the disk.
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::{self, BufWriter};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use crate::error::TauphiError;
+use crate::export::Exporter;
+use crate::sampling::Sample;
+use crate::sink::{CountingWriter, SampleSink};
+
+/// When to start a new file and which old files to keep.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RotationPolicy {
+    /// Start a new file once the current one has this many bytes.
+    pub max_size: Option<u64>,
+    /// Start a new file once the current one is this old.
+    pub max_age: Option<Duration>,
+    /// Remove the oldest files beyond this number of files.
+    pub keep_files: Option<usize>,
+    /// Remove the oldest files while all of them have more bytes.
+    pub keep_bytes: Option<u64>,
+}
+
+impl RotationPolicy {
+    /// Whether new files are ever started.
+    pub fn rotates(&self) -> bool {
+        self.max_size.is_some() || self.max_age.is_some()
+    }
+}
+
+/// Exports samples into a series of files `<path>.0`, `<path>.1`, ...
+///
+/// Each file is a complete output of the exporter, e.g. with its own CSV
+/// header or folded stacks of its period. Files are rotated only when
+/// a sample arrives. Without rotation, samples are exported into `<path>`
+/// itself. Only files written by this sink are ever removed.
+pub struct RotatingSink<'a> {
+    path: PathBuf,
+    policy: RotationPolicy,
+    exporter: &'a dyn Exporter,
+    sink: Box<dyn SampleSink>,
+    /// Bytes written into all files so far.
+    written: Rc<Cell<u64>>,
+    /// Value of [RotatingSink::written] when the current file was opened.
+    file_start: u64,
+    opened: Instant,
+    current: PathBuf,
+    /// Number of the next file.
+    next: usize,
+    /// Finished files with their sizes, oldest first.
+    finished: VecDeque<(PathBuf, u64)>,
+}
+
+impl<'a> RotatingSink<'a> {
+    /// Export into files starting at `path`, see [RotatingSink].
+    pub fn new(
+        path: impl Into<PathBuf>,
+        policy: RotationPolicy,
+        exporter: &'a dyn Exporter,
+    ) -> Result<RotatingSink<'a>, TauphiError> {
+        let path = path.into();
+        let current = if policy.rotates() {
+            numbered(&path, 0)
+        } else {
+            path.clone()
+        };
+        let written = Rc::new(Cell::new(0));
+        let sink = open(&current, exporter, &written)?;
+        Ok(RotatingSink {
+            path,
+            policy,
+            exporter,
+            sink,
+            written,
+            file_start: 0,
+            opened: Instant::now(),
+            current,
+            next: 1,
+            finished: VecDeque::new(),
+        })
+    }
+
+    /// Paths of all kept files, oldest first.
+    pub fn files(&self) -> impl Iterator<Item = &PathBuf> {
+        self.finished
+            .iter()
+            .map(|(path, _)| path)
+            .chain([&self.current])
+    }
+
+    /// Number of bytes written into all files so far, including removed
+    /// ones, updated as samples are exported.
+    pub fn written(&self) -> Rc<Cell<u64>> {
+        self.written.clone()
+    }
+
+    /// Bytes written into the current file so far.
+    fn file_size(&self) -> u64 {
+        self.written.get() - self.file_start
+    }
+
+    /// Whether the current file should be finished.
+    fn is_due(&self) -> bool {
+        let too_big = self
+            .policy
+            .max_size
+            .is_some_and(|max_size| self.file_size() >= max_size);
+        let too_old = self
+            .policy
+            .max_age
+            .is_some_and(|max_age| self.opened.elapsed() >= max_age);
+        too_big || too_old
+    }
+
+    /// Finish the current file and continue in a new one.
+    fn rotate(&mut self) -> Result<(), TauphiError> {
+        self.sink.finish()?;
+        let current = numbered(&self.path, self.next);
+        self.next += 1;
+        let finished = mem::replace(&mut self.current, current);
+        self.finished.push_back((finished, self.file_size()));
+        self.file_start = self.written.get();
+        self.sink = open(&self.current, self.exporter, &self.written)?;
+        self.opened = Instant::now();
+        self.retain()
+    }
+
+    /// Remove the oldest finished files exceeding the retention.
+    fn retain(&mut self) -> Result<(), TauphiError> {
+        loop {
+            let files = self.finished.len() + 1;
+            let bytes: u64 =
+                self.file_size() + self.finished.iter().map(|(_, size)| size).sum::<u64>();
+            let too_many = self.policy.keep_files.is_some_and(|keep| files > keep);
+            let too_big = self.policy.keep_bytes.is_some_and(|keep| bytes > keep);
+            if !(too_many || too_big) {
+                return Ok(());
+            }
+            let Some((oldest, _)) = self.finished.pop_front() else {
+                return Ok(());
+            };
+            match fs::remove_file(oldest) {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err.into()),
+                _ => (),
+            }
+        }
+    }
+}
+
+impl SampleSink for RotatingSink<'_> {
+    fn consume(&mut self, sample: &Sample) -> Result<(), TauphiError> {
+        if self.is_due() {
+            self.rotate()?;
+        }
+        self.sink.consume(sample)
+    }
+
+    fn flush(&mut self) -> Result<(), TauphiError> {
+        self.sink.flush()
+    }
+
+    fn finish(&mut self) -> Result<(), TauphiError> {
+        self.sink.finish()?;
+        self.retain()
+    }
+}
+
+/// `<path>.<number>`
+fn numbered(path: &Path, number: usize) -> PathBuf {
+    let mut numbered = path.as_os_str().to_owned();
+    numbered.push(format!(".{number}"));
+    numbered.into()
+}
+
+/// Create the file and start exporting into it.
+fn open(
+    path: &Path,
+    exporter: &dyn Exporter,
+    written: &Rc<Cell<u64>>,
+) -> Result<Box<dyn SampleSink>, TauphiError> {
+    let file = BufWriter::new(File::create(path).map_err(TauphiError::file(path))?);
+    Ok(exporter.sink(Box::new(CountingWriter::new(file, written.clone()))))
+}
+
+#[test]
+fn rotating_sink_test() {
+    use crate::export::ExporterRegistry;
+
+    let dir = std::env::temp_dir().join(format!("tauphi-rotate-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let registry = ExporterRegistry::with_builtin();
+    let policy = RotationPolicy {
+        max_size: Some(1),
+        keep_files: Some(2),
+        ..Default::default()
+    };
+    let mut sink =
+        RotatingSink::new(dir.join("out.csv"), policy, registry.get("csv").unwrap()).unwrap();
+    for time in 0..4 {
+        let sample = Sample {
+            time,
+            ..Default::default()
+        };
+        sink.consume(&sample).unwrap();
+    }
+    sink.finish().unwrap();
+    let kept: Vec<_> = sink.files().cloned().collect();
+    assert_eq!(kept, [dir.join("out.csv.2"), dir.join("out.csv.3")]);
+    // Every file has its own header.
+    let last = fs::read_to_string(dir.join("out.csv.3")).unwrap();
+    assert!(last.starts_with("time,") && last.contains("\n3,"));
+    assert_eq!(sink.written().get(), 4 * last.len() as u64);
+    assert!(!dir.join("out.csv.1").exists());
+    fs::remove_dir_all(dir).unwrap();
+}
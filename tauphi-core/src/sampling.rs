@@ -0,0 +1,1345 @@
+//! Sampling of CPUs or processes based leveraging Linux perf events.
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::fs::{self, File};
+#[cfg(feature = "async-tokio")]
+use std::future;
+use std::iter;
+use std::os::fd::AsRawFd;
+use std::path::Path;
+use std::str::FromStr;
+#[cfg(feature = "async-tokio")]
+use std::task::Poll;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use libc;
+#[cfg(feature = "async-tokio")]
+use tokio::io::unix::AsyncFd;
+
+use perf_event as pe;
+#[cfg(feature = "async-tokio")]
+use perf_event::error::PerfError;
+
+use crate::backend::{BufferUsage, SamplerBackend};
+use crate::error::TauphiError;
+use crate::pool::{PooledSample, SamplePool};
+use crate::record::parse_record;
+use crate::replay::RawDump;
+
+/// Maximum entries in the stack trace.
+///
+/// 123 keeps samples within 1KB.
+const CALLCHAIN_DEPTH: usize = 123;
+
+/// Largest record payload read from the ring buffer, fits a sample with
+/// [CALLCHAIN_DEPTH] frames and the context markers between them.
+const MAX_RECORD_SIZE: usize = 2048;
+
+/// A collected sample.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Sample {
+    /// Instruction pointer
+    pub ip: u64,
+    /// Process ID
+    pub pid: u32,
+    /// Thread ID
+    pub tid: u32,
+    /// Timestamp of this sample in nanoseconds, monotonic
+    pub time: u64,
+    /// Sampled CPU index
+    pub cpu: u32,
+    /// Instruction pointers for the callchain.
+    pub callchain: Vec<u64>,
+    /// Event which triggered the sample, sessions can sample several.
+    pub event: Event,
+    /// Data of the sample written by a BPF program, see [Event::BpfOutput].
+    /// Empty for other events.
+    pub raw: Vec<u8>,
+}
+
+/// Event triggering the samples.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Event {
+    /// CPU time consumed by the sampled tasks.
+    #[default]
+    TaskClock,
+    /// Both minor and major page faults.
+    PageFaults,
+    /// Switches of the CPU between tasks.
+    ContextSwitches,
+    /// Moves of tasks to another CPU.
+    CpuMigrations,
+    /// Page faults served without any IO.
+    MinorFaults,
+    /// Page faults which required IO.
+    MajorFaults,
+    /// Cache accesses, usually of the last level cache.
+    CacheReferences,
+    /// Cache misses, usually of the last level cache.
+    CacheMisses,
+    /// Load misses of the L1 data cache.
+    L1DcacheLoadMisses,
+    /// Load misses of the last level cache.
+    LlcLoadMisses,
+    /// Retired branch instructions.
+    Branches,
+    /// Mispredicted branch instructions.
+    BranchMisses,
+    /// CPU cycles, not affected by frequency scaling on all CPUs.
+    Cycles,
+    /// Retired instructions.
+    Instructions,
+    /// Data written by BPF programs via `bpf_perf_event_output()`, each call
+    /// produces one sample with [Sample::raw].
+    BpfOutput,
+    /// Kernel tracepoint with the ID from tracefs, e.g. of
+    /// `sched:sched_switch`, see [crate::event_spec].
+    Tracepoint(u64),
+}
+
+impl Event {
+    /// All events with a fixed name, in the order of their names.
+    pub const ALL: [Event; 15] = [
+        Event::TaskClock,
+        Event::PageFaults,
+        Event::ContextSwitches,
+        Event::CpuMigrations,
+        Event::MinorFaults,
+        Event::MajorFaults,
+        Event::CacheReferences,
+        Event::CacheMisses,
+        Event::L1DcacheLoadMisses,
+        Event::LlcLoadMisses,
+        Event::Branches,
+        Event::BranchMisses,
+        Event::Cycles,
+        Event::Instructions,
+        Event::BpfOutput,
+    ];
+
+    /// Name of the event, as used by perf.
+    pub fn name(self) -> &'static str {
+        match self {
+            Event::TaskClock => "task-clock",
+            Event::PageFaults => "page-faults",
+            Event::ContextSwitches => "context-switches",
+            Event::CpuMigrations => "cpu-migrations",
+            Event::MinorFaults => "minor-faults",
+            Event::MajorFaults => "major-faults",
+            Event::CacheReferences => "cache-references",
+            Event::CacheMisses => "cache-misses",
+            Event::L1DcacheLoadMisses => "L1-dcache-load-misses",
+            Event::LlcLoadMisses => "LLC-load-misses",
+            Event::Branches => "branches",
+            Event::BranchMisses => "branch-misses",
+            Event::Cycles => "cycles",
+            Event::Instructions => "instructions",
+            Event::BpfOutput => "bpf-output",
+            Event::Tracepoint(_) => "tracepoint",
+        }
+    }
+
+    /// `PERF_TYPE_*` and the event config for perf_event_open.
+    pub(crate) fn type_and_config(self) -> (u32, u64) {
+        /// PERF_TYPE_HARDWARE
+        const HARDWARE: u32 = 0;
+        /// PERF_TYPE_SOFTWARE
+        const SOFTWARE: u32 = 1;
+        /// PERF_TYPE_TRACEPOINT
+        const TRACEPOINT: u32 = 2;
+        /// PERF_TYPE_HW_CACHE
+        const HW_CACHE: u32 = 3;
+        /// Config of PERF_TYPE_HW_CACHE events, see `man perf_event_open (2)`.
+        const fn hw_cache(cache: u64, op: u64, result: u64) -> u64 {
+            cache | (op << 8) | (result << 16)
+        }
+        /// PERF_COUNT_HW_CACHE_L1D
+        const L1D: u64 = 0;
+        /// PERF_COUNT_HW_CACHE_LL
+        const LL: u64 = 2;
+        /// PERF_COUNT_HW_CACHE_OP_READ
+        const OP_READ: u64 = 0;
+        /// PERF_COUNT_HW_CACHE_RESULT_MISS
+        const RESULT_MISS: u64 = 1;
+        match self {
+            // PERF_COUNT_SW_TASK_CLOCK
+            Event::TaskClock => (SOFTWARE, 1),
+            // PERF_COUNT_SW_PAGE_FAULTS
+            Event::PageFaults => (SOFTWARE, 2),
+            // PERF_COUNT_SW_CONTEXT_SWITCHES
+            Event::ContextSwitches => (SOFTWARE, 3),
+            // PERF_COUNT_SW_CPU_MIGRATIONS
+            Event::CpuMigrations => (SOFTWARE, 4),
+            // PERF_COUNT_SW_PAGE_FAULTS_MIN
+            Event::MinorFaults => (SOFTWARE, 5),
+            // PERF_COUNT_SW_PAGE_FAULTS_MAJ
+            Event::MajorFaults => (SOFTWARE, 6),
+            // PERF_COUNT_HW_CACHE_REFERENCES
+            Event::CacheReferences => (HARDWARE, 2),
+            // PERF_COUNT_HW_CACHE_MISSES
+            Event::CacheMisses => (HARDWARE, 3),
+            Event::L1DcacheLoadMisses => (HW_CACHE, hw_cache(L1D, OP_READ, RESULT_MISS)),
+            Event::LlcLoadMisses => (HW_CACHE, hw_cache(LL, OP_READ, RESULT_MISS)),
+            // PERF_COUNT_HW_BRANCH_INSTRUCTIONS
+            Event::Branches => (HARDWARE, 4),
+            // PERF_COUNT_HW_BRANCH_MISSES
+            Event::BranchMisses => (HARDWARE, 5),
+            // PERF_COUNT_HW_CPU_CYCLES
+            Event::Cycles => (HARDWARE, 0),
+            // PERF_COUNT_HW_INSTRUCTIONS
+            Event::Instructions => (HARDWARE, 1),
+            // PERF_COUNT_SW_BPF_OUTPUT
+            Event::BpfOutput => (SOFTWARE, 10),
+            Event::Tracepoint(id) => (TRACEPOINT, id),
+        }
+    }
+}
+
+impl FromStr for Event {
+    type Err = TauphiError;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            // Aliases used by perf.
+            "faults" => Ok(Event::PageFaults),
+            "cs" => Ok(Event::ContextSwitches),
+            "migrations" => Ok(Event::CpuMigrations),
+            _ => Event::ALL
+                .into_iter()
+                .find(|event| event.name() == name)
+                .ok_or_else(|| TauphiError::InvalidArgument(format!("unknown event '{name}'"))),
+        }
+    }
+}
+
+/// What happens when the consumer does not keep up with the samples.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Backpressure {
+    /// The kernel drops new samples while its buffer is full, they are
+    /// reported by [Record::Lost].
+    #[default]
+    Drop,
+    /// Disable the event while the buffer is mostly full, until the consumer
+    /// drains it. No samples are lost, but none are taken meanwhile either.
+    Pause,
+    /// Move records from the kernel buffer into a user-space queue of at most
+    /// this many records whenever the sampler is read, only then drop.
+    Queue(usize),
+}
+
+impl FromStr for Backpressure {
+    type Err = TauphiError;
+
+    /// Parse `drop`, `pause` or `queue:<N>`.
+    fn from_str(policy: &str) -> Result<Self, Self::Err> {
+        match policy.split_once(':') {
+            None if policy == "drop" => Ok(Backpressure::Drop),
+            None if policy == "pause" => Ok(Backpressure::Pause),
+            Some(("queue", bound)) => bound.parse().map(Backpressure::Queue).map_err(|_| {
+                TauphiError::InvalidArgument(format!("invalid queue bound '{bound}'"))
+            }),
+            _ => Err(TauphiError::InvalidArgument(format!(
+                "unknown backpressure policy '{policy}'"
+            ))),
+        }
+    }
+}
+
+/// Memory for the ring buffers of a session, in KiB.
+///
+/// The kernel locks the buffers in memory, unprivileged users get
+/// `perf_event_mlock_kb` per CPU, see [BufferSize::per_sampler()].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BufferSize {
+    /// Shared by all samplers of the session.
+    Total(usize),
+    /// For each sampler, each samples one CPU in system-wide mode.
+    PerCpu(usize),
+}
+
+impl BufferSize {
+    /// Bytes of the buffer of each sampler, checked against the locked
+    /// memory limit of unprivileged users.
+    ///
+    /// # Arguments
+    /// * `samplers` - Number of samplers sharing the budget.
+    pub fn per_sampler(self, samplers: usize) -> Result<usize, TauphiError> {
+        let samplers = samplers.max(1);
+        let per_sampler_kb = match self {
+            BufferSize::Total(kb) => kb / samplers,
+            BufferSize::PerCpu(kb) => kb,
+        };
+        let privileged = unsafe { libc::geteuid() } == 0;
+        if let (false, Some(limit_kb)) = (privileged, mlock_limit_kb()) {
+            let allowed_kb = limit_kb * num_cpus();
+            if per_sampler_kb * samplers > allowed_kb {
+                return Err(TauphiError::InvalidArgument(format!(
+                    "{} KiB of buffers for {samplers} samplers exceed the {allowed_kb} KiB \
+                     allowed by perf_event_mlock_kb",
+                    per_sampler_kb * samplers
+                )));
+            }
+        }
+        Ok(per_sampler_kb << 10)
+    }
+}
+
+impl FromStr for BufferSize {
+    type Err = TauphiError;
+
+    /// Parse `<KIB>` of all samplers or `<KIB>/cpu` of each.
+    fn from_str(size: &str) -> Result<Self, Self::Err> {
+        let invalid = || TauphiError::InvalidArgument(format!("invalid buffer size '{size}'"));
+        let (kb, variant): (_, fn(usize) -> BufferSize) = match size.strip_suffix("/cpu") {
+            Some(kb) => (kb, BufferSize::PerCpu),
+            None => (size, BufferSize::Total),
+        };
+        match kb.parse() {
+            Ok(0) | Err(_) => Err(invalid()),
+            Ok(kb) => Ok(variant(kb)),
+        }
+    }
+}
+
+/// Locked memory for perf buffers of unprivileged users, per CPU.
+pub fn mlock_limit_kb() -> Option<usize> {
+    fs::read_to_string("/proc/sys/kernel/perf_event_mlock_kb")
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// What and how to sample, see [Sampler::with_options()].
+#[derive(Debug, Clone)]
+pub struct SamplerOptions {
+    /// Event triggering the samples.
+    pub event: Event,
+    /// How many samples per second to generate.
+    pub frequency: usize,
+    /// Sample every this many events instead of at [SamplerOptions::frequency].
+    pub period: Option<u64>,
+    /// Whether to sample also future children of the sampled process.
+    /// See [Sampler::new_pid_with_children()].
+    pub follow_children: bool,
+    /// Whether to not sample idle CPUs at all, see also
+    /// [crate::filter::SampleFilter::without_idle()].
+    pub exclude_idle: bool,
+    /// Whether to sample only code running in the kernel.
+    pub kernel_only: bool,
+    /// Whether to sample only code running in user space.
+    pub user_only: bool,
+    /// Requested `precise_ip`, 0 allows arbitrary skid of the sampled
+    /// instruction pointer, 3 requires none. Hardware without PEBS or IBS
+    /// grants less, see [Sampler::precise()].
+    pub precise: u8,
+    /// What happens when the consumer falls behind.
+    pub backpressure: Backpressure,
+    /// Bytes of the ring buffer, rounded down to a power of two pages.
+    /// If not given, it holds about 10 seconds of samples at the frequency.
+    pub buffer_size: Option<usize>,
+    /// Whether to report [Record::Fork], [Record::Exit] and [Record::Comm]
+    /// of all processes the sampler sees.
+    pub task_events: bool,
+    /// Whether to report [Record::Cgroup] of cgroups created while sampling.
+    pub cgroup_events: bool,
+    /// Where callchain buffers of samples come from.
+    pub pool: SamplePool,
+    /// Where to copy the raw records read from the kernel, for debugging.
+    pub dump: Option<RawDump>,
+}
+
+impl SamplerOptions {
+    /// Sample [Event::TaskClock] at the given frequency.
+    pub fn new(frequency: usize) -> SamplerOptions {
+        SamplerOptions {
+            event: Event::default(),
+            frequency,
+            period: None,
+            follow_children: false,
+            exclude_idle: false,
+            kernel_only: false,
+            user_only: false,
+            precise: 0,
+            backpressure: Backpressure::default(),
+            buffer_size: None,
+            task_events: false,
+            cgroup_events: false,
+            pool: SamplePool::default(),
+            dump: None,
+        }
+    }
+}
+
+/// Number of online CPUs.
+pub fn num_cpus() -> usize {
+    unsafe { libc::sysconf(libc::_SC_NPROCESSORS_ONLN) as usize }
+}
+
+/// Creation or termination of a process or a thread.
+///
+/// Decoded from perf_event FORK and EXIT records.
+#[derive(Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TaskEvent {
+    /// Process ID
+    pub pid: u32,
+    /// Parent process ID
+    pub ppid: u32,
+    /// Thread ID
+    pub tid: u32,
+    /// Parent thread ID
+    pub ptid: u32,
+    /// Timestamp of this event in nanoseconds, monotonic
+    pub time: u64,
+}
+
+impl TaskEvent {
+    /// Whether the event concerns a whole process rather than just a thread.
+    pub fn is_process(&self) -> bool {
+        self.pid == self.tid
+    }
+}
+
+/// New command name of a process or a thread, e.g. after `exec()`.
+///
+/// Decoded from perf_event COMM records.
+#[derive(Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CommEvent {
+    /// Process ID
+    pub pid: u32,
+    /// Thread ID
+    pub tid: u32,
+    /// Command name, truncated by the kernel to 15 bytes.
+    pub comm: String,
+}
+
+/// Decoded from perf_event CGROUP records.
+#[derive(Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CgroupEvent {
+    /// Cgroup ID, the inode of its directory.
+    pub id: u64,
+    /// Path of the cgroup relative to the cgroup v2 root.
+    pub path: String,
+}
+
+/// A record produced by a [Sampler].
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Record {
+    Sample(Sample),
+    /// A new process or thread was created.
+    Fork(TaskEvent),
+    /// A process or thread terminated.
+    Exit(TaskEvent),
+    /// A process or thread changed its command name.
+    Comm(CommEvent),
+    /// A cgroup was created, see [SamplerOptions::cgroup_events].
+    Cgroup(CgroupEvent),
+    /// Number of records dropped by the kernel because the buffer was full.
+    Lost(u64),
+    /// The kernel throttled the sampling because it took too much CPU time.
+    ///
+    /// Contains the timestamp in nanoseconds, monotonic.
+    Throttle(u64),
+    /// The throttled sampling resumed.
+    ///
+    /// Contains the timestamp in nanoseconds, monotonic.
+    Unthrottle(u64),
+    /// A record that could not be decoded, with the reason.
+    Malformed(String),
+}
+
+/// Asynchronous sampling of a single CPU or PID.
+///
+/// Offers synchronous API, for fully asynchronous variant, see [AsyncSampler].
+///
+/// The sampling starts on construction and ends when [drop()] is called.
+/// The samples are collected in an internal buffer and [Sampler::get_sample()]
+/// is used to retrieve them. If the internal buffers overflows,
+/// samples will be discarded.
+///
+/// # Examples
+/// ```no_run
+/// use tauphi_core::sampling::Sampler;
+/// let pid = 12; // PID of the process to sample.
+/// let mut sampler = Sampler::new_pid(pid,10).expect("Failed to start the sampling");
+/// // Samples are now being collected by the Linux kernel.
+/// // Use blocking iterator to access them.
+/// for sample in sampler.take(10) {
+///     println!("Sample: {:#?}", sample);
+/// }
+/// ```
+///
+/// Records come from perf events by default, other sources such as
+/// [MockBackend](crate::backend::MockBackend) are plugged in via
+/// [Sampler::with_backend()].
+pub struct Sampler<B: SamplerBackend = PerfBackend> {
+    backend: B,
+    started: Instant,
+    /// Records returned so far, for [Sampler::stats()].
+    samples: Cell<u64>,
+    lost: Cell<u64>,
+    throttles: Cell<u64>,
+    malformed: Cell<u64>,
+    backpressure: Backpressure,
+    /// Records moved out of the kernel buffer, see [Backpressure::Queue].
+    queue: RefCell<VecDeque<Record>>,
+    queue_peak: Cell<usize>,
+    /// Highest fill of the buffer seen before reading a record.
+    buffer_peak: Cell<f64>,
+    /// Since when the event is paused, see [Backpressure::Pause].
+    paused_since: Cell<Option<Instant>>,
+    pauses: Cell<u64>,
+    paused: Cell<Duration>,
+    /// Whether [Sampler::stop()] was called, the event must stay disabled.
+    stopped: Cell<bool>,
+    pool: SamplePool,
+    /// Event of the samples, see [Sample::event].
+    event: Event,
+    /// Granted `precise_ip`, see [SamplerOptions::precise].
+    precise: u8,
+}
+
+/// Statistics of a sampling, see [Sampler::finish()].
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SessionStats {
+    /// Samples delivered to the consumer.
+    pub samples: u64,
+    /// Samples lost because the buffer was full.
+    pub lost: u64,
+    /// How many times the kernel throttled the sampling.
+    pub throttles: u64,
+    /// Time since the start of the sampling.
+    pub duration: Duration,
+    /// How many times the event was paused, see [Backpressure::Pause].
+    pub pauses: u64,
+    /// Total time the event was paused.
+    pub paused: Duration,
+    /// Most records queued at once, see [Backpressure::Queue].
+    pub queue_peak: usize,
+    /// Records that could not be decoded, see [Record::Malformed].
+    pub malformed: u64,
+    /// Highest ratio of a buffer occupied by unread records, from 0 to 1.
+    /// Close to 1 means the buffer or the reading is too small for the
+    /// frequency.
+    pub buffer_peak: f64,
+}
+
+impl SessionStats {
+    /// Delivered samples per second.
+    pub fn rate(&self) -> f64 {
+        if self.duration.is_zero() {
+            0.0
+        } else {
+            self.samples as f64 / self.duration.as_secs_f64()
+        }
+    }
+
+    /// Add statistics of a sampler running in parallel.
+    pub fn merge(&mut self, other: &SessionStats) {
+        self.samples += other.samples;
+        self.lost += other.lost;
+        self.throttles += other.throttles;
+        self.duration = self.duration.max(other.duration);
+        self.pauses += other.pauses;
+        self.paused += other.paused;
+        self.queue_peak = self.queue_peak.max(other.queue_peak);
+        self.malformed += other.malformed;
+        self.buffer_peak = self.buffer_peak.max(other.buffer_peak);
+    }
+}
+
+impl Sampler {
+    /// Start a new sampler for the required CPU at given frequency.
+    ///
+    /// # Arguments
+    /// * `cpu` CPU to periodically sample, indexed from 0 to number of CPUs.
+    /// * `frequency` how many samples per second to generate.
+    pub fn new_cpu(cpu: i32, frequency: usize) -> Result<Sampler, TauphiError> {
+        Self::with_options(cpu, -1, &SamplerOptions::new(frequency))
+    }
+
+    /// Start a new sampler of the given process at the given frequency.
+    ///
+    /// Do note this does not perform off-cpu sampling, if the process is
+    /// not running (for any reason), the samples are not collected.
+    ///
+    /// # Arguments
+    /// * `pid` Process with ID to periodically sample.
+    /// * `frequency` how many samples per second to generate.
+    pub fn new_pid(pid: i32, frequency: usize) -> Result<Sampler, TauphiError> {
+        Self::with_options(-1, pid, &SamplerOptions::new(frequency))
+    }
+
+    /// Start a new sampler of the given process and all its future children.
+    ///
+    /// Samples of the children are delivered by this sampler too, their
+    /// lifetime is reported via [Record::Fork] and [Record::Exit] records,
+    /// see [Sampler::get_record()].
+    ///
+    /// perf_event does not allow buffers of inherited events for all CPUs,
+    /// one sampler per CPU is needed, see [num_cpus()].
+    ///
+    /// # Arguments
+    /// * `pid` Process with ID to periodically sample.
+    /// * `cpu` CPU on which to sample the process and its children.
+    /// * `frequency` how many samples per second to generate.
+    pub fn new_pid_with_children(
+        pid: i32,
+        cpu: i32,
+        frequency: usize,
+    ) -> Result<Sampler, TauphiError> {
+        let options = SamplerOptions {
+            follow_children: true,
+            ..SamplerOptions::new(frequency)
+        };
+        Self::with_options(cpu, pid, &options)
+    }
+
+    /// Start a new sampler of a CPU, a process or a process on a CPU.
+    ///
+    /// # Arguments
+    /// * `cpu` CPU to periodically sample, -1 for all CPUs.
+    /// * `pid` Process with ID to periodically sample, -1 for all processes.
+    /// * `options` What and how to sample.
+    ///
+    /// Either `cpu` or `pid` must not be -1.
+    pub fn with_options(
+        cpu: i32,
+        pid: i32,
+        options: &SamplerOptions,
+    ) -> Result<Sampler, TauphiError> {
+        Self::open(cpu, pid, None, options)
+    }
+
+    /// Start a new sampler of processes in a cgroup running on a CPU.
+    ///
+    /// perf_event requires a specific CPU for cgroups, one sampler per CPU
+    /// is needed to cover all of them, see [num_cpus()].
+    ///
+    /// # Arguments
+    /// * `cgroup` Directory of the cgroup (v2) in the cgroup filesystem.
+    /// * `cpu` CPU to periodically sample.
+    /// * `options` What and how to sample.
+    pub fn new_cgroup(
+        cgroup: &Path,
+        cpu: i32,
+        options: &SamplerOptions,
+    ) -> Result<Sampler, TauphiError> {
+        Self::open(cpu, -1, Some(cgroup), options)
+    }
+
+    /// Start a new sampler, of the cgroup instead of `pid` if given.
+    fn open(
+        cpu: i32,
+        pid: i32,
+        cgroup: Option<&Path>,
+        options: &SamplerOptions,
+    ) -> Result<Sampler, TauphiError> {
+        // The descriptor is needed only for opening the event.
+        let dir = match cgroup {
+            Some(path) => Some(File::open(path).map_err(TauphiError::file(path))?),
+            None => None,
+        };
+        let pid = dir.as_ref().map_or(pid, |dir| dir.as_raw_fd());
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGE_SIZE) as usize };
+        let frequency = options.frequency;
+
+        let sample_size = core::mem::size_of::<Sample>();
+        // perf_event requires the size to be a power of two.
+        let num_pages = match options.buffer_size {
+            // Stay within the budget, but use at least a page.
+            Some(bytes) => match bytes / page_size {
+                0 => 1,
+                pages => 1 << pages.ilog2(),
+            },
+            // Store at least X seconds of events.
+            // That also handles the case of 0->1 pages due to integer division.
+            None => {
+                (Self::BUFFER_SIZE_SECS * frequency * sample_size / page_size).next_power_of_two()
+            }
+        };
+        // Target poll every 100ms
+        let poll_freq: usize = 1.max(frequency / (1000 / Self::POLL_FREQUENCY_MS));
+        assert!(num_pages > 0);
+        let (event_type, event_config) = options.event.type_and_config();
+        let mut config = pe::SamplerConfig {
+            event_type,
+            event_config,
+            frequency,
+            poll_freq,
+            num_pages,
+            callchain_depth_limit: CALLCHAIN_DEPTH,
+            follow_children: options.follow_children,
+            cgroup: cgroup.is_some(),
+            exclude_idle: options.exclude_idle,
+            exclude_user: options.kernel_only,
+            exclude_kernel: options.user_only,
+            // Every output of the BPF program is a sample.
+            sample_period: match options.event {
+                Event::BpfOutput => options.period.unwrap_or(1),
+                _ => options.period.unwrap_or(0),
+            },
+            sample_raw: options.event == Event::BpfOutput,
+            task_events: options.task_events,
+            precise_ip: options.precise,
+            cgroup_events: options.cgroup_events,
+        };
+        // Like perf, lower the precise level until the hardware accepts it.
+        let mut opened = pe::PerfEventHandle::new(cpu, pid, &config);
+        while opened.is_err() && config.precise_ip > 0 {
+            config.precise_ip -= 1;
+            opened = pe::PerfEventHandle::new(cpu, pid, &config);
+        }
+        let handle = opened.map_err(|source| TauphiError::Sampler {
+            target: match (cgroup, pid, cpu) {
+                (Some(path), _, _) => format!("cgroup {} on CPU {cpu}", path.display()),
+                (None, -1, _) => format!("CPU {cpu}"),
+                (None, _, -1) => format!("PID {pid}"),
+                (None, _, _) => format!("PID {pid} on CPU {cpu}"),
+            },
+            source,
+        })?;
+        handle.start(true)?;
+        tracing::debug!(
+            cpu = cpu,
+            pid = pid,
+            event = options.event.name(),
+            frequency = frequency,
+            pages = num_pages,
+            precise = config.precise_ip,
+            "opened the perf event"
+        );
+        let backend = PerfBackend {
+            handle,
+            dump: options.dump.clone(),
+        };
+        let mut sampler = Sampler::with_backend(backend, options);
+        sampler.precise = config.precise_ip;
+        Ok(sampler)
+    }
+
+    /// How often is POLLIN triggered on the sampler.
+    const POLL_FREQUENCY_MS: usize = 100;
+    /// Without [SamplerOptions::buffer_size], store at least X seconds of
+    /// pending samples in the internal perf buffer.
+    const BUFFER_SIZE_SECS: usize = 10;
+}
+
+impl<B: SamplerBackend> Sampler<B> {
+    /// Read records of an already started backend.
+    ///
+    /// Only [SamplerOptions::backpressure] and [SamplerOptions::pool] apply,
+    /// the rest configures the backend itself.
+    pub fn with_backend(backend: B, options: &SamplerOptions) -> Sampler<B> {
+        Sampler {
+            backend,
+            started: Instant::now(),
+            samples: Cell::new(0),
+            lost: Cell::new(0),
+            throttles: Cell::new(0),
+            malformed: Cell::new(0),
+            backpressure: options.backpressure,
+            queue: RefCell::new(VecDeque::new()),
+            queue_peak: Cell::new(0),
+            buffer_peak: Cell::new(0.0),
+            paused_since: Cell::new(None),
+            pauses: Cell::new(0),
+            paused: Cell::new(Duration::ZERO),
+            stopped: Cell::new(false),
+            pool: options.pool.clone(),
+            event: options.event,
+            precise: options.precise,
+        }
+    }
+
+    /// Return the next sample if there is one available.
+    ///
+    /// Other records preceding the sample are discarded.
+    pub fn get_sample(&self) -> Option<Sample> {
+        loop {
+            if let Record::Sample(sample) = self.get_record()? {
+                return Some(sample);
+            }
+        }
+    }
+
+    /// Return the next sample if there is one available, recycled into
+    /// [SamplerOptions::pool] when dropped.
+    pub fn get_pooled_sample(&self) -> Option<PooledSample> {
+        Some(self.pool.guard(self.get_sample()?))
+    }
+
+    /// Return the next sample if there is one available, never blocks.
+    ///
+    /// Non-blocking counterpart of [Iterator::next()], same as
+    /// [Sampler::get_sample()].
+    pub fn try_next(&self) -> Option<Sample> {
+        self.get_sample()
+    }
+
+    /// Return the next sample, waiting for it at most `timeout`.
+    ///
+    /// Sleeps while waiting, unlike [Iterator::next()]. Returns `None` on
+    /// timeout or once the sampled process exited and all its samples
+    /// were returned.
+    pub fn next_timeout(&self, timeout: Duration) -> Option<Sample> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(sample) = self.get_sample() {
+                return Some(sample);
+            }
+            if self.has_exited() {
+                // Samples written before the exit might have just arrived.
+                return self.get_sample();
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return None;
+            }
+            // The signal comes only every `poll_freq` samples, the deadline
+            // might come first.
+            self.wait(remaining);
+        }
+    }
+
+    /// Sleep until new records are signalled, the sampled process exits or
+    /// the timeout passes.
+    ///
+    /// Returns false on timeout. Records are signalled in batches, some
+    /// might be available even on timeout.
+    pub fn wait(&self, timeout: Duration) -> bool {
+        self.backend.wait(timeout)
+    }
+
+    /// Return the next record if there is one available.
+    pub fn get_record(&self) -> Option<Record> {
+        let mut record = match self.backpressure {
+            Backpressure::Drop => self.read_next_record(),
+            Backpressure::Pause => {
+                self.regulate();
+                self.read_next_record()
+            }
+            Backpressure::Queue(bound) => {
+                let mut queue = self.queue.borrow_mut();
+                // Free the kernel buffer, what does not fit stays there.
+                while queue.len() < bound {
+                    match self.read_next_record() {
+                        Some(record) => queue.push_back(record),
+                        None => break,
+                    }
+                }
+                self.queue_peak.set(self.queue_peak.get().max(queue.len()));
+                queue.pop_front()
+            }
+        }?;
+        match &mut record {
+            Record::Sample(sample) => {
+                sample.event = self.event;
+                self.samples.set(self.samples.get() + 1)
+            }
+            Record::Lost(lost) => {
+                tracing::warn!(lost = *lost, "the kernel dropped samples");
+                self.lost.set(self.lost.get() + *lost)
+            }
+            Record::Throttle(_) => {
+                tracing::info!("the kernel throttled the sampling");
+                self.throttles.set(self.throttles.get() + 1)
+            }
+            Record::Malformed(reason) => {
+                tracing::warn!("skipping a malformed record: {}", reason);
+                self.malformed.set(self.malformed.get() + 1)
+            }
+            _ => (),
+        }
+        Some(record)
+    }
+
+    /// Statistics of the records returned so far.
+    pub fn stats(&self) -> SessionStats {
+        let pausing = self
+            .paused_since
+            .get()
+            .map_or(Duration::ZERO, |since| since.elapsed());
+        SessionStats {
+            samples: self.samples.get(),
+            lost: self.lost.get(),
+            throttles: self.throttles.get(),
+            duration: self.started.elapsed(),
+            pauses: self.pauses.get(),
+            paused: self.paused.get() + pausing,
+            queue_peak: self.queue_peak.get(),
+            malformed: self.malformed.get(),
+            buffer_peak: self.buffer_peak.get(),
+        }
+    }
+
+    /// Pause the event while the buffer is mostly full, see
+    /// [Backpressure::Pause].
+    fn regulate(&self) {
+        let fill = self.backend.buffer_usage().fill();
+        match self.paused_since.get() {
+            // A failed pause or resume is retried on the next call.
+            None if fill > Self::PAUSE_ABOVE_FILL
+                && !self.stopped.get()
+                && self.backend.stop().is_ok() =>
+            {
+                tracing::debug!(fill = fill, "pausing the sampling");
+                self.paused_since.set(Some(Instant::now()));
+                self.pauses.set(self.pauses.get() + 1);
+            }
+            Some(since)
+                if fill < Self::RESUME_BELOW_FILL
+                    && (self.stopped.get() || self.backend.start().is_ok()) =>
+            {
+                tracing::debug!(fill = fill, "resuming the sampling");
+                self.paused_since.set(None);
+                self.paused.set(self.paused.get() + since.elapsed());
+            }
+            _ => (),
+        }
+    }
+
+    /// Stop the sampling and return the remaining samples with statistics.
+    ///
+    /// Unlike dropping the sampler, samples collected but not yet read are
+    /// not discarded.
+    pub fn finish(self) -> Result<(Vec<Sample>, SessionStats), TauphiError> {
+        self.stop()?;
+        let duration = self.started.elapsed();
+        let remaining = iter::from_fn(|| self.get_sample()).collect();
+        Ok((
+            remaining,
+            SessionStats {
+                duration,
+                ..self.stats()
+            },
+        ))
+    }
+
+    /// Read the next record from the backend, if there is one.
+    fn read_next_record(&self) -> Option<Record> {
+        let fill = self.backend.buffer_usage().fill();
+        self.buffer_peak.set(self.buffer_peak.get().max(fill));
+        self.backend.read_record(&self.pool)
+    }
+
+    /// Current occupancy of the buffer, e.g. for sizing it.
+    ///
+    /// The highest fill so far is in [SessionStats::buffer_peak].
+    pub fn buffer_usage(&self) -> BufferUsage {
+        self.backend.buffer_usage()
+    }
+
+    /// Event of the samples.
+    pub fn event(&self) -> Event {
+        self.event
+    }
+
+    /// Granted `precise_ip`, lower than [SamplerOptions::precise] if the
+    /// hardware does not support it, samples then skid past the
+    /// instruction which caused the event.
+    pub fn precise(&self) -> u8 {
+        self.precise
+    }
+
+    /// Stop collecting new samples.
+    ///
+    /// Already collected records can still be read.
+    pub fn stop(&self) -> Result<(), TauphiError> {
+        self.stopped.set(true);
+        self.backend.stop()
+    }
+
+    /// Change the number of samples per second to generate.
+    pub fn set_frequency(&self, frequency: usize) -> Result<(), TauphiError> {
+        self.backend.set_frequency(frequency)
+    }
+
+    /// Whether the sampled process exited.
+    ///
+    /// The remaining records can still be read, but no new ones will come.
+    pub fn has_exited(&self) -> bool {
+        self.backend.has_exited()
+    }
+
+    /// Buffer fill ratio at which [Backpressure::Pause] disables the event.
+    const PAUSE_ABOVE_FILL: f64 = 0.75;
+    /// Buffer fill ratio at which [Backpressure::Pause] enables it again.
+    const RESUME_BELOW_FILL: f64 = 0.25;
+}
+
+/// Expose the raw perf_event file descriptor.
+impl AsRawFd for Sampler {
+    fn as_raw_fd(&self) -> std::os::fd::RawFd {
+        self.backend.handle.as_raw_fd()
+    }
+}
+
+/// Records of the kernel's ring buffer, the default [SamplerBackend].
+pub struct PerfBackend {
+    handle: pe::PerfEventHandle,
+    dump: Option<RawDump>,
+}
+
+impl SamplerBackend for PerfBackend {
+    fn read_record(&self, pool: &SamplePool) -> Option<Record> {
+        loop {
+            let (record_type, size) = self.handle.get_event(&mut [], true)?;
+            if let pe::RecordType::Other(_) | pe::RecordType::Mmap | pe::RecordType::Read =
+                record_type
+            {
+                // Skip records we do not care about.
+                self.handle.get_event(&mut [], false)?;
+                continue;
+            }
+            let mut payload = [0_u8; MAX_RECORD_SIZE];
+            self.handle.get_event(&mut payload, false)?;
+            if let Some(dump) = &self.dump {
+                dump.write(record_type.into(), &payload[..size.min(payload.len())]);
+            }
+            let record = if size > payload.len() {
+                Record::Malformed(format!("{record_type:?} record of {size} bytes"))
+            } else {
+                match parse_record(record_type, &payload[..size], pool) {
+                    Ok(record) => record?,
+                    Err(err) => Record::Malformed(err.to_string()),
+                }
+            };
+            return Some(record);
+        }
+    }
+
+    fn start(&self) -> Result<(), TauphiError> {
+        Ok(self.handle.start(false)?)
+    }
+
+    fn stop(&self) -> Result<(), TauphiError> {
+        Ok(self.handle.stop()?)
+    }
+
+    fn set_frequency(&self, frequency: usize) -> Result<(), TauphiError> {
+        Ok(self.handle.set_frequency(frequency)?)
+    }
+
+    fn buffer_usage(&self) -> BufferUsage {
+        BufferUsage {
+            pending: self.handle.buffer_used(),
+            size: self.handle.buffer_size(),
+        }
+    }
+
+    fn has_exited(&self) -> bool {
+        self.handle.has_exited()
+    }
+
+    fn wait(&self, timeout: Duration) -> bool {
+        let timeout_ms = timeout.as_millis().clamp(1, i32::MAX as u128);
+        self.handle.wait(timeout_ms as i32)
+    }
+}
+
+/// Infinite iterator over the gathered samples.
+///
+/// [Sampler::next()] blocks if necessary to wait for the next sample.
+impl<B: SamplerBackend> Iterator for Sampler<B> {
+    type Item = Sample;
+
+    /// Returns the next sample.
+    ///
+    /// Contains a busy loop, blocking until the sample is available.
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.get_sample() {
+                None => (),
+                x => return x,
+            }
+            thread::yield_now();
+        }
+    }
+}
+
+/// Sampler with asynchronous API.
+///
+/// See [Sampler] for synchronous, iterator-based variant.
+///
+/// # Examples
+/// ```no_run
+/// async fn async_main() {
+///     use tauphi_core::sampling::{Sampler,AsyncSampler};
+///     let sampler = Sampler::new_cpu(0, 5).expect("Failed to start the sampling.");
+///     let sampler = AsyncSampler::from_sync(sampler).unwrap();
+///     for i in 1..10 {
+///         let sample = sampler.get_sample().await.unwrap().unwrap();
+///         println!("#{i} {:#?}", sample);
+///     }
+///     drop(sampler); // Stop collecting the samples.
+/// }
+/// ```
+#[cfg(feature = "async-tokio")]
+pub struct AsyncSampler {
+    poll_fd: AsyncFd<Sampler>,
+}
+
+#[cfg(feature = "async-tokio")]
+impl AsyncSampler {
+    /// Construct an asynchronous version of the Sampler.
+    pub fn from_sync(sampler: Sampler) -> Result<AsyncSampler, PerfError> {
+        Ok(AsyncSampler {
+            poll_fd: AsyncFd::new(sampler)?,
+        })
+    }
+
+    /// Stop collecting new samples, see [Sampler::stop()].
+    pub fn stop(&self) -> Result<(), TauphiError> {
+        self.poll_fd.get_ref().stop()
+    }
+
+    /// Change the sampling frequency, see [Sampler::set_frequency()].
+    pub fn set_frequency(&self, frequency: usize) -> Result<(), TauphiError> {
+        self.poll_fd.get_ref().set_frequency(frequency)
+    }
+
+    /// Statistics of the records returned so far, see [Sampler::stats()].
+    pub fn stats(&self) -> SessionStats {
+        self.poll_fd.get_ref().stats()
+    }
+
+    /// Return the next sample.
+    ///
+    /// Other records preceding the sample are discarded.
+    /// Returns `None` once the sampled process exited and all its samples
+    /// were returned.
+    pub async fn get_sample(&self) -> Result<Option<Sample>, PerfError> {
+        while let Some(record) = self.get_record().await? {
+            if let Record::Sample(sample) = record {
+                return Ok(Some(sample));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Return the next record.
+    ///
+    /// Returns `None` once the sampled process exited and all its records
+    /// were returned.
+    pub async fn get_record(&self) -> Result<Option<Record>, PerfError> {
+        loop {
+            // Try to get the record from the ring buffer, non-blocking.
+            if let Some(record) = self.poll_fd.get_ref().get_record() {
+                return Ok(Some(record));
+            }
+            if self.poll_fd.get_ref().has_exited() {
+                // Records written before the exit might have just arrived.
+                return Ok(self.poll_fd.get_ref().get_record());
+            }
+
+            let mut guard = self.poll_fd.readable().await?;
+            // Clear the POLLIN flag immedietely. There is no actual read to do,
+            // perf_event only signals POLLIN once each time the wakeup counter
+            // overflows.
+            guard.clear_ready();
+        }
+    }
+}
+
+/// Merged asynchronous sampling of several CPUs or PIDs.
+///
+/// Samples are taken from the underlying samplers in a round-robin fashion,
+/// they are therefore not ordered by their timestamp across samplers.
+///
+/// # Examples
+/// ```no_run
+/// async fn async_main() {
+///     use tauphi_core::sampling::{Sampler,AsyncSampler,MultiSampler};
+///     let samplers = [12, 13]
+///         .into_iter()
+///         .map(|pid| Sampler::new_pid(pid, 5).expect("Failed to start the sampling."))
+///         .map(|sampler| AsyncSampler::from_sync(sampler).unwrap())
+///         .collect();
+///     let mut sampler = MultiSampler::new(samplers);
+///     while let Some(sample) = sampler.get_sample().await.unwrap() {
+///         println!("{:#?}", sample);
+///     }
+/// }
+/// ```
+#[cfg(feature = "async-tokio")]
+pub struct MultiSampler {
+    samplers: Vec<AsyncSampler>,
+    /// Sampler to query first on the next call, ensures fairness.
+    next: usize,
+    /// Statistics of already dropped samplers.
+    retired: SessionStats,
+}
+
+#[cfg(feature = "async-tokio")]
+impl MultiSampler {
+    /// Merge the given samplers into one stream of samples.
+    ///
+    /// [MultiSampler::get_sample()] returns `None` immediately if `samplers`
+    /// is empty.
+    pub fn new(samplers: Vec<AsyncSampler>) -> MultiSampler {
+        MultiSampler {
+            samplers,
+            next: 0,
+            retired: SessionStats::default(),
+        }
+    }
+
+    /// Stop collecting new samples by all samplers, see [Sampler::stop()].
+    pub fn stop(&self) -> Result<(), TauphiError> {
+        self.samplers.iter().try_for_each(AsyncSampler::stop)
+    }
+
+    /// Change the sampling frequency of all samplers,
+    /// see [Sampler::set_frequency()].
+    pub fn set_frequency(&self, frequency: usize) -> Result<(), TauphiError> {
+        self.samplers
+            .iter()
+            .try_for_each(|sampler| sampler.set_frequency(frequency))
+    }
+
+    /// Combined statistics of all samplers, see [Sampler::stats()].
+    pub fn stats(&self) -> SessionStats {
+        let mut stats = self.retired;
+        for sampler in &self.samplers {
+            stats.merge(&sampler.stats());
+        }
+        stats
+    }
+
+    /// Return the next already collected record from any of the samplers.
+    ///
+    /// Does not wait for new records, intended for draining the samplers
+    /// after [MultiSampler::stop()].
+    pub fn try_get_record(&mut self) -> Option<Record> {
+        for _ in 0..self.samplers.len() {
+            self.next %= self.samplers.len();
+            let sampler = self.samplers[self.next].poll_fd.get_ref();
+            self.next += 1;
+            if let Some(record) = sampler.get_record() {
+                return Some(record);
+            }
+        }
+        None
+    }
+
+    /// Return the next sample from any of the samplers.
+    ///
+    /// Other records preceding the sample are discarded.
+    /// Returns `None` once all sampled processes exited.
+    pub async fn get_sample(&mut self) -> Result<Option<Sample>, PerfError> {
+        while let Some(record) = self.get_record().await? {
+            if let Record::Sample(sample) = record {
+                return Ok(Some(sample));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Return the next record from any of the samplers.
+    ///
+    /// Samplers of exited processes are dropped once drained.
+    /// Returns `None` once all sampled processes exited.
+    pub async fn get_record(&mut self) -> Result<Option<Record>, PerfError> {
+        future::poll_fn(|cx| {
+            let mut remaining = self.samplers.len();
+            'samplers: while remaining > 0 {
+                remaining -= 1;
+                self.next %= self.samplers.len();
+                let poll_fd = &self.samplers[self.next].poll_fd;
+                loop {
+                    if let Some(record) = poll_fd.get_ref().get_record() {
+                        self.next += 1;
+                        return Poll::Ready(Ok(Some(record)));
+                    }
+                    if poll_fd.get_ref().has_exited() {
+                        break;
+                    }
+                    match poll_fd.poll_read_ready(cx) {
+                        // See AsyncSampler::get_record() for why it is cleared.
+                        Poll::Ready(Ok(mut guard)) => guard.clear_ready(),
+                        Poll::Ready(Err(err)) => return Poll::Ready(Err(err.into())),
+                        // Waker is registered, try the next sampler.
+                        Poll::Pending => {
+                            self.next += 1;
+                            continue 'samplers;
+                        }
+                    }
+                }
+                // Records written before the exit might have just arrived.
+                if let Some(record) = poll_fd.get_ref().get_record() {
+                    return Poll::Ready(Ok(Some(record)));
+                }
+                // The next sampler moves into its place.
+                let sampler = self.samplers.remove(self.next);
+                self.retired.merge(&sampler.stats());
+            }
+            if self.samplers.is_empty() {
+                Poll::Ready(Ok(None))
+            } else {
+                Poll::Pending
+            }
+        })
+        .await
+    }
+}
+
+#[test]
+fn session_stats_test() {
+    let mut stats = SessionStats {
+        samples: 10,
+        lost: 1,
+        throttles: 0,
+        duration: Duration::from_secs(2),
+        ..Default::default()
+    };
+    assert_eq!(stats.rate(), 5.0);
+    stats.merge(&SessionStats {
+        samples: 5,
+        lost: 2,
+        throttles: 1,
+        duration: Duration::from_secs(1),
+        buffer_peak: 0.5,
+        ..Default::default()
+    });
+    assert_eq!((stats.samples, stats.lost, stats.throttles), (15, 3, 1));
+    assert_eq!(stats.rate(), 7.5);
+    assert_eq!(stats.buffer_peak, 0.5);
+}
+
+#[test]
+fn parse_buffer_size_test() {
+    assert_eq!(
+        "4096".parse::<BufferSize>().unwrap(),
+        BufferSize::Total(4096)
+    );
+    assert_eq!(
+        "512/cpu".parse::<BufferSize>().unwrap(),
+        BufferSize::PerCpu(512)
+    );
+    assert!("0".parse::<BufferSize>().is_err());
+    assert!("1M".parse::<BufferSize>().is_err());
+    assert_eq!(BufferSize::Total(64).per_sampler(4).unwrap(), 16 << 10);
+}
+
+#[test]
+fn parse_backpressure_test() {
+    assert_eq!(
+        "pause".parse::<Backpressure>().unwrap(),
+        Backpressure::Pause
+    );
+    assert_eq!(
+        "queue:100".parse::<Backpressure>().unwrap(),
+        Backpressure::Queue(100)
+    );
+    assert!("queue".parse::<Backpressure>().is_err());
+    assert!("queue:x".parse::<Backpressure>().is_err());
+}
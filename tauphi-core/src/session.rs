@@ -0,0 +1,235 @@
+//! Profiling sessions collecting samples in the background.
+use std::panic;
+
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+use tracing::Instrument;
+
+use crate::clock::WallClock;
+use crate::error::TauphiError;
+use crate::metadata::RecordingMetadata;
+use crate::sampling::{
+    num_cpus, AsyncSampler, Event, MultiSampler, Record, Sample, Sampler, SamplerOptions,
+};
+use crate::sink::SampleSink;
+
+/// Samples collected by a [ProfileSession].
+#[derive(Debug, Default, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Profile {
+    /// Collected samples, ordered by time only within each sampled CPU
+    /// or process.
+    pub samples: Vec<Sample>,
+    /// Number of samples lost due to full buffers.
+    pub lost: u64,
+    /// Anchor of sample timestamps, see [Sample::wall_time()].
+    pub clock: WallClock,
+    /// Description of the sampled machine and configuration.
+    pub metadata: RecordingMetadata,
+}
+
+impl Profile {
+    /// Account the record to the profile.
+    fn add(&mut self, record: Record) {
+        match record {
+            Record::Sample(sample) => self.samples.push(sample),
+            Record::Lost(lost) => self.lost += lost,
+            _ => (),
+        }
+    }
+}
+
+/// Keeps the samples in memory.
+impl SampleSink for Profile {
+    fn consume(&mut self, sample: &Sample) -> Result<(), TauphiError> {
+        self.samples.push(sample.clone());
+        Ok(())
+    }
+}
+
+/// Configuration of a [ProfileSession], see [ProfileSession::builder()].
+#[derive(Debug, Clone)]
+pub struct ProfileSessionBuilder {
+    pids: Vec<i32>,
+    cpus: Vec<i32>,
+    options: SamplerOptions,
+}
+
+impl ProfileSessionBuilder {
+    /// Sample the process, can be called repeatedly.
+    pub fn pid(mut self, pid: i32) -> Self {
+        self.pids.push(pid);
+        self
+    }
+
+    /// Sample only on the CPU, can be called repeatedly.
+    ///
+    /// Without any processes, everything running on the CPU is sampled.
+    pub fn cpu(mut self, cpu: i32) -> Self {
+        self.cpus.push(cpu);
+        self
+    }
+
+    /// Number of samples per second to generate.
+    pub fn frequency(mut self, frequency: usize) -> Self {
+        self.options.frequency = frequency;
+        self
+    }
+
+    /// Event triggering the samples.
+    pub fn event(mut self, event: Event) -> Self {
+        self.options.event = event;
+        self
+    }
+
+    /// Whether to sample also children of the processes.
+    pub fn follow_children(mut self, follow_children: bool) -> Self {
+        self.options.follow_children = follow_children;
+        self
+    }
+
+    /// Start the sampling in the background.
+    ///
+    /// Must be called within a tokio runtime.
+    pub fn start(self) -> Result<ProfileSession, TauphiError> {
+        let all_cpus = || (0..num_cpus() as i32).collect();
+        let cpus: Vec<i32> = if !self.cpus.is_empty() {
+            self.cpus
+        } else if self.pids.is_empty() || self.options.follow_children {
+            // Everything and inherited events can be sampled only per CPU.
+            all_cpus()
+        } else {
+            vec![-1]
+        };
+        let metadata = RecordingMetadata::capture(&self.options, &self.pids);
+        let pids = if self.pids.is_empty() {
+            vec![-1]
+        } else {
+            self.pids
+        };
+        let mut samplers = Vec::new();
+        for &pid in &pids {
+            for &cpu in &cpus {
+                let sampler = Sampler::with_options(cpu, pid, &self.options)?;
+                samplers.push(AsyncSampler::from_sync(sampler)?);
+            }
+        }
+
+        let span = tracing::info_span!(
+            "session",
+            samplers = samplers.len(),
+            frequency = self.options.frequency
+        );
+        let (stop, stopped) = oneshot::channel();
+        let (snapshots, snapshot_requests) = mpsc::channel(1);
+        let task = tokio::spawn(
+            collect(
+                MultiSampler::new(samplers),
+                metadata,
+                stopped,
+                snapshot_requests,
+            )
+            .instrument(span),
+        );
+        Ok(ProfileSession {
+            stop,
+            snapshots,
+            task,
+        })
+    }
+}
+
+/// Running sampling of processes or CPUs collecting a [Profile].
+///
+/// # Examples
+/// ```no_run
+/// async fn async_main() {
+///     use tauphi_core::session::ProfileSession;
+///     let session = ProfileSession::builder()
+///         .pid(12)
+///         .frequency(997)
+///         .start()
+///         .expect("Failed to start the sampling.");
+///     tokio::time::sleep(std::time::Duration::from_secs(10)).await;
+///     let profile = session.stop().await.unwrap();
+///     println!("Collected {} samples.", profile.samples.len());
+/// }
+/// ```
+pub struct ProfileSession {
+    stop: oneshot::Sender<()>,
+    /// Requests for a copy of the profile collected so far.
+    snapshots: mpsc::Sender<oneshot::Sender<Profile>>,
+    task: JoinHandle<Result<Profile, TauphiError>>,
+}
+
+impl ProfileSession {
+    /// Configure a new session, samples all CPUs at 5 Hz by default.
+    pub fn builder() -> ProfileSessionBuilder {
+        ProfileSessionBuilder {
+            pids: Vec::new(),
+            cpus: Vec::new(),
+            options: SamplerOptions::new(5),
+        }
+    }
+
+    /// Whether the sampling ended because all sampled processes exited.
+    pub fn is_finished(&self) -> bool {
+        self.task.is_finished()
+    }
+
+    /// Return a copy of the profile collected so far, sampling continues.
+    ///
+    /// Returns `None` once the session finished, [ProfileSession::stop()]
+    /// then returns the whole profile.
+    pub async fn snapshot(&self) -> Option<Profile> {
+        let (reply, snapshot) = oneshot::channel();
+        self.snapshots.send(reply).await.ok()?;
+        snapshot.await.ok()
+    }
+
+    /// Stop the sampling and return everything collected.
+    pub async fn stop(self) -> Result<Profile, TauphiError> {
+        // The task might have finished already, that is fine.
+        let _ = self.stop.send(());
+        self.task
+            .await
+            .unwrap_or_else(|err| panic::resume_unwind(err.into_panic()))
+    }
+}
+
+/// Collect records into a profile until stopped or the processes exit.
+async fn collect(
+    mut sampler: MultiSampler,
+    metadata: RecordingMetadata,
+    mut stopped: oneshot::Receiver<()>,
+    mut snapshots: mpsc::Receiver<oneshot::Sender<Profile>>,
+) -> Result<Profile, TauphiError> {
+    let mut profile = Profile {
+        clock: WallClock::capture(),
+        metadata,
+        ..Profile::default()
+    };
+    loop {
+        tokio::select! {
+            record = sampler.get_record() => match record? {
+                Some(record) => profile.add(record),
+                None => {
+                    tracing::info!("all sampled processes exited");
+                    return Ok(profile);
+                }
+            },
+            Some(reply) = snapshots.recv() => {
+                // The requester might have given up waiting.
+                let _ = reply.send(profile.clone());
+            }
+            _ = &mut stopped => break,
+        }
+    }
+    tracing::debug!("stopping the session");
+    // Keep what is already buffered.
+    sampler.stop()?;
+    while let Some(record) = sampler.try_get_record() {
+        profile.add(record);
+    }
+    Ok(profile)
+}
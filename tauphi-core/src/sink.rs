@@ -0,0 +1,89 @@
+//! Consumers of collected samples.
+use std::cell::Cell;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+use crate::error::TauphiError;
+use crate::sampling::Sample;
+
+/// Destination of collected samples, e.g. a file or an in-memory aggregator.
+///
+/// Every sample is passed to [SampleSink::consume()], [SampleSink::finish()]
+/// is called once after the last one.
+pub trait SampleSink {
+    /// Process one sample.
+    fn consume(&mut self, sample: &Sample) -> Result<(), TauphiError>;
+
+    /// Persist buffered samples, if any.
+    fn flush(&mut self) -> Result<(), TauphiError> {
+        Ok(())
+    }
+
+    /// No more samples will follow.
+    fn finish(&mut self) -> Result<(), TauphiError> {
+        self.flush()
+    }
+}
+
+/// Writes numbered samples in their debug representation.
+pub struct DebugSink<W: Write> {
+    writer: W,
+    /// Number of samples written so far.
+    count: usize,
+}
+
+impl<W: Write> DebugSink<W> {
+    /// Write the samples to the writer.
+    pub fn new(writer: W) -> DebugSink<W> {
+        DebugSink { writer, count: 0 }
+    }
+}
+
+impl<W: Write> SampleSink for DebugSink<W> {
+    fn consume(&mut self, sample: &Sample) -> Result<(), TauphiError> {
+        self.count += 1;
+        writeln!(self.writer, "#{} {:#?}", self.count, sample)?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), TauphiError> {
+        Ok(self.writer.flush()?)
+    }
+}
+
+/// Counts bytes written through it, e.g. to report the output size.
+pub struct CountingWriter<W: Write> {
+    inner: W,
+    written: Rc<Cell<u64>>,
+}
+
+impl<W: Write> CountingWriter<W> {
+    /// Write into `inner`, adding the number of written bytes to `written`.
+    pub fn new(inner: W, written: Rc<Cell<u64>>) -> CountingWriter<W> {
+        CountingWriter { inner, written }
+    }
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.written.set(self.written.get() + written as u64);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[test]
+fn debug_sink_test() {
+    let mut output = Vec::new();
+    let mut sink = DebugSink::new(&mut output);
+    sink.consume(&Sample::default()).unwrap();
+    sink.consume(&Sample::default()).unwrap();
+    sink.finish().unwrap();
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.starts_with("#1 Sample {"));
+    assert!(output.contains("#2 Sample {"));
+}
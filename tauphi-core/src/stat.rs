@@ -0,0 +1,659 @@
+//! Counting events of a whole command, like `perf stat`.
+//!
+//! Counters only add up events, they cost far less than sampling and give
+//! exact totals, but no stacks. Events whose ratio is reported, e.g. cycles
+//! and instructions, are counted in one group so they cover the same time.
+use std::ffi::CString;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::process::ExitStatusExt;
+use std::process::ExitStatus;
+use std::ptr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use perf_event as pe;
+
+use crate::error::TauphiError;
+use crate::sampling::Event;
+
+/// Events counted when none are given.
+pub const DEFAULT_EVENTS: [Event; 7] = [
+    Event::TaskClock,
+    Event::Cycles,
+    Event::Instructions,
+    Event::Branches,
+    Event::BranchMisses,
+    Event::CacheReferences,
+    Event::CacheMisses,
+];
+
+/// Events counted for each thread with [StatOptions::per_thread].
+pub const PER_THREAD_EVENTS: [Event; 4] = [
+    Event::Cycles,
+    Event::Instructions,
+    Event::ContextSwitches,
+    Event::CpuMigrations,
+];
+
+/// Events counted together to report the share of the second in the first.
+const RATIOS: [(Event, Event); 3] = [
+    (Event::Cycles, Event::Instructions),
+    (Event::Branches, Event::BranchMisses),
+    (Event::CacheReferences, Event::CacheMisses),
+];
+
+/// Total of one counted event.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Count {
+    pub event: Event,
+    /// Number of events, extrapolated if the counter shared the PMU with
+    /// others. `None` if the event is not supported or was never scheduled.
+    pub value: Option<u64>,
+    /// Whether the kernel or the hardware supports the event.
+    pub supported: bool,
+    /// Share of the time the counter was scheduled, from 0 to 1.
+    pub running: f64,
+}
+
+/// Counts of a command, see [count_command()].
+#[derive(Debug, Clone)]
+pub struct StatReport {
+    /// The counted command with its arguments.
+    pub command: Vec<String>,
+    pub counts: Vec<Count>,
+    /// Wall-clock time from `exec()` until the command exited.
+    pub elapsed: Duration,
+    /// How the command exited.
+    pub status: ExitStatus,
+    /// Counts of each thread of the command with [StatOptions::per_thread],
+    /// by thread ID.
+    pub threads: Vec<ThreadCounts>,
+}
+
+/// [PER_THREAD_EVENTS] of a thread of the command.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ThreadCounts {
+    pub tid: u32,
+    /// Last seen name of the thread.
+    pub name: String,
+    pub counts: Vec<Count>,
+}
+
+/// What and how to count, see [count_command()].
+#[derive(Debug, Clone, Default)]
+pub struct StatOptions {
+    /// Counted events, [DEFAULT_EVENTS] if empty.
+    pub events: Vec<Event>,
+    /// How often to take a [Snapshot], only the totals are counted if not
+    /// given.
+    pub interval: Option<Duration>,
+    /// Whether to count [PER_THREAD_EVENTS] of each thread of the command.
+    ///
+    /// Threads are found by polling, those living shorter than
+    /// [THREAD_SCAN_PERIOD] might be missed. Threads of child processes
+    /// are not counted separately.
+    pub per_thread: bool,
+}
+
+/// How often to look for new threads with [StatOptions::per_thread].
+pub const THREAD_SCAN_PERIOD: Duration = Duration::from_millis(10);
+
+/// Counts of one interval of [count_command()].
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    /// End of the interval, since the command started.
+    pub time: Duration,
+    /// Events counted during the interval only.
+    pub counts: Vec<Count>,
+}
+
+/// A group of counters, the first one leads it.
+struct CounterGroup {
+    counters: Vec<(Event, Option<pe::PerfEventHandle>)>,
+}
+
+/// Run the command and count the events of it and its children.
+///
+/// The command waits for the counters before it starts, the counters start
+/// with its `exec()`. The first event of each group must be supported,
+/// other unsupported events are reported without a value. Commands which
+/// cannot be executed exit with 127, like in shells.
+///
+/// # Arguments
+/// * `command` - Program and its arguments.
+/// * `options` - What and how to count.
+/// * `on_snapshot` - Receives the counts of each [StatOptions::interval]
+///   while the command runs, e.g. to see phases of a benchmark. The last
+///   interval is shorter.
+pub fn count_command(
+    command: &[String],
+    options: &StatOptions,
+    mut on_snapshot: impl FnMut(&Snapshot),
+) -> Result<StatReport, TauphiError> {
+    if command.is_empty() {
+        return Err(TauphiError::InvalidArgument(
+            "no command to count".to_owned(),
+        ));
+    }
+    let events = match options.events.as_slice() {
+        [] => &DEFAULT_EVENTS[..],
+        events => events,
+    };
+    let arguments = command
+        .iter()
+        .map(|argument| CString::new(argument.as_str()))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| TauphiError::InvalidArgument("command contains a NUL byte".to_owned()))?;
+    let mut argv: Vec<_> = arguments.iter().map(|argument| argument.as_ptr()).collect();
+    argv.push(ptr::null());
+    let (gate, release) = pipe()?;
+
+    // std::process::Command reports exec() failures and therefore waits
+    // for exec(), the command must however wait for the counters first.
+    let pid = unsafe { libc::fork() };
+    if pid < 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    if pid == 0 {
+        // Only async-signal-safe calls until exec(), other threads of the
+        // parent might have held locks.
+        unsafe {
+            libc::close(release.as_raw_fd());
+            let mut byte = 0_u8;
+            // An empty read means the counters failed to open.
+            if libc::read(gate.as_raw_fd(), (&mut byte as *mut u8).cast(), 1) == 1 {
+                libc::execvp(argv[0], argv.as_ptr());
+                let message = b"tauphi: cannot execute the command\n";
+                libc::write(2, message.as_ptr().cast(), message.len());
+            }
+            libc::_exit(127);
+        }
+    }
+    drop(gate);
+    let groups = match open_groups(pid, events, true, true) {
+        Ok(groups) => groups,
+        Err(err) => {
+            drop(release);
+            let _ = wait(pid, None);
+            return Err(err);
+        }
+    };
+    let mut threads = options.per_thread.then(|| {
+        // The process is named after tauphi until exec().
+        let name = command[0].rsplit('/').next().unwrap_or_default();
+        let mut threads = ThreadCounters::new(pid, name);
+        threads.scan(true);
+        threads
+    });
+
+    // Interrupting the command must not interrupt the counting.
+    let previous = unsafe { libc::signal(libc::SIGINT, libc::SIG_IGN) };
+    let started = Instant::now();
+    let released = unsafe { libc::write(release.as_raw_fd(), [1_u8].as_ptr().cast(), 1) };
+    drop(release);
+    let zero = vec![pe::CounterReading::default(); events.len()];
+    let mut since = zero.clone();
+    let mut next_snapshot = options.interval.map(|interval| started + interval);
+    let status = loop {
+        let deadline = match (&threads, next_snapshot) {
+            (Some(_), Some(at)) => Some(at.min(Instant::now() + THREAD_SCAN_PERIOD)),
+            (Some(_), None) => Some(Instant::now() + THREAD_SCAN_PERIOD),
+            (None, at) => at,
+        };
+        let status = wait(pid, deadline);
+        if let Some(threads) = &mut threads {
+            threads.scan(false);
+        }
+        let exited = !matches!(status, Ok(None));
+        if let (Some(at), Some(interval)) = (&mut next_snapshot, options.interval) {
+            if exited || Instant::now() >= *at {
+                let counts;
+                (counts, since) = read_counts(&groups, events, &since)?;
+                on_snapshot(&Snapshot {
+                    time: started.elapsed(),
+                    counts,
+                });
+                *at += interval;
+            }
+        }
+        if exited {
+            break status;
+        }
+    };
+    let elapsed = started.elapsed();
+    unsafe { libc::signal(libc::SIGINT, previous) };
+    if released != 1 {
+        return Err(io::Error::last_os_error().into());
+    }
+    // Waiting ends only with the exit or an error.
+    let status = status?.unwrap();
+
+    let (counts, _) = read_counts(&groups, events, &zero)?;
+    Ok(StatReport {
+        command: command.to_vec(),
+        counts,
+        elapsed,
+        status,
+        threads: match threads {
+            Some(threads) => threads.counts()?,
+            None => Vec::new(),
+        },
+    })
+}
+
+/// Counters of the threads of a process, opened as the threads appear.
+struct ThreadCounters {
+    pid: i32,
+    /// Thread ID, name and counters of each thread, by thread ID.
+    threads: Vec<(u32, String, Vec<CounterGroup>)>,
+}
+
+impl ThreadCounters {
+    fn new(pid: i32, name: &str) -> ThreadCounters {
+        ThreadCounters {
+            pid,
+            threads: vec![(pid as u32, name.to_owned(), Vec::new())],
+        }
+    }
+
+    /// Open counters of new threads and update the names of running ones.
+    ///
+    /// # Arguments
+    /// * `on_exec` - Whether the counters start with `exec()`, only before
+    ///   the command runs.
+    fn scan(&mut self, on_exec: bool) {
+        let Ok(tasks) = fs::read_dir(format!("/proc/{}/task", self.pid)) else {
+            // The process exited, the names are the last ones seen.
+            return;
+        };
+        let tids = tasks.filter_map(|task| task.ok()?.file_name().to_str()?.parse().ok());
+        for tid in tids {
+            let index = match self.threads.binary_search_by_key(&tid, |thread| thread.0) {
+                Ok(index) => index,
+                Err(index) => {
+                    self.threads.insert(index, (tid, String::new(), Vec::new()));
+                    index
+                }
+            };
+            let thread = &mut self.threads[index];
+            if thread.2.is_empty() {
+                // Threads exiting meanwhile are left out.
+                // Future threads of the thread are found by the next scans.
+                match open_groups(tid as i32, &PER_THREAD_EVENTS, false, on_exec) {
+                    Ok(groups) => thread.2 = groups,
+                    Err(err) => tracing::debug!(tid = tid, "cannot count the thread: {}", err),
+                }
+            }
+            if !on_exec {
+                if let Ok(name) = fs::read_to_string(format!("/proc/{}/task/{tid}/comm", self.pid))
+                {
+                    thread.1 = name.trim_end().to_owned();
+                }
+            }
+        }
+    }
+
+    /// Totals of the threads whose counters opened.
+    fn counts(&self) -> Result<Vec<ThreadCounts>, TauphiError> {
+        let zero = [pe::CounterReading::default(); PER_THREAD_EVENTS.len()];
+        let mut threads = Vec::with_capacity(self.threads.len());
+        for (tid, name, groups) in &self.threads {
+            if groups.is_empty() {
+                continue;
+            }
+            threads.push(ThreadCounts {
+                tid: *tid,
+                name: name.clone(),
+                counts: read_counts(groups, &PER_THREAD_EVENTS, &zero)?.0,
+            });
+        }
+        Ok(threads)
+    }
+}
+
+/// Open the counters of the process, grouping each ratio of [RATIOS].
+///
+/// # Arguments
+/// * `pid` - Process or thread to count.
+/// * `events` - Counted events.
+/// * `inherit` - Whether to count also future children and threads.
+/// * `on_exec` - Whether the counters start with the next `exec()`.
+fn open_groups(
+    pid: i32,
+    events: &[Event],
+    inherit: bool,
+    on_exec: bool,
+) -> Result<Vec<CounterGroup>, TauphiError> {
+    let mut groups: Vec<CounterGroup> = Vec::new();
+    for &event in events {
+        let (event_type, event_config) = event.type_and_config();
+        let leader = groups.iter_mut().find(|group| {
+            RATIOS.contains(&(group.counters[0].0, event)) && group.counters[0].1.is_some()
+        });
+        match leader {
+            Some(group) => {
+                let leader = group.counters[0].1.as_ref();
+                let handle = pe::PerfEventHandle::new_group_counter(
+                    pid,
+                    event_type,
+                    event_config,
+                    leader,
+                    inherit,
+                    on_exec,
+                );
+                if let Err(err) = &handle {
+                    tracing::debug!(event = event.name(), "cannot count: {}", err);
+                }
+                group.counters.push((event, handle.ok()));
+            }
+            None => {
+                let handle = pe::PerfEventHandle::new_group_counter(
+                    pid,
+                    event_type,
+                    event_config,
+                    None,
+                    inherit,
+                    on_exec,
+                );
+                let handle = match handle {
+                    Ok(handle) => Some(handle),
+                    // Report hardware events missing e.g. in virtual machines.
+                    Err(pe::error::PerfError::FailedOpen(err))
+                        if err.raw_os_error() == Some(libc::ENOENT)
+                            || err.raw_os_error() == Some(libc::EOPNOTSUPP) =>
+                    {
+                        tracing::debug!(event = event.name(), "not supported: {}", err);
+                        None
+                    }
+                    Err(source) => {
+                        return Err(TauphiError::Sampler {
+                            target: format!("PID {pid}"),
+                            source,
+                        })
+                    }
+                };
+                groups.push(CounterGroup {
+                    counters: vec![(event, handle)],
+                });
+            }
+        }
+    }
+    Ok(groups)
+}
+
+/// Counts of all counters since the earlier readings, in the order of the
+/// events, and the current readings.
+fn read_counts(
+    groups: &[CounterGroup],
+    events: &[Event],
+    since: &[pe::CounterReading],
+) -> Result<(Vec<Count>, Vec<pe::CounterReading>), TauphiError> {
+    let mut counts = Vec::with_capacity(events.len());
+    let mut readings = Vec::with_capacity(events.len());
+    let counters = groups.iter().flat_map(|group| &group.counters);
+    for ((event, handle), since) in counters.zip(since) {
+        let reading = match handle {
+            Some(handle) => handle.read_group_counter()?,
+            None => {
+                counts.push(Count {
+                    event: *event,
+                    value: None,
+                    supported: false,
+                    running: 0.0,
+                });
+                readings.push(*since);
+                continue;
+            }
+        };
+        counts.push(delta_count(*event, &reading, since));
+        readings.push(reading);
+    }
+    // Report in the order of the events, not of the groups.
+    let mut order: Vec<_> = (0..counts.len()).collect();
+    order.sort_by_key(|&index| {
+        events
+            .iter()
+            .position(|event| *event == counts[index].event)
+    });
+    let counts = order.iter().map(|&index| counts[index]).collect();
+    Ok((counts, readings))
+}
+
+/// Count between the readings, extrapolated to the whole time the counter
+/// was enabled.
+fn delta_count(event: Event, reading: &pe::CounterReading, since: &pe::CounterReading) -> Count {
+    let value = reading.value.saturating_sub(since.value);
+    let enabled = reading.time_enabled.saturating_sub(since.time_enabled);
+    let running = reading.time_running.saturating_sub(since.time_running);
+    Count {
+        event,
+        value: (running > 0).then(|| (value as u128 * enabled as u128 / running as u128) as u64),
+        supported: true,
+        running: if enabled == 0 {
+            0.0
+        } else {
+            running as f64 / enabled as f64
+        },
+    }
+}
+
+/// Wait for the child process to exit, `None` if it still runs at the
+/// deadline.
+fn wait(pid: i32, deadline: Option<Instant>) -> io::Result<Option<ExitStatus>> {
+    /// How often to check for the exit before the deadline.
+    const POLL_PERIOD: Duration = Duration::from_millis(10);
+    let flags = if deadline.is_some() { libc::WNOHANG } else { 0 };
+    let mut status = 0;
+    loop {
+        match unsafe { libc::waitpid(pid, &mut status, flags) } {
+            0 => {
+                let remaining = deadline.unwrap().saturating_duration_since(Instant::now());
+                if remaining.is_zero() {
+                    return Ok(None);
+                }
+                thread::sleep(remaining.min(POLL_PERIOD));
+            }
+            result if result == pid => return Ok(Some(ExitStatus::from_raw(status))),
+            _ => {
+                let err = io::Error::last_os_error();
+                if err.kind() != io::ErrorKind::Interrupted {
+                    return Err(err);
+                }
+            }
+        }
+    }
+}
+
+/// Pipe whose ends are closed on `exec()`, (read, write).
+fn pipe() -> Result<(OwnedFd, OwnedFd), TauphiError> {
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } != 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    Ok(unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) })
+}
+
+impl StatReport {
+    /// Value of the event, if counted.
+    pub fn value(&self, event: Event) -> Option<u64> {
+        self.counts
+            .iter()
+            .find(|count| count.event == event)
+            .and_then(|count| count.value)
+    }
+
+    /// Exit code of the command, 128 + the signal if it was killed.
+    pub fn exit_code(&self) -> i32 {
+        match (self.status.code(), self.status.signal()) {
+            (Some(code), _) => code,
+            (None, Some(signal)) => 128 + signal,
+            (None, None) => 1,
+        }
+    }
+
+    /// Metric derived from the count of the event and its name, e.g. the
+    /// instructions per cycle for instructions.
+    pub fn metric(&self, event: Event) -> Option<(f64, &'static str)> {
+        let ratio = |numerator: Event, denominator: Event| {
+            let denominator = self.value(denominator).filter(|&value| value > 0)?;
+            Some(self.value(numerator)? as f64 / denominator as f64)
+        };
+        match event {
+            // Task clock counts nanoseconds.
+            Event::TaskClock => {
+                let busy = self.value(Event::TaskClock)? as f64;
+                Some((
+                    busy / self.elapsed.as_nanos().max(1) as f64,
+                    "CPUs utilized",
+                ))
+            }
+            Event::Cycles => Some((ratio(Event::Cycles, Event::TaskClock)?, "GHz")),
+            Event::Instructions => {
+                Some((ratio(Event::Instructions, Event::Cycles)?, "insn per cycle"))
+            }
+            Event::BranchMisses => Some((
+                100.0 * ratio(Event::BranchMisses, Event::Branches)?,
+                "% of all branches",
+            )),
+            Event::CacheMisses => Some((
+                100.0 * ratio(Event::CacheMisses, Event::CacheReferences)?,
+                "% of all cache refs",
+            )),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for StatReport {
+    /// Table of the counts with derived metrics, in the layout of `perf stat`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "\n Performance counter stats for '{}':\n",
+            self.command.join(" ")
+        )?;
+        for count in &self.counts {
+            let value = match (count.event, count.value) {
+                (Event::TaskClock, Some(nanos)) => format!("{:.2} msec", nanos as f64 / 1e6),
+                (_, Some(value)) => format!("{}     ", with_separators(value)),
+                (_, None) if count.supported => "<not counted>     ".to_owned(),
+                (_, None) => "<not supported>     ".to_owned(),
+            };
+            let mut line = format!("{value:>24} {:<24}", count.event.name());
+            if let Some((metric, unit)) = self.metric(count.event) {
+                line += &format!(" # {metric:8.2} {unit}");
+            }
+            if count.value.is_some() && count.running < 1.0 {
+                line += &format!("  ({:.2} %)", count.running * 100.0);
+            }
+            writeln!(f, "{}", line.trim_end())?;
+        }
+        if !self.threads.is_empty() {
+            write!(f, "\n{:>8}  {:<16}", "TID", "COMMAND")?;
+            for event in PER_THREAD_EVENTS {
+                write!(f, " {:>16}", event.name())?;
+            }
+            writeln!(f)?;
+            for thread in &self.threads {
+                write!(f, "{:>8}  {:<16}", thread.tid, thread.name)?;
+                for count in &thread.counts {
+                    let value = count.value.map_or_else(|| "-".to_owned(), with_separators);
+                    write!(f, " {value:>16}")?;
+                }
+                writeln!(f)?;
+            }
+        }
+        write!(
+            f,
+            "\n{:>16.9} seconds time elapsed\n",
+            self.elapsed.as_secs_f64()
+        )
+    }
+}
+
+/// Format the number with thousands separated by commas.
+fn with_separators(value: u64) -> String {
+    let digits = value.to_string();
+    let mut separated = String::with_capacity(digits.len() * 4 / 3);
+    for (index, digit) in digits.chars().enumerate() {
+        if index > 0 && (digits.len() - index).is_multiple_of(3) {
+            separated.push(',');
+        }
+        separated.push(digit);
+    }
+    separated
+}
+
+#[test]
+fn delta_count_test() {
+    let reading = |value, time_enabled, time_running| pe::CounterReading {
+        value,
+        time_enabled,
+        time_running,
+    };
+    // Counting half of the interval extrapolates to twice the value.
+    let count = delta_count(
+        Event::Cycles,
+        &reading(300, 400, 300),
+        &reading(100, 200, 200),
+    );
+    assert_eq!((count.value, count.running), (Some(400), 0.5));
+    let idle = delta_count(Event::Cycles, &reading(0, 100, 0), &Default::default());
+    assert_eq!((idle.value, idle.running), (None, 0.0));
+}
+
+#[test]
+fn stat_report_test() {
+    let count = |event, value| Count {
+        event,
+        value,
+        supported: true,
+        running: 1.0,
+    };
+    let report = StatReport {
+        command: vec!["true".to_owned()],
+        counts: vec![
+            count(Event::TaskClock, Some(2_000_000)),
+            count(Event::Cycles, Some(4_000_000)),
+            count(Event::Instructions, Some(6_000_000)),
+            Count {
+                running: 0.0,
+                ..count(Event::BranchMisses, None)
+            },
+            Count {
+                supported: false,
+                ..count(Event::CacheMisses, None)
+            },
+        ],
+        elapsed: Duration::from_millis(4),
+        status: ExitStatus::from_raw(0),
+        threads: vec![ThreadCounts {
+            tid: 42,
+            name: "worker".to_owned(),
+            counts: PER_THREAD_EVENTS
+                .into_iter()
+                .map(|event| count(event, Some(1000)))
+                .collect(),
+        }],
+    };
+    assert_eq!(
+        report.metric(Event::TaskClock),
+        Some((0.5, "CPUs utilized"))
+    );
+    assert_eq!(report.metric(Event::Cycles), Some((2.0, "GHz")));
+    assert_eq!(
+        report.metric(Event::Instructions),
+        Some((1.5, "insn per cycle"))
+    );
+    assert_eq!(report.metric(Event::BranchMisses), None);
+    let table = report.to_string();
+    assert!(table.contains("6,000,000      instructions"));
+    assert!(table.contains("<not counted>      branch-misses"));
+    assert!(table.contains("<not supported>      cache-misses"));
+    assert!(table.contains("      42  worker                      1,000"));
+    assert_eq!(with_separators(1234567), "1,234,567");
+    assert_eq!(with_separators(123), "123");
+}
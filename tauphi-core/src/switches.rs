@@ -0,0 +1,108 @@
+//! Context switches and CPU migrations of sampled threads.
+//!
+//! Threads switched out or moved between CPUs often lose their caches, many
+//! switches explain a low IPC even of stacks which look fine. Sample
+//! [Event::ContextSwitches] and [Event::CpuMigrations] with a period of 1
+//! alongside the CPU samples and feed all samples to [SwitchTable].
+use std::collections::HashMap;
+use std::fs;
+
+use crate::sampling::{Event, Sample};
+
+/// Switches and migrations of a thread seen during the sampling.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ThreadSwitches {
+    pub pid: u32,
+    pub tid: u32,
+    /// Name of the thread, `None` if it exited before it was read.
+    pub name: Option<String>,
+    /// Samples of other events, e.g. of the CPU time.
+    pub samples: u64,
+    /// Samples of [Event::ContextSwitches].
+    pub switches: u64,
+    /// Samples of [Event::CpuMigrations].
+    pub migrations: u64,
+}
+
+/// Collects [ThreadSwitches] of all threads with samples.
+#[derive(Debug, Default)]
+pub struct SwitchTable {
+    threads: HashMap<u32, ThreadSwitches>,
+}
+
+impl SwitchTable {
+    /// Account the sample to its thread, reads the name of the thread on
+    /// its first sample.
+    pub fn add_sample(&mut self, sample: &Sample) {
+        let thread = self
+            .threads
+            .entry(sample.tid)
+            .or_insert_with(|| ThreadSwitches {
+                pid: sample.pid,
+                tid: sample.tid,
+                name: fs::read_to_string(format!("/proc/{}/task/{}/comm", sample.pid, sample.tid))
+                    .ok()
+                    .map(|comm| comm.trim_end().to_owned()),
+                ..Default::default()
+            });
+        match sample.event {
+            Event::ContextSwitches => thread.switches += 1,
+            Event::CpuMigrations => thread.migrations += 1,
+            _ => thread.samples += 1,
+        }
+    }
+
+    /// Threads which were switched or migrated, the most switched first.
+    pub fn threads(&self) -> Vec<&ThreadSwitches> {
+        let mut threads: Vec<_> = self
+            .threads
+            .values()
+            .filter(|thread| thread.switches + thread.migrations > 0)
+            .collect();
+        threads.sort_by_key(|thread| {
+            (
+                u64::MAX - thread.switches,
+                u64::MAX - thread.migrations,
+                thread.tid,
+            )
+        });
+        threads
+    }
+}
+
+#[test]
+fn switch_table_test() {
+    let mut table = SwitchTable::default();
+    let sample = |tid, event| Sample {
+        pid: 1,
+        tid,
+        event,
+        ..Default::default()
+    };
+    for event in [
+        Event::TaskClock,
+        Event::ContextSwitches,
+        Event::ContextSwitches,
+        Event::CpuMigrations,
+    ] {
+        table.add_sample(&sample(u32::MAX, event));
+    }
+    table.add_sample(&sample(u32::MAX - 1, Event::CpuMigrations));
+    // Threads only running are left out.
+    table.add_sample(&sample(u32::MAX - 2, Event::TaskClock));
+
+    let threads = table.threads();
+    assert_eq!(threads.len(), 2);
+    assert_eq!(
+        threads[0],
+        &ThreadSwitches {
+            pid: 1,
+            tid: u32::MAX,
+            name: None,
+            samples: 1,
+            switches: 2,
+            migrations: 1,
+        }
+    );
+    assert_eq!(threads[1].tid, u32::MAX - 1);
+}
@@ -0,0 +1,342 @@
+//! Function symbols of ELF files, names sampled addresses of native code.
+//!
+//! Only the symbol tables are read, `.symtab` if the file is not stripped
+//! and `.dynsym` otherwise. Neither debug info nor inlined functions are
+//! used, names are not demangled but matched by their path, see
+//! [Frame::is_function()].
+//!
+//! Addresses without a symbol are named relative to their file, JIT-compiled
+//! and kernel code only by its kind, see [Frame].
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use crate::elf::{u32_at, u64_at, ElfFile, SectionHeader};
+use crate::maps::MapsCache;
+
+/// `PT_LOAD` program header of ELF files.
+const PT_LOAD: u32 = 1;
+/// `SHT_SYMTAB` section of ELF files.
+const SHT_SYMTAB: u32 = 2;
+/// `SHT_DYNSYM` section of ELF files.
+const SHT_DYNSYM: u32 = 11;
+/// `STT_FUNC` type of ELF symbols.
+const STT_FUNC: u8 = 2;
+/// Smallest valid size of entries of symbol tables.
+const SYMBOL_SIZE: u64 = 24;
+/// Larger tables are not read, avoids reading garbage.
+const MAX_TABLE_SIZE: u64 = 256 << 20;
+/// Reading symbols of a file for longer is logged as a warning.
+const SLOW_SYMBOLS: Duration = Duration::from_millis(200);
+/// Addresses from here on are of the kernel.
+const KERNEL_START: u64 = 0xffff_8000_0000_0000;
+
+/// Code at a sampled address, see [Symbolizer::frame()].
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Frame {
+    /// A function of a mapped file, with the name of the file.
+    Function { name: String, file: String },
+    /// Code without a symbol, e.g. `libfoo.so+0x1234` or `[kernel]`.
+    Unnamed(String),
+}
+
+impl Frame {
+    /// Whether this is the function of the given name, either as in the
+    /// symbol table or by its demangled path. The path may leave out
+    /// leading modules, e.g. `rt::lang_start` for `std::rt::lang_start`.
+    pub fn is_function(&self, function: &str) -> bool {
+        let Frame::Function { name, .. } = self else {
+            return false;
+        };
+        name == function
+            || mangled_path(name).is_some_and(|path| {
+                path == function
+                    || path
+                        .strip_suffix(function)
+                        .is_some_and(|parent| parent.ends_with("::"))
+            })
+    }
+}
+
+/// The function followed by its file, or the unnamed location.
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Frame::Function { name, file } => write!(f, "{name}  {file}"),
+            Frame::Unnamed(location) => write!(f, "{location}"),
+        }
+    }
+}
+
+/// A [Symbolizer] shared by the reports and sinks of a run, the mappings
+/// and symbols are read once for all of them.
+pub type SharedSymbolizer = Rc<RefCell<Symbolizer>>;
+
+/// Names addresses of processes by the symbols of their mapped files.
+///
+/// The mappings of a process are read on its first address or observed
+/// record, libraries loaded later are not named, see
+/// [MapsCache::snapshot()].
+#[derive(Debug, Default)]
+pub struct Symbolizer {
+    maps: MapsCache,
+    /// Symbols of mapped files by their device and inode, `None` for files
+    /// which are not ELF.
+    symbols: HashMap<(String, u64), Option<ElfSymbols>>,
+}
+
+impl Symbolizer {
+    /// The mappings of processes, e.g. to snapshot them by
+    /// [MapsCache::observe()] before processes exit.
+    pub fn maps(&mut self) -> &mut MapsCache {
+        &mut self.maps
+    }
+
+    /// Code at the address in the process.
+    pub fn frame(&mut self, pid: u32, ip: u64) -> Frame {
+        if ip >= KERNEL_START {
+            return Frame::Unnamed("[kernel]".to_owned());
+        }
+        let Some(mapping) = self.maps.snapshot(pid).and_then(|mappings| {
+            mappings
+                .iter()
+                .find(|mapping| mapping.addresses.contains(&ip))
+                .cloned()
+        }) else {
+            return Frame::Unnamed("[unknown]".to_owned());
+        };
+        let Some(path) = mapping.path.as_deref() else {
+            return Frame::Unnamed("[anonymous JIT]".to_owned());
+        };
+        let offset = ip - mapping.addresses.start + mapping.offset;
+        let symbols = self
+            .symbols
+            .entry(mapping.inode.clone())
+            .or_insert_with(|| load_symbols(pid, path));
+        let file = Path::new(path)
+            .file_name()
+            .map_or(path.into(), |name| name.to_string_lossy())
+            .into_owned();
+        match symbols
+            .as_ref()
+            .and_then(|symbols| symbols.function_at(offset))
+        {
+            Some(name) => Frame::Function {
+                name: name.to_owned(),
+                file,
+            },
+            None => Frame::Unnamed(format!("{file}+{offset:#x}")),
+        }
+    }
+}
+
+/// Path of a mangled name without the hash of Rust symbols, e.g.
+/// `std::rt::lang_start` of `_ZN3std2rt10lang_start17h0123456789abcdefE`.
+/// Escapes such as `$LT$` are kept. `None` for names which are not
+/// mangled or not plain paths, e.g. generic Rust functions.
+fn mangled_path(name: &str) -> Option<String> {
+    if let Some(rest) = name.strip_prefix("_R") {
+        let (segments, _) = v0_path(rest)?;
+        return Some(segments.join("::"));
+    }
+    let mut rest = name.strip_prefix("_ZN")?;
+    let mut segments = Vec::new();
+    while !rest.starts_with('E') {
+        let digits = rest.find(|c: char| !c.is_ascii_digit())?;
+        let length: usize = rest[..digits].parse().ok()?;
+        segments.push(rest.get(digits..digits + length)?);
+        rest = &rest[digits + length..];
+    }
+    let is_hash = |segment: &str| {
+        segment.len() == 17
+            && segment.starts_with('h')
+            && segment[1..].bytes().all(|byte| byte.is_ascii_hexdigit())
+    };
+    if segments.last().is_some_and(|&last| is_hash(last)) {
+        segments.pop();
+    }
+    (!segments.is_empty()).then(|| segments.join("::"))
+}
+
+/// Segments of a path in the v0 mangling of Rust, e.g. `Nv` of a function,
+/// and the rest of the name. Closures and shims have no names of their own.
+fn v0_path(name: &str) -> Option<(Vec<String>, &str)> {
+    let (mut segments, rest) = match name.as_bytes().first()? {
+        b'C' => (Vec::new(), &name[1..]),
+        b'N' => v0_path(name.get(2..)?)?,
+        _ => return None,
+    };
+    // An optional disambiguator `s<base 62>_` precedes the identifier.
+    let rest = match rest.strip_prefix('s') {
+        Some(rest) => &rest[rest.find('_')? + 1..],
+        None => rest,
+    };
+    let digits = rest
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(rest.len());
+    let length: usize = rest[..digits].parse().ok()?;
+    let rest = rest[digits..].strip_prefix('_').unwrap_or(&rest[digits..]);
+    let identifier = rest.get(..length)?;
+    // Uppercase namespaces are of items without names, e.g. `C` closures.
+    match name.strip_prefix('N').map(|rest| rest.as_bytes()[0]) {
+        Some(b'C') => segments.push("{closure}".to_owned()),
+        Some(namespace) if namespace.is_ascii_uppercase() => return None,
+        _ => segments.push(identifier.to_owned()),
+    }
+    Some((segments, &rest[length..]))
+}
+
+/// Symbols of the file mapped by the process, logs how they were read.
+fn load_symbols(pid: u32, path: &str) -> Option<ElfSymbols> {
+    if !path.starts_with('/') {
+        return None;
+    }
+    let started = Instant::now();
+    // Read through the process's root for processes in containers.
+    let symbols = ElfSymbols::read(&format!("/proc/{pid}/root{path}"));
+    let elapsed = started.elapsed();
+    match &symbols {
+        Some(symbols) => tracing::debug!(
+            pid = pid,
+            path = path,
+            functions = symbols.functions.len(),
+            ms = elapsed.as_millis() as u64,
+            "loaded the symbols"
+        ),
+        None => tracing::debug!(pid = pid, path = path, "no ELF symbols to load"),
+    }
+    if elapsed > SLOW_SYMBOLS {
+        tracing::warn!(
+            path = path,
+            ms = elapsed.as_millis() as u64,
+            "slow symbolization of a file"
+        );
+    }
+    symbols
+}
+
+/// Functions of an ELF file by their addresses.
+#[derive(Debug, Default)]
+pub struct ElfSymbols {
+    /// Loaded segments as (file offset, size in the file, virtual address).
+    segments: Vec<(u64, u64, u64)>,
+    /// Functions as (start, end, name), sorted by the start.
+    functions: Vec<(u64, u64, String)>,
+}
+
+impl ElfSymbols {
+    /// Read the functions of a 64-bit ELF file, `None` if it is none.
+    pub fn read(path: &str) -> Option<ElfSymbols> {
+        let elf = ElfFile::open(path)?;
+        let mut symbols = ElfSymbols::default();
+        for segment in elf.program_headers()? {
+            if segment.kind == PT_LOAD {
+                symbols
+                    .segments
+                    .push((segment.offset, segment.size, segment.address));
+            }
+        }
+        let sections = elf.section_headers()?;
+        // Stripped files keep only the dynamic symbols.
+        let table = [SHT_SYMTAB, SHT_DYNSYM]
+            .iter()
+            .find_map(|&kind| sections.iter().find(|section| section.kind == kind))?;
+        let strings = sections.get(table.link as usize)?;
+        if table.entry_size < SYMBOL_SIZE {
+            return None;
+        }
+        let read = |section: &SectionHeader| elf.read(section.offset, section.size, MAX_TABLE_SIZE);
+        let (table_bytes, strings) = (read(table)?, read(strings)?);
+        for symbol in table_bytes.chunks_exact(table.entry_size as usize) {
+            let (start, size) = (u64_at(symbol, 8), u64_at(symbol, 16));
+            if symbol[4] & 0xf != STT_FUNC || start == 0 || size == 0 {
+                continue;
+            }
+            let name = &strings[(u32_at(symbol, 0) as usize).min(strings.len())..];
+            let name = &name[..name.iter().position(|&byte| byte == 0).unwrap_or(0)];
+            symbols.functions.push((
+                start,
+                start + size,
+                String::from_utf8_lossy(name).into_owned(),
+            ));
+        }
+        symbols.functions.sort_unstable();
+        Some(symbols)
+    }
+
+    /// Name of the function at the offset in the file, e.g. of an address
+    /// in a mapping of the file.
+    pub fn function_at(&self, offset: u64) -> Option<&str> {
+        let &(segment_offset, _, address) = self
+            .segments
+            .iter()
+            .find(|&&(start, size, _)| (start..start + size).contains(&offset))?;
+        let address = offset - segment_offset + address;
+        let index = self
+            .functions
+            .partition_point(|&(start, _, _)| start <= address);
+        let (_, end, name) = self.functions.get(index.checked_sub(1)?)?;
+        (address < *end).then_some(name.as_str())
+    }
+}
+
+#[test]
+fn elf_symbols_test() {
+    #[inline(never)]
+    fn sampled_function() -> u64 {
+        std::hint::black_box(42)
+    }
+
+    let address = sampled_function as fn() -> u64 as usize as u64;
+    let maps = std::fs::read_to_string("/proc/self/maps").unwrap();
+    let mapping = crate::maps::parse_maps(&maps)
+        .into_iter()
+        .find(|mapping| mapping.addresses.contains(&address))
+        .unwrap();
+    let symbols = ElfSymbols::read(mapping.path.as_deref().unwrap()).unwrap();
+    let name = symbols
+        .function_at(address - mapping.addresses.start + mapping.offset)
+        .unwrap();
+    // Mangled with the path of the function.
+    assert!(name.contains("sampled_function"), "{name}");
+    assert!(symbols.function_at(u64::MAX).is_none());
+    assert!(ElfSymbols::read("/proc/self/maps").is_none());
+
+    let mut symbolizer = Symbolizer::default();
+    let pid = std::process::id();
+    let Frame::Function { name, .. } = symbolizer.frame(pid, address) else {
+        panic!("Expected a function.");
+    };
+    assert!(name.contains("sampled_function"), "{name}");
+    assert_eq!(symbolizer.frame(pid, KERNEL_START).to_string(), "[kernel]");
+    assert_eq!(symbolizer.frame(pid, 1).to_string(), "[unknown]");
+}
+
+#[test]
+fn is_function_test() {
+    let frame = |name: &str| Frame::Function {
+        name: name.to_owned(),
+        file: "tauphi".to_owned(),
+    };
+    let lang_start = frame("_ZN3std2rt10lang_start17h0123456789abcdefE");
+    for function in [
+        "_ZN3std2rt10lang_start17h0123456789abcdefE",
+        "std::rt::lang_start",
+        "rt::lang_start",
+        "lang_start",
+    ] {
+        assert!(lang_start.is_function(function), "{function}");
+    }
+    assert!(!lang_start.is_function("start"));
+    assert!(!lang_start.is_function("std::rt"));
+    assert!(frame("_ZN3foo3barEv").is_function("foo::bar"));
+    let parse = frame("_RNvNtCs1234_6tauphi6parser5parse");
+    assert!(parse.is_function("parser::parse"));
+    assert!(frame("_RNCNvCs1234_6tauphi4main0").is_function("main::{closure}"));
+    assert!(frame("main").is_function("main"));
+    assert!(!frame("_ZN3foo").is_function("foo"));
+    assert!(!Frame::Unnamed("[kernel]".to_owned()).is_function("[kernel]"));
+}
@@ -0,0 +1,160 @@
+//! Samples of each process over time, shows when the CPU was used.
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::time::Duration;
+
+use crate::error::TauphiError;
+use crate::sampling::Sample;
+use crate::sink::SampleSink;
+
+/// Sample counts per process in buckets of fixed duration.
+///
+/// Buckets start at the first sample. With a time-based event such as
+/// task-clock, the count of a bucket divided by the frequency and the
+/// bucket length is the number of CPUs the process used.
+#[derive(Debug, Clone)]
+pub struct Timeline {
+    bucket: Duration,
+    /// Time of the first sample.
+    start: Option<u64>,
+    /// Counts of each bucket, keyed by PID.
+    counts: BTreeMap<u32, Vec<u64>>,
+}
+
+impl Timeline {
+    /// Empty timeline with buckets of the given length.
+    pub fn new(bucket: Duration) -> Timeline {
+        assert!(!bucket.is_zero(), "Buckets must not be empty.");
+        Timeline {
+            bucket,
+            start: None,
+            counts: BTreeMap::new(),
+        }
+    }
+
+    /// Count the sample into its bucket.
+    pub fn add_sample(&mut self, sample: &Sample) {
+        let start = *self.start.get_or_insert(sample.time);
+        // Samples of different CPUs can arrive slightly out of order.
+        let since_start = sample.time.saturating_sub(start);
+        let index = (since_start / self.bucket.as_nanos() as u64) as usize;
+        let counts = self.counts.entry(sample.pid).or_default();
+        if counts.len() <= index {
+            counts.resize(index + 1, 0);
+        }
+        counts[index] += 1;
+    }
+
+    /// Number of buckets up to the last sample.
+    pub fn len(&self) -> usize {
+        self.counts.values().map(Vec::len).max().unwrap_or(0)
+    }
+
+    /// Whether there are no samples.
+    pub fn is_empty(&self) -> bool {
+        self.counts.is_empty()
+    }
+
+    /// Counts of all buckets of the process, zeros if it has no samples.
+    pub fn counts(&self, pid: u32) -> Vec<u64> {
+        let mut counts = self.counts.get(&pid).cloned().unwrap_or_default();
+        counts.resize(self.len(), 0);
+        counts
+    }
+
+    /// Processes with samples, those with most samples first.
+    pub fn pids(&self) -> Vec<u32> {
+        let mut pids: Vec<_> = self.counts.keys().copied().collect();
+        pids.sort_by_key(|pid| std::cmp::Reverse(self.counts[pid].iter().sum::<u64>()));
+        pids
+    }
+
+    /// Write `bucket,pid,samples` rows with a header, the bucket is the
+    /// start time in seconds since the first sample.
+    pub fn write_csv(&self, mut writer: impl Write) -> Result<(), TauphiError> {
+        writeln!(writer, "time,pid,samples")?;
+        for index in 0..self.len() {
+            let time = self.bucket.as_secs_f64() * index as f64;
+            for (pid, counts) in &self.counts {
+                match counts.get(index) {
+                    Some(&count) if count > 0 => writeln!(writer, "{time},{pid},{count}")?,
+                    _ => (),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// One sparkline per process for the `top` processes with most samples.
+    ///
+    /// All lines share one scale, so spikes of different processes compare.
+    pub fn sparklines(&self, top: usize) -> String {
+        const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+        let max = self.counts.values().flatten().max().copied().unwrap_or(0);
+        let mut lines = String::new();
+        for pid in self.pids().into_iter().take(top) {
+            let counts = self.counts(pid);
+            let line: String = counts
+                .iter()
+                .map(|&count| match count {
+                    0 => ' ',
+                    _ => BARS[((count * BARS.len() as u64).div_ceil(max) - 1) as usize],
+                })
+                .collect();
+            let total: u64 = counts.iter().sum();
+            lines.push_str(&format!("{pid:>8} {line} {total} samples\n"));
+        }
+        lines
+    }
+}
+
+/// Collects samples into a [Timeline] and writes it as CSV when finished.
+pub struct TimelineSink<W: Write> {
+    writer: W,
+    timeline: Timeline,
+}
+
+impl<W: Write> TimelineSink<W> {
+    /// Write the timeline with buckets of the given length to the writer.
+    pub fn new(writer: W, bucket: Duration) -> TimelineSink<W> {
+        TimelineSink {
+            writer,
+            timeline: Timeline::new(bucket),
+        }
+    }
+}
+
+impl<W: Write> SampleSink for TimelineSink<W> {
+    fn consume(&mut self, sample: &Sample) -> Result<(), TauphiError> {
+        self.timeline.add_sample(sample);
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), TauphiError> {
+        self.timeline.write_csv(&mut self.writer)?;
+        Ok(self.writer.flush()?)
+    }
+}
+
+#[test]
+fn timeline_test() {
+    let mut timeline = Timeline::new(Duration::from_secs(1));
+    for (pid, time) in [(1, 5_000_000_000), (1, 5_100_000_000), (2, 7_000_000_000)] {
+        timeline.add_sample(&Sample {
+            pid,
+            time,
+            ..Default::default()
+        });
+    }
+    assert_eq!(timeline.len(), 3);
+    assert_eq!(timeline.counts(1), [2, 0, 0]);
+    assert_eq!(timeline.pids(), [1, 2]);
+    assert_eq!(timeline.sparklines(1), "       1 █   2 samples\n");
+
+    let mut csv = Vec::new();
+    timeline.write_csv(&mut csv).unwrap();
+    assert_eq!(
+        String::from_utf8(csv).unwrap(),
+        "time,pid,samples\n0,1,2\n2,2,1\n"
+    );
+}
@@ -0,0 +1,158 @@
+//! The hottest functions of recent samples, for periodic summaries.
+//!
+//! Samples are named by the function symbols of their mapped files, see
+//! [Symbolizer](crate::symbols::Symbolizer).
+//!
+//! Shares are estimated from the samples, each is printed with the margin
+//! of its 95 % confidence interval. Functions with too few samples are
+//! marked, their shares are likely noise.
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::sampling::Sample;
+use crate::symbols::SharedSymbolizer;
+
+/// Functions with fewer samples are marked as noise in a [Summary].
+const MIN_SAMPLES: u64 = 10;
+/// Quantile of the normal distribution for 95 % confidence intervals.
+const Z_95: f64 = 1.96;
+
+/// Samples of a function in a [Summary].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HotFunction {
+    /// Name of the function, or of the file with the offset in it.
+    pub name: String,
+    pub samples: u64,
+}
+
+/// The hottest functions since the previous summary.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Summary {
+    /// All samples, including those of functions left out.
+    pub samples: u64,
+    /// The most sampled functions first.
+    pub functions: Vec<HotFunction>,
+}
+
+impl Summary {
+    /// Share of the function in percent and the margin of its confidence
+    /// interval, by the normal approximation of the binomial distribution.
+    pub fn share(&self, function: &HotFunction) -> (f64, f64) {
+        let samples = self.samples.max(1) as f64;
+        let share = function.samples as f64 / samples;
+        let margin = Z_95 * (share * (1.0 - share) / samples).sqrt();
+        (100.0 * share, 100.0 * margin)
+    }
+}
+
+/// One line per function with its share of the samples, those with too
+/// few samples marked by `?`.
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for function in &self.functions {
+            let (share, margin) = self.share(function);
+            let noise = if function.samples < MIN_SAMPLES {
+                '?'
+            } else {
+                ' '
+            };
+            writeln!(
+                f,
+                "{:>5.1} % ±{:>4.1} {:>8}{} {}",
+                share, margin, function.samples, noise, function.name
+            )?;
+        }
+        if self
+            .functions
+            .iter()
+            .any(|function| function.samples < MIN_SAMPLES)
+        {
+            writeln!(
+                f,
+                "? Fewer than {MIN_SAMPLES} samples, the share is likely noise."
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Counts samples by the function of their instruction pointer.
+#[derive(Debug)]
+pub struct HotFunctions {
+    symbolizer: SharedSymbolizer,
+    /// Samples of each function since the last summary.
+    counts: HashMap<String, u64>,
+    samples: u64,
+}
+
+impl HotFunctions {
+    /// Name the functions of samples by the symbolizer.
+    pub fn new(symbolizer: SharedSymbolizer) -> HotFunctions {
+        HotFunctions {
+            symbolizer,
+            counts: HashMap::new(),
+            samples: 0,
+        }
+    }
+
+    /// Count the sample to its function, reads the mappings of its process
+    /// and the symbols of its file when first seen.
+    pub fn add_sample(&mut self, sample: &Sample) {
+        let name = self
+            .symbolizer
+            .borrow_mut()
+            .frame(sample.pid, sample.ip)
+            .to_string();
+        *self.counts.entry(name).or_default() += 1;
+        self.samples += 1;
+    }
+
+    /// The `top` most sampled functions, starts counting anew.
+    pub fn summary(&mut self, top: usize) -> Summary {
+        let mut functions: Vec<_> = self
+            .counts
+            .drain()
+            .map(|(name, samples)| HotFunction { name, samples })
+            .collect();
+        functions.sort_by(|a, b| b.samples.cmp(&a.samples).then_with(|| a.name.cmp(&b.name)));
+        functions.truncate(top);
+        Summary {
+            samples: std::mem::take(&mut self.samples),
+            functions,
+        }
+    }
+}
+
+#[test]
+fn hot_functions_test() {
+    let mut hot = HotFunctions::new(SharedSymbolizer::default());
+    let pid = std::process::id();
+    let sample = |ip| Sample {
+        pid,
+        ip,
+        ..Default::default()
+    };
+    let function = hot_functions_test as fn() as usize as u64;
+    for ip in [function, function, u64::MAX, 1] {
+        hot.add_sample(&sample(ip));
+    }
+    let summary = hot.summary(2);
+    assert_eq!(summary.samples, 4);
+    assert_eq!(summary.functions.len(), 2);
+    assert!(summary.functions[0].name.contains("hot_functions_test"));
+    assert_eq!(summary.functions[0].samples, 2);
+    assert_eq!(summary.functions[1].name, "[kernel]");
+    assert_eq!(summary.share(&summary.functions[0]), (50.0, 49.0));
+    let report = summary.to_string();
+    assert!(report.starts_with(" 50.0 % ±49.0        2? "), "{report}");
+    assert!(report.ends_with("likely noise.\n"), "{report}");
+    let many = Summary {
+        samples: 100,
+        functions: vec![HotFunction {
+            name: "main".to_owned(),
+            samples: 100,
+        }],
+    };
+    assert_eq!(many.to_string(), "100.0 % ± 0.0      100  main\n");
+    assert_eq!(hot.summary(2), Summary::default());
+}
@@ -0,0 +1,843 @@
+//! Command line interface of tauphi.
+use std::ops::Range;
+use std::time::Duration;
+
+use tauphi_core::error::TauphiError;
+use tauphi_core::event_spec::EventSpec;
+use tauphi_core::export::Pruning;
+use tauphi_core::rotate::RotationPolicy;
+use tauphi_core::sampling::{Backpressure, BufferSize, Event};
+
+use crate::exit::ErrorFormat;
+
+/// Output of `tauphi stat`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StatFormat {
+    /// A table like that of `perf stat`.
+    #[default]
+    Text,
+    /// One JSON object per interval and one of the totals.
+    Json,
+    /// Rows of time, event, value and the share of time counted.
+    Csv,
+}
+
+/// Settings of common sampling scenarios, see [Preset::apply()].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Preset {
+    /// Where the CPU time goes, without the idle task.
+    Cpu,
+    /// What delays the processes, finely sampled with their context
+    /// switches and over time.
+    Latency,
+    /// Which code touches new memory, by page faults.
+    Memory,
+    /// Which code enters the kernel, by system calls.
+    Syscalls,
+    /// Where threads block, by being switched out.
+    Offcpu,
+}
+
+impl Preset {
+    /// Set the events, frequency and reports of the scenario, keeping the
+    /// events, frequency and format given explicitly.
+    ///
+    /// # Arguments
+    /// * `args` - Parsed arguments to complete.
+    /// * `frequency_given` - Whether `--freq` was given.
+    /// * `format_given` - Whether `--format` was given.
+    fn apply(
+        self,
+        args: &mut Args,
+        frequency_given: bool,
+        format_given: bool,
+    ) -> Result<(), TauphiError> {
+        let (events, frequency): (&[&str], usize) = match self {
+            Preset::Cpu => {
+                args.no_idle = true;
+                (&["task-clock"], 99)
+            }
+            Preset::Latency => {
+                args.switches = true;
+                args.timeline = true;
+                (&["task-clock"], 999)
+            }
+            Preset::Memory => (&["page-faults"], 99),
+            Preset::Syscalls => {
+                args.processes = true;
+                (&["raw_syscalls:sys_enter"], 999)
+            }
+            // Every switch, their frequency is what the profile shows.
+            Preset::Offcpu => (&["sched:sched_switch/period=1/"], 99),
+        };
+        if args.events.is_empty() {
+            args.events = events
+                .iter()
+                .map(|event| event.parse())
+                .collect::<Result<_, _>>()?;
+        }
+        if !frequency_given {
+            args.frequency = frequency;
+        }
+        // The summaries of tauphi watch are its only output.
+        if !format_given && !args.watch {
+            args.format = "folded".to_owned();
+        }
+        Ok(())
+    }
+}
+
+impl std::str::FromStr for Preset {
+    type Err = TauphiError;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "cpu" => Ok(Preset::Cpu),
+            "latency" => Ok(Preset::Latency),
+            "memory" => Ok(Preset::Memory),
+            "syscalls" => Ok(Preset::Syscalls),
+            "offcpu" => Ok(Preset::Offcpu),
+            _ => Err(TauphiError::InvalidArgument(format!(
+                "unknown preset '{name}', use one of cpu, latency, memory, syscalls or offcpu"
+            ))),
+        }
+    }
+}
+
+/// Usage text printed for `--help` and on invalid arguments.
+pub const USAGE: &str = "\
+Usage: tauphi [OPTIONS]
+       tauphi doctor        Check the environment for sampling, suggest fixes.
+       tauphi stat [-e EVENT]... [--interval MS] [--per-thread] [--json|--csv]
+                   -- COMMAND [ARGS]...
+                            Count events of the command and its children,
+                            by default task-clock, cycles, instructions,
+                            branches, cache references and their misses.
+       tauphi watch [OPTIONS] [--every SECS]
+                            Print the hottest functions of the sampled
+                            processes every 10 s or as given.
+
+Options:
+  -p, --pid <PID[,PID...]>  Process to sample, can be repeated.
+      --follow-children     Sample also children the processes create.
+      --cgroup <PATH>       Sample processes of the cgroup, relative paths
+                            start at the cgroup v2 root.
+      --container <NAME|ID> Sample processes of the container.
+      --k8s-pod <NAME>      Sample containers of the Kubernetes pod.
+      --k8s-namespace <NS>  Sample containers of pods in the namespace.
+  -u, --uid <USER>          Sample all processes of the user, name or ID.
+  -C, --cpu <CPU>           CPU to sample when no process is given [default: 0].
+      --no-idle             Drop samples of the idle task.
+      --no-kthreads         Drop samples of kernel threads.
+      --include-self        Keep samples of tauphi itself and its children.
+      --dso <NAME>          Keep only samples in the executable or library,
+                            can be repeated.
+      --address-range <START-END>
+                            Keep only samples in the hexadecimal address
+                            range, can be repeated.
+      --exclude-idle        Do not sample idle CPUs at all, not supported
+                            by all events.
+      --kernel-only         Sample only code running in the kernel.
+      --user-only           Sample only code running in user space, does not
+                            require privileges for own processes.
+      --preset <NAME>       Sample a common scenario, sets the events,
+                            frequency, reports and the folded format:
+                            cpu         task-clock at 99 Hz without idle,
+                            latency     task-clock at 999 Hz with
+                                        --switches and --timeline,
+                            memory      page-faults at 99 Hz,
+                            syscalls    raw_syscalls:sys_enter at 999 Hz
+                                        with --processes,
+                            offcpu      every sched:sched_switch.
+                            Events, frequency and format given explicitly
+                            take precedence.
+  -F, --freq <HZ>           Number of samples per second [default: 5].
+  -e, --event <EVENT>       Event triggering the samples, one of task-clock,
+                            page-faults, context-switches, cpu-migrations,
+                            minor-faults, major-faults,
+                            cache-references, cache-misses,
+                            L1-dcache-load-misses, LLC-load-misses,
+                            branches, branch-misses, cycles,
+                            instructions, bpf-output or a
+                            <SUBSYSTEM>:<TRACEPOINT>,
+                            in the syntax of perf, e.g. cycles:u, cycles:pp or
+                            cache-misses/period=10000/, can be repeated to
+                            sample several events at once
+                            [default: task-clock].
+      --bpf-map <PATH>      Pinned perf event array of a BPF program to
+                            receive its bpf-output records.
+  -d, --duration <SECS>     Stop sampling after the given time.
+      --delay <SECS>        Start sampling only after the given time, e.g.
+                            after the target warmed up.
+      --marker-fifo <PATH>  Start a new phase named by each line written
+                            into the named pipe, created if missing.
+      --marker-signal       Start a new phase on each SIGUSR1.
+  -n, --samples <N>         Stop sampling after collecting N samples of the
+                            first event.
+  -f, --format <FORMAT>     Output format of samples, debug, csv, folded
+                            stacks or timeline of samples per process and
+                            second [default: debug].
+  -o, --output <PATH>       Write the output into the file instead of stdout.
+      --rotate-size <MIB>   Continue in a new output file <PATH>.<N> once
+                            the current one is this large.
+      --rotate-interval <SECS>
+                            Continue in a new output file after this time.
+      --keep <N>            Remove the oldest output files beyond N.
+      --keep-size <MIB>     Remove the oldest output files while all of
+                            them are larger.
+      --memory-budget <MIB> Spill folded stacks over the budget to temporary
+                            files, merged at the end.
+      --min-percent <PERCENT>
+                            Leave out folded stacks with fewer samples.
+      --top <N>             Keep only the N folded stacks with most samples.
+      --max-frames <N>      Cut folded stacks to the N innermost frames.
+      --trim-startup        Strip frames which start the program or its
+                            threads, such as _start, __libc_start_main and
+                            std::rt::lang_start, from folded stacks.
+      --max-loss <PERCENT>  Lower the frequency while more samples are lost.
+      --buffer-size <KIB>[/cpu]
+                            Memory for the ring buffers of all samplers, or
+                            of each with /cpu [default: 10 s of samples].
+      --backpressure <POLICY>
+                            When output falls behind: drop new samples,
+                            pause sampling, or queue:<N> more records
+                            [default: drop].
+      --energy              Report energy consumed during the sampling.
+      --timeline            Print when the busiest processes were sampled.
+      --processes           Print the most sampled processes with their
+                            parent, command and when they started or exited.
+      --switches            Sample also every context switch and CPU
+                            migration, print the most switched threads.
+      --containers          Print the most sampled containers and pods with
+                            their busiest processes.
+      --butterfly <FUNCTION>
+                            Print the direct callers and callees of the
+                            function, named as in its symbol table or by
+                            its path, e.g. `parser::parse`.
+      --measure-overhead    Report CPU time used by tauphi and the share of
+                            samples that hit tauphi itself.
+      --header              Print the machine, tauphi version, event,
+                            targets and build IDs of their files before
+                            sampling.
+      --dump-raw <PATH>     Copy the raw records read from the kernel into
+                            the file, for debugging.
+      --replay <PATH>       Process records of a --dump-raw file instead of
+                            sampling.
+      --no-progress         Do not show the live status line, it is shown
+                            only when stderr is a terminal.
+  -v, --verbose             Log what the samplers and the pipeline do,
+                            repeat or use -vv for more details.
+      --interval <MS>       Print the counts of tauphi stat also for each
+                            interval while the command runs.
+      --per-thread          Print cycles, instructions, context switches and
+                            migrations of each thread of the command.
+      --every <SECS>        How often tauphi watch prints the hottest
+                            functions, an `s` suffix is optional [default: 10].
+      --json                Print the counts of tauphi stat as JSON.
+      --csv                 Print the counts of tauphi stat as CSV.
+      --error-format <FORMAT>
+                            Print fatal errors as text or json
+                            [default: text].
+  -h, --help                Print this help.
+
+Exit codes:
+  0  Success.
+  1  Internal error, or failed checks of tauphi doctor.
+  2  Invalid command line.
+  3  Permission denied, e.g. by perf_event_paranoid.
+  4  Process, cgroup, container, user or file not found.
+  5  Event not supported by the kernel or the hardware.";
+
+/// Parsed command line arguments.
+#[derive(Debug, PartialEq)]
+pub struct Args {
+    /// Processes to sample, their samples are merged into one stream.
+    ///
+    /// Empty to sample [Args::cpu] instead.
+    pub pids: Vec<i32>,
+    /// Whether children of [Args::pids] are sampled too.
+    pub follow_children: bool,
+    /// Cgroup whose processes to sample instead of [Args::pids].
+    pub cgroup: Option<String>,
+    /// Container whose processes to sample instead of [Args::pids].
+    pub container: Option<String>,
+    /// Kubernetes pod whose containers to sample.
+    pub k8s_pod: Option<String>,
+    /// Kubernetes namespace whose pods to sample, narrows [Args::k8s_pod].
+    pub k8s_namespace: Option<String>,
+    /// User whose processes to sample, name or numeric ID.
+    pub user: Option<String>,
+    /// CPU to sample if no process was given.
+    pub cpu: i32,
+    /// Whether to drop samples of the idle task.
+    pub no_idle: bool,
+    /// Whether to drop samples of kernel threads.
+    pub no_kthreads: bool,
+    /// Whether samples of tauphi's own processes are kept.
+    pub include_self: bool,
+    /// Executables and libraries whose samples are kept.
+    pub dsos: Vec<String>,
+    /// Address ranges whose samples are kept.
+    pub address_ranges: Vec<Range<u64>>,
+    /// Whether to stop the event on idle CPUs.
+    pub exclude_idle: bool,
+    /// Whether to sample only the kernel.
+    pub kernel_only: bool,
+    /// Whether to sample only user space.
+    pub user_only: bool,
+    /// Number of samples per second to generate.
+    pub frequency: usize,
+    /// Events triggering the samples, each with its own samplers.
+    ///
+    /// Empty to sample the task-clock.
+    pub events: Vec<EventSpec>,
+    /// Stop sampling after this time.
+    pub duration: Option<Duration>,
+    /// Wait this long before sampling.
+    pub delay: Option<Duration>,
+    /// Named pipe delivering phase markers.
+    pub marker_fifo: Option<String>,
+    /// Whether SIGUSR1 marks a new phase.
+    pub marker_signal: bool,
+    /// Stop sampling after collecting this many samples.
+    pub samples: Option<usize>,
+    /// Name of the output format of samples.
+    pub format: String,
+    /// File to write the output into instead of stdout.
+    pub output: Option<String>,
+    /// Rotation and retention of [Args::output].
+    pub rotation: RotationPolicy,
+    /// Memory for aggregated stacks in MiB, unlimited if not given.
+    pub memory_budget: Option<usize>,
+    /// Stacks left out of the folded output.
+    pub pruning: Pruning,
+    /// What happens when the output falls behind.
+    pub backpressure: Backpressure,
+    /// Memory for the ring buffers, sized by the frequency if not given.
+    pub buffer_size: Option<BufferSize>,
+    /// Tolerated percentage of lost samples, enables adaptive frequency.
+    pub max_loss: Option<f64>,
+    /// Whether to report consumed energy.
+    pub energy: bool,
+    /// Whether to print the utilization timeline of processes.
+    pub timeline: bool,
+    /// Whether to print the table of sampled processes.
+    pub processes: bool,
+    /// Whether to sample context switches and migrations of threads.
+    pub switches: bool,
+    /// Whether to print samples grouped by containers.
+    pub containers: bool,
+    /// Function whose callers and callees to print.
+    pub butterfly: Option<String>,
+    /// Whether to report the overhead of tauphi itself.
+    pub measure_overhead: bool,
+    /// Whether to print the recording metadata.
+    pub header: bool,
+    /// Pinned map receiving the samplers of bpf-output events.
+    pub bpf_map: Option<String>,
+    /// File to copy the raw records into.
+    pub dump_raw: Option<String>,
+    /// Raw records to process instead of sampling.
+    pub replay: Option<String>,
+    /// Whether the environment check was requested instead of sampling.
+    pub doctor: bool,
+    /// Whether counting [Args::command] was requested instead of sampling.
+    pub stat: bool,
+    /// Command with its arguments, after `--`.
+    pub command: Vec<String>,
+    /// How the counts are printed.
+    pub stat_format: StatFormat,
+    /// How often to print the counts while the command runs.
+    pub interval: Option<Duration>,
+    /// Whether to print the counts of each thread of the command.
+    pub per_thread: bool,
+    /// Whether periodic summaries were requested instead of an output.
+    pub watch: bool,
+    /// How often to print the summaries of [Args::watch].
+    pub every: Duration,
+    /// Whether only the usage was requested.
+    pub help: bool,
+    /// How fatal errors are printed.
+    pub error_format: ErrorFormat,
+    /// Whether the live status line is hidden.
+    pub no_progress: bool,
+    /// How detailed the logging is, 0 logs only warnings.
+    pub verbosity: u8,
+}
+
+impl Default for Args {
+    fn default() -> Self {
+        Args {
+            pids: Vec::new(),
+            follow_children: false,
+            cgroup: None,
+            container: None,
+            k8s_pod: None,
+            k8s_namespace: None,
+            user: None,
+            cpu: 0,
+            no_idle: false,
+            no_kthreads: false,
+            include_self: false,
+            dsos: Vec::new(),
+            address_ranges: Vec::new(),
+            exclude_idle: false,
+            kernel_only: false,
+            user_only: false,
+            frequency: 5,
+            events: Vec::new(),
+            duration: None,
+            delay: None,
+            marker_fifo: None,
+            marker_signal: false,
+            samples: None,
+            format: "debug".to_owned(),
+            output: None,
+            rotation: RotationPolicy::default(),
+            memory_budget: None,
+            pruning: Pruning::default(),
+            backpressure: Backpressure::default(),
+            buffer_size: None,
+            max_loss: None,
+            energy: false,
+            timeline: false,
+            processes: false,
+            switches: false,
+            containers: false,
+            butterfly: None,
+            measure_overhead: false,
+            header: false,
+            bpf_map: None,
+            dump_raw: None,
+            replay: None,
+            doctor: false,
+            stat: false,
+            command: Vec::new(),
+            stat_format: StatFormat::default(),
+            interval: None,
+            per_thread: false,
+            watch: false,
+            every: Duration::from_secs(10),
+            help: false,
+            no_progress: false,
+            error_format: ErrorFormat::default(),
+            verbosity: 0,
+        }
+    }
+}
+
+impl Args {
+    /// Parse the arguments, without the leading program name.
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Args, TauphiError> {
+        let mut parsed = Args::default();
+        let mut args = args.into_iter().peekable();
+        let mut preset = None;
+        let (mut frequency_given, mut format_given) = (false, false);
+        if args.next_if(|arg| arg == "doctor").is_some() {
+            parsed.doctor = true;
+        } else if args.next_if(|arg| arg == "stat").is_some() {
+            parsed.stat = true;
+        } else if args.next_if(|arg| arg == "watch").is_some() {
+            parsed.watch = true;
+        }
+        while let Some(arg) = args.next() {
+            // Support both `--flag value` and `--flag=value`.
+            let (flag, inline_value) = match arg.split_once('=') {
+                Some((flag, value)) if flag.starts_with("--") => (flag.to_owned(), Some(value)),
+                _ => (arg.clone(), None),
+            };
+            let mut value = || {
+                inline_value
+                    .map(str::to_owned)
+                    .or_else(|| args.next())
+                    .ok_or_else(|| TauphiError::InvalidArgument(format!("{flag} requires a value")))
+            };
+            match flag.as_str() {
+                "-p" | "--pid" => {
+                    for pid in value()?.split(',') {
+                        parsed.pids.push(parse_number(&flag, pid)?);
+                    }
+                }
+                "--follow-children" => parsed.follow_children = true,
+                "--cgroup" => parsed.cgroup = Some(value()?),
+                "--container" => parsed.container = Some(value()?),
+                "--k8s-pod" => parsed.k8s_pod = Some(value()?),
+                "--k8s-namespace" => parsed.k8s_namespace = Some(value()?),
+                "-u" | "--uid" => parsed.user = Some(value()?),
+                "-C" | "--cpu" => parsed.cpu = parse_number(&flag, &value()?)?,
+                "--no-idle" => parsed.no_idle = true,
+                "--no-kthreads" => parsed.no_kthreads = true,
+                "--include-self" => parsed.include_self = true,
+                "--dso" => parsed.dsos.push(value()?),
+                "--address-range" => parsed
+                    .address_ranges
+                    .push(parse_address_range(&flag, &value()?)?),
+                "--exclude-idle" => parsed.exclude_idle = true,
+                "--kernel-only" => parsed.kernel_only = true,
+                "--user-only" => parsed.user_only = true,
+                "-F" | "--freq" => {
+                    parsed.frequency = parse_number(&flag, &value()?)?;
+                    frequency_given = true;
+                }
+                "--preset" => preset = Some(value()?.parse::<Preset>()?),
+                "-e" | "--event" => parsed.events.push(value()?.parse()?),
+                "--delay" => {
+                    let secs: f64 = parse_number(&flag, &value()?)?;
+                    parsed.delay = Some(Duration::try_from_secs_f64(secs).map_err(|_| {
+                        TauphiError::InvalidArgument(format!("invalid value '{secs}' for {flag}"))
+                    })?);
+                }
+                "--marker-fifo" => parsed.marker_fifo = Some(value()?),
+                "--marker-signal" => parsed.marker_signal = true,
+                "-d" | "--duration" => {
+                    let secs: f64 = parse_number(&flag, &value()?)?;
+                    parsed.duration = Some(Duration::try_from_secs_f64(secs).map_err(|_| {
+                        TauphiError::InvalidArgument(format!("invalid value '{secs}' for {flag}"))
+                    })?);
+                }
+                "-n" | "--samples" => parsed.samples = Some(parse_number(&flag, &value()?)?),
+                "-f" | "--format" => {
+                    parsed.format = value()?;
+                    format_given = true;
+                }
+                "-o" | "--output" => parsed.output = Some(value()?),
+                "--rotate-size" => {
+                    let mib: u64 = parse_number(&flag, &value()?)?;
+                    parsed.rotation.max_size = Some(mib << 20);
+                }
+                "--rotate-interval" => {
+                    let secs: f64 = parse_number(&flag, &value()?)?;
+                    parsed.rotation.max_age =
+                        Some(Duration::try_from_secs_f64(secs).map_err(|_| {
+                            TauphiError::InvalidArgument(format!(
+                                "invalid value '{secs}' for {flag}"
+                            ))
+                        })?);
+                }
+                "--keep" => parsed.rotation.keep_files = Some(parse_number(&flag, &value()?)?),
+                "--keep-size" => {
+                    let mib: u64 = parse_number(&flag, &value()?)?;
+                    parsed.rotation.keep_bytes = Some(mib << 20);
+                }
+                "--memory-budget" => parsed.memory_budget = Some(parse_number(&flag, &value()?)?),
+                "--min-percent" => {
+                    let percent: f64 = parse_number(&flag, &value()?)?;
+                    parsed.pruning.min_share = Some(percent / 100.0);
+                }
+                "--top" => parsed.pruning.top = Some(parse_number(&flag, &value()?)?),
+                "--max-frames" => parsed.pruning.max_frames = Some(parse_number(&flag, &value()?)?),
+                "--trim-startup" => parsed.pruning.trim_startup = true,
+                "--backpressure" => parsed.backpressure = value()?.parse()?,
+                "--buffer-size" => parsed.buffer_size = Some(value()?.parse()?),
+                "--max-loss" => parsed.max_loss = Some(parse_number(&flag, &value()?)?),
+                "--energy" => parsed.energy = true,
+                "--timeline" => parsed.timeline = true,
+                "--processes" => parsed.processes = true,
+                "--switches" => parsed.switches = true,
+                "--containers" => parsed.containers = true,
+                "--butterfly" => parsed.butterfly = Some(value()?),
+                "--measure-overhead" => parsed.measure_overhead = true,
+                "--header" => parsed.header = true,
+                "--bpf-map" => parsed.bpf_map = Some(value()?),
+                "--dump-raw" => parsed.dump_raw = Some(value()?),
+                "--replay" => parsed.replay = Some(value()?),
+                "-h" | "--help" => parsed.help = true,
+                "--no-progress" => parsed.no_progress = true,
+                "--error-format" => parsed.error_format = value()?.parse()?,
+                "--every" => {
+                    let value = value()?;
+                    let secs: f64 = parse_number(&flag, value.strip_suffix('s').unwrap_or(&value))?;
+                    parsed.every = Duration::try_from_secs_f64(secs)
+                        .ok()
+                        .filter(|every| !every.is_zero())
+                        .ok_or_else(|| {
+                            TauphiError::InvalidArgument(format!(
+                                "invalid value '{value}' for {flag}"
+                            ))
+                        })?;
+                }
+                "--json" => parsed.stat_format = StatFormat::Json,
+                "--csv" => parsed.stat_format = StatFormat::Csv,
+                "--per-thread" => parsed.per_thread = true,
+                "--interval" => {
+                    let ms: u64 = parse_number(&flag, &value()?)?;
+                    if ms == 0 {
+                        return Err(TauphiError::InvalidArgument(
+                            "--interval must be positive".to_owned(),
+                        ));
+                    }
+                    parsed.interval = Some(Duration::from_millis(ms));
+                }
+                "--" => parsed.command = args.by_ref().collect(),
+                "-v" | "--verbose" => parsed.verbosity += 1,
+                "-vv" => parsed.verbosity += 2,
+                _ => {
+                    return Err(TauphiError::InvalidArgument(format!(
+                        "unknown argument '{arg}'"
+                    )))
+                }
+            }
+        }
+        if let Some(preset) = preset {
+            preset.apply(&mut parsed, frequency_given, format_given)?;
+        }
+        let targets = [
+            !parsed.pids.is_empty(),
+            parsed.cgroup.is_some(),
+            parsed.container.is_some(),
+            parsed.k8s_pod.is_some() || parsed.k8s_namespace.is_some(),
+            parsed.user.is_some(),
+        ];
+        if targets.into_iter().filter(|&given| given).count() > 1 {
+            return Err(TauphiError::InvalidArgument(
+                "--pid, --cgroup, --container, --k8s-* and --uid are mutually exclusive".to_owned(),
+            ));
+        }
+        if parsed.output.is_none() && parsed.rotation != RotationPolicy::default() {
+            return Err(TauphiError::InvalidArgument(
+                "--rotate-* and --keep* require --output".to_owned(),
+            ));
+        }
+        if parsed.stat == parsed.command.is_empty() {
+            return Err(TauphiError::InvalidArgument(
+                "tauphi stat requires a command after --".to_owned(),
+            ));
+        }
+        let stat_options = parsed.stat_format != StatFormat::Text
+            || parsed.interval.is_some()
+            || parsed.per_thread;
+        if stat_options && !parsed.stat {
+            return Err(TauphiError::InvalidArgument(
+                "--json, --csv, --interval and --per-thread require tauphi stat".to_owned(),
+            ));
+        }
+        if parsed.every != Duration::from_secs(10) && !parsed.watch {
+            return Err(TauphiError::InvalidArgument(
+                "--every requires tauphi watch".to_owned(),
+            ));
+        }
+        if parsed.watch && (parsed.output.is_some() || parsed.format != Args::default().format) {
+            return Err(TauphiError::InvalidArgument(
+                "tauphi watch does not support --output and --format".to_owned(),
+            ));
+        }
+        if parsed.per_thread && parsed.stat_format == StatFormat::Csv {
+            return Err(TauphiError::InvalidArgument(
+                "--per-thread does not support --csv".to_owned(),
+            ));
+        }
+        if parsed.dump_raw.is_some() && parsed.replay.is_some() {
+            return Err(TauphiError::InvalidArgument(
+                "--dump-raw and --replay are mutually exclusive".to_owned(),
+            ));
+        }
+        let bpf_output = parsed
+            .events
+            .iter()
+            .any(|spec| spec.event == Event::BpfOutput);
+        if parsed.bpf_map.is_some() && !bpf_output {
+            return Err(TauphiError::InvalidArgument(
+                "--bpf-map requires the bpf-output event".to_owned(),
+            ));
+        }
+        let modifiers = parsed
+            .events
+            .iter()
+            .map(|spec| (spec.kernel_only, spec.user_only));
+        for (kernel_only, user_only) in modifiers.chain([(false, false)]) {
+            if (parsed.kernel_only || kernel_only) && (parsed.user_only || user_only) {
+                return Err(TauphiError::InvalidArgument(
+                    "--kernel-only and --user-only are mutually exclusive".to_owned(),
+                ));
+            }
+        }
+        Ok(parsed)
+    }
+}
+
+/// Parse a numeric value of the given flag.
+fn parse_number<T: std::str::FromStr>(flag: &str, value: &str) -> Result<T, TauphiError> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| TauphiError::InvalidArgument(format!("invalid value '{value}' for {flag}")))
+}
+
+/// Parse a `START-END` range of hexadecimal addresses, `0x` is optional.
+fn parse_address_range(flag: &str, value: &str) -> Result<Range<u64>, TauphiError> {
+    let invalid = || TauphiError::InvalidArgument(format!("invalid value '{value}' for {flag}"));
+    let address = |address: &str| {
+        let address = address.trim();
+        let digits = address.strip_prefix("0x").unwrap_or(address);
+        u64::from_str_radix(digits, 16).map_err(|_| invalid())
+    };
+    let (start, end) = value.split_once('-').ok_or_else(invalid)?;
+    let range = address(start)?..address(end)?;
+    if range.is_empty() {
+        return Err(invalid());
+    }
+    Ok(range)
+}
+
+#[test]
+fn parse_multiple_pids_test() {
+    let args = Args::parse(
+        ["-p", "1,2", "--pid", "3", "--pid=4", "-F", "99"]
+            .into_iter()
+            .map(String::from),
+    )
+    .unwrap();
+    assert_eq!(args.pids, vec![1, 2, 3, 4]);
+    assert_eq!(args.frequency, 99);
+    assert!(Args::parse(["-p", "x"].into_iter().map(String::from)).is_err());
+}
+
+#[test]
+fn parse_exclusive_targets_test() {
+    let args = Args::parse(["--container", "web"].into_iter().map(String::from)).unwrap();
+    assert_eq!(args.container.as_deref(), Some("web"));
+    assert!(Args::parse(["-p", "1", "--cgroup=a"].into_iter().map(String::from)).is_err());
+}
+
+#[test]
+fn parse_rotation_test() {
+    let args = Args::parse(
+        ["-o", "out.csv", "--rotate-size", "2", "--keep=3"]
+            .into_iter()
+            .map(String::from),
+    )
+    .unwrap();
+    assert_eq!(args.rotation.max_size, Some(2 << 20));
+    assert_eq!(args.rotation.keep_files, Some(3));
+    assert!(Args::parse(["--keep", "3"].into_iter().map(String::from)).is_err());
+}
+
+#[test]
+fn parse_doctor_test() {
+    let args = Args::parse(["doctor"].into_iter().map(String::from)).unwrap();
+    assert!(args.doctor);
+    assert!(Args::parse(["-p", "1", "doctor"].into_iter().map(String::from)).is_err());
+}
+
+#[test]
+fn parse_stat_test() {
+    let args = Args::parse(
+        [
+            "stat",
+            "-e",
+            "cycles",
+            "--interval",
+            "100",
+            "--json",
+            "--",
+            "make",
+            "-j",
+            "4",
+        ]
+        .into_iter()
+        .map(String::from),
+    )
+    .unwrap();
+    assert!(args.stat);
+    assert_eq!(args.stat_format, StatFormat::Json);
+    assert_eq!(args.interval, Some(Duration::from_millis(100)));
+    assert_eq!(args.command, ["make", "-j", "4"]);
+    assert_eq!(args.events[0].event, Event::Cycles);
+    assert!(Args::parse(["stat"].into_iter().map(String::from)).is_err());
+    assert!(Args::parse(["--", "make"].into_iter().map(String::from)).is_err());
+    assert!(Args::parse(["--csv"].into_iter().map(String::from)).is_err());
+}
+
+#[test]
+fn parse_watch_test() {
+    let args = Args::parse(
+        ["watch", "-p", "42", "--every", "2.5s"]
+            .into_iter()
+            .map(String::from),
+    )
+    .unwrap();
+    assert!(args.watch);
+    assert_eq!(args.pids, [42]);
+    assert_eq!(args.every, Duration::from_millis(2500));
+    let parse = |args: &[&str]| Args::parse(args.iter().map(|&arg| arg.to_owned()));
+    assert_eq!(
+        parse(&["watch", "--every", "3"]).unwrap().every,
+        Duration::from_secs(3)
+    );
+    assert!(parse(&["watch", "--every", "0s"]).is_err());
+    assert!(parse(&["--every", "3"]).is_err());
+    assert!(parse(&["watch", "-o", "out.folded"]).is_err());
+}
+
+#[test]
+fn parse_preset_test() {
+    let parse = |args: &[&str]| Args::parse(args.iter().map(|&arg| arg.to_owned()));
+    let cpu = parse(&["--preset", "cpu", "-p", "1"]).unwrap();
+    assert_eq!(cpu.events[0].event, Event::TaskClock);
+    assert_eq!((cpu.frequency, cpu.format.as_str()), (99, "folded"));
+    assert!(cpu.no_idle);
+
+    // Explicit options win regardless of their order.
+    let latency = parse(&[
+        "-F",
+        "5",
+        "-e",
+        "page-faults",
+        "--preset=latency",
+        "-f",
+        "csv",
+    ])
+    .unwrap();
+    assert_eq!(latency.events.len(), 1);
+    assert_eq!(latency.events[0].event, Event::PageFaults);
+    assert_eq!((latency.frequency, latency.format.as_str()), (5, "csv"));
+    assert!(latency.switches && latency.timeline);
+
+    assert!(parse(&["--preset", "fast"]).is_err());
+}
+
+#[test]
+fn parse_verbosity_test() {
+    let args = Args::parse(["-v", "--verbose", "-vv"].into_iter().map(String::from)).unwrap();
+    assert_eq!(args.verbosity, 4);
+}
+
+#[test]
+fn parse_address_range_test() {
+    let args = Args::parse(
+        ["--address-range", "0x1000-2000", "--dso", "libc.so"]
+            .into_iter()
+            .map(String::from),
+    )
+    .unwrap();
+    assert_eq!(args.address_ranges, vec![0x1000..0x2000]);
+    assert_eq!(args.dsos, ["libc.so"]);
+    assert!(parse_address_range("--address-range", "0x2000-0x1000").is_err());
+    assert!(parse_address_range("--address-range", "0x1000").is_err());
+}
+
+#[test]
+fn parse_event_spec_test() {
+    let args = Args::parse(
+        ["-e", "cache-misses/period=10000/:u", "-e", "cycles"]
+            .into_iter()
+            .map(String::from),
+    )
+    .unwrap();
+    let events: Vec<_> = args.events.iter().map(|spec| spec.event).collect();
+    assert_eq!(events, [Event::CacheMisses, Event::Cycles]);
+    assert_eq!(args.events[0].period, Some(10000));
+    assert!(args.events[0].user_only);
+    assert!(Args::parse(
+        ["-e", "cycles:k", "--user-only"]
+            .into_iter()
+            .map(String::from)
+    )
+    .is_err());
+    assert!(Args::parse(
+        ["--bpf-map", "/sys/fs/bpf/events"]
+            .into_iter()
+            .map(String::from)
+    )
+    .is_err());
+}
@@ -0,0 +1,190 @@
+//! Exit codes of tauphi and reporting of the errors ending it.
+use std::io;
+use std::process;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use perf_event::error::PerfError;
+use tauphi_core::error::TauphiError;
+
+/// Documented exit codes, listed in [crate::cli::USAGE].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitCode {
+    /// Internal or otherwise unclassified error.
+    Failure = 1,
+    /// Invalid command line.
+    Usage = 2,
+    /// Missing privileges, e.g. because of `perf_event_paranoid`.
+    PermissionDenied = 3,
+    /// The process, cgroup, container, user or a file does not exist.
+    TargetNotFound = 4,
+    /// The kernel or the hardware does not support the requested sampling.
+    Unsupported = 5,
+}
+
+impl ExitCode {
+    /// Classify the error.
+    pub fn of(err: &TauphiError) -> ExitCode {
+        match err {
+            TauphiError::InvalidArgument(_) => ExitCode::Usage,
+            TauphiError::TargetNotFound(_) => ExitCode::TargetNotFound,
+            TauphiError::IO(err) => Self::of_io(err),
+            TauphiError::Perf(PerfError::FailedOpen(err))
+            | TauphiError::Sampler {
+                source: PerfError::FailedOpen(err),
+                ..
+            } => Self::of_open(err),
+            TauphiError::Sampler { .. } => ExitCode::Failure,
+            TauphiError::File { source, .. } => Self::of_io(source),
+            TauphiError::Perf(PerfError::FailedIO(err)) => Self::of_io(err),
+            TauphiError::Perf(_) | TauphiError::MalformedRecord(_) => ExitCode::Failure,
+        }
+    }
+
+    /// Classify a failed file operation.
+    fn of_io(err: &io::Error) -> ExitCode {
+        match err.kind() {
+            io::ErrorKind::PermissionDenied => ExitCode::PermissionDenied,
+            io::ErrorKind::NotFound => ExitCode::TargetNotFound,
+            _ => ExitCode::Failure,
+        }
+    }
+
+    /// Classify a failed `perf_event_open()`, its error numbers mean more
+    /// than those of files.
+    fn of_open(err: &io::Error) -> ExitCode {
+        match err.raw_os_error() {
+            Some(libc::EACCES | libc::EPERM) => ExitCode::PermissionDenied,
+            Some(libc::ESRCH) => ExitCode::TargetNotFound,
+            // Unknown event, PMU or attribute.
+            Some(libc::ENOENT | libc::ENODEV | libc::EOPNOTSUPP | libc::ENOSYS) => {
+                ExitCode::Unsupported
+            }
+            _ => ExitCode::Failure,
+        }
+    }
+
+    /// Identifier of the code in machine-readable errors.
+    pub fn name(self) -> &'static str {
+        match self {
+            ExitCode::Failure => "failure",
+            ExitCode::Usage => "usage",
+            ExitCode::PermissionDenied => "permission_denied",
+            ExitCode::TargetNotFound => "target_not_found",
+            ExitCode::Unsupported => "unsupported",
+        }
+    }
+}
+
+/// How fatal errors are printed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ErrorFormat {
+    /// A sentence for humans.
+    #[default]
+    Text,
+    /// One JSON object per line, for wrapper scripts.
+    Json,
+}
+
+impl FromStr for ErrorFormat {
+    type Err = TauphiError;
+
+    /// Parse `text` or `json`.
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format {
+            "text" => Ok(ErrorFormat::Text),
+            "json" => Ok(ErrorFormat::Json),
+            _ => Err(TauphiError::InvalidArgument(format!(
+                "unknown error format '{format}'"
+            ))),
+        }
+    }
+}
+
+/// Whether the arguments ask for JSON errors, for command lines which
+/// failed to parse otherwise.
+pub fn requests_json(args: &[String]) -> bool {
+    args.iter().any(|arg| arg == "--error-format=json")
+        || args
+            .windows(2)
+            .any(|pair| pair[0] == "--error-format" && pair[1] == "json")
+}
+
+/// Whether [fail()] prints JSON, set once the arguments are known.
+static JSON: AtomicBool = AtomicBool::new(false);
+
+/// Print fatal errors in the format from now on.
+pub fn set_format(format: ErrorFormat) {
+    JSON.store(format == ErrorFormat::Json, Ordering::Relaxed);
+}
+
+/// Print the error and exit with its code.
+pub fn fail(code: ExitCode, message: &str) -> ! {
+    if JSON.load(Ordering::Relaxed) {
+        eprintln!("{}", to_json(code, message));
+    } else {
+        eprintln!("{message}");
+    }
+    process::exit(code as i32)
+}
+
+/// Print `context: err` and exit with the code of `err`.
+pub fn fail_with(context: &str, err: &TauphiError) -> ! {
+    fail(ExitCode::of(err), &format!("{context}: {err}"))
+}
+
+fn to_json(code: ExitCode, message: &str) -> String {
+    format!(
+        "{{\"error\":{},\"kind\":\"{}\",\"exit_code\":{}}}",
+        json_string(message),
+        code.name(),
+        code as i32
+    )
+}
+
+/// Quote the text as a JSON string.
+pub fn json_string(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len() + 2);
+    escaped.push('"');
+    for character in text.chars() {
+        match character {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            character if character.is_control() => {
+                escaped.push_str(&format!("\\u{:04x}", character as u32))
+            }
+            character => escaped.push(character),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+#[test]
+fn exit_code_test() {
+    let open =
+        |errno| TauphiError::Perf(PerfError::FailedOpen(io::Error::from_raw_os_error(errno)));
+    assert_eq!(
+        ExitCode::of(&open(libc::EACCES)),
+        ExitCode::PermissionDenied
+    );
+    assert_eq!(ExitCode::of(&open(libc::ESRCH)), ExitCode::TargetNotFound);
+    assert_eq!(ExitCode::of(&open(libc::ENOENT)), ExitCode::Unsupported);
+    let missing = io::Error::from(io::ErrorKind::NotFound);
+    assert_eq!(ExitCode::of(&missing.into()), ExitCode::TargetNotFound);
+    assert_eq!(
+        to_json(ExitCode::Usage, "bad \"-p\"\n"),
+        r#"{"error":"bad \"-p\"\n","kind":"usage","exit_code":2}"#
+    );
+}
+
+#[test]
+fn requests_json_test() {
+    let requests =
+        |args: &[&str]| requests_json(&args.iter().map(|&arg| arg.into()).collect::<Vec<_>>());
+    assert!(requests(&["tauphi", "--bogus", "--error-format", "json"]));
+    assert!(requests(&["tauphi", "--error-format=json", "--bogus"]));
+    assert!(!requests(&["tauphi", "--error-format=text", "--bogus"]));
+    assert!(!requests(&["tauphi", "--error-format", "--bogus"]));
+}
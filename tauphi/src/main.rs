@@ -1,12 +1,956 @@
-pub mod error;
-pub mod sampling;
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::{env, future, io, process};
+
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::mpsc;
+use tokio::time;
+
+use tauphi_core::event_spec::EventSpec;
+use tauphi_core::export::{ExporterRegistry, FoldedExporter};
+use tauphi_core::pipeline::Pipeline;
+use tauphi_core::pool::SamplePool;
+use tauphi_core::replay::{RawDump, ReplayBackend};
+use tauphi_core::rotate::RotatingSink;
+use tauphi_core::sink::{CountingWriter, DebugSink, SampleSink};
+use tauphi_core::symbols::SharedSymbolizer;
+use tauphi_core::{
+    adaptive, bpf, butterfly, cgroup, clock, containers, doctor, energy, error, filter, jvm,
+    metadata, overhead, phase, processes, progress, sampling, stat, switches, timeline, watch,
+};
+
+use crate::exit::ExitCode;
+
+pub mod cli;
+pub mod exit;
+pub mod reports;
 
 #[tokio::main]
 async fn main() {
-    let sampler = sampling::Sampler::new_cpu(0, 5).expect("Failed to start the sampling.");
-    let sampler = sampling::AsyncSampler::from_sync(sampler).unwrap();
-    for i in 1..10 {
-        let sample = sampler.get_sample().await.unwrap();
-        println!("#{i} {:#?}", sample);
+    let args = cli::Args::parse(env::args().skip(1)).unwrap_or_else(|err| {
+        // The arguments are invalid, the requested format is looked up alone.
+        let arguments: Vec<_> = env::args().collect();
+        if exit::requests_json(&arguments) {
+            exit::set_format(exit::ErrorFormat::Json);
+            exit::fail(ExitCode::Usage, &err.to_string());
+        }
+        exit::fail(ExitCode::Usage, &format!("{err}\n\n{}", cli::USAGE));
+    });
+    exit::set_format(args.error_format);
+    if args.help {
+        println!("{}", cli::USAGE);
+        return;
+    }
+    let level = match args.verbosity {
+        0 => tracing::Level::WARN,
+        1 => tracing::Level::INFO,
+        2 => tracing::Level::DEBUG,
+        _ => tracing::Level::TRACE,
+    };
+    tracing_subscriber::fmt()
+        .with_max_level(level)
+        .with_writer(io::stderr)
+        .init();
+    if args.doctor {
+        let healthy = print_checks(&doctor::run_checks());
+        process::exit(if healthy { 0 } else { 1 });
+    }
+    if args.stat {
+        run_stat(&args);
+        return;
+    }
+
+    let mut options = sampling::SamplerOptions {
+        event: sampling::Event::default(),
+        frequency: args.frequency,
+        period: None,
+        follow_children: args.follow_children,
+        exclude_idle: args.exclude_idle,
+        kernel_only: args.kernel_only,
+        user_only: args.user_only,
+        precise: 0,
+        backpressure: args.backpressure,
+        buffer_size: None,
+        task_events: args.processes,
+        cgroup_events: args.containers,
+        pool: SamplePool::new(SAMPLE_POOL_CAPACITY),
+        dump: args.dump_raw.as_ref().map(|path| {
+            RawDump::create(path)
+                .unwrap_or_else(|err| exit::fail_with(&format!("Failed to create {path}"), &err))
+        }),
+    };
+    // Options of each sampled event, they share the pool and the dump.
+    let mut events = match args.events.as_slice() {
+        [] => vec![EventSpec::default()],
+        events => events.to_vec(),
+    };
+    if args.switches {
+        // Every switch and migration is a sample.
+        for event in [
+            sampling::Event::ContextSwitches,
+            sampling::Event::CpuMigrations,
+        ] {
+            events.push(EventSpec {
+                event,
+                period: Some(1),
+                ..Default::default()
+            });
+        }
+    }
+    let mut event_options: Vec<_> = events
+        .iter()
+        .enumerate()
+        .map(|(index, spec)| {
+            let mut options = options.clone();
+            spec.apply(&mut options);
+            // Samplers of the other events would see the same tasks.
+            options.task_events &= index == 0;
+            options.cgroup_events &= index == 0;
+            options
+        })
+        .collect();
+    options = event_options[0].clone();
+    if args.header {
+        eprintln!(
+            "{}",
+            metadata::RecordingMetadata::capture(&options, &args.pids)
+        );
+    }
+    // Reports and the folded stacks name frames by the same symbols.
+    let symbolizer = SharedSymbolizer::default();
+    for &pid in &args.pids {
+        if let Some(advice) = jvm::advise(pid) {
+            eprintln!("{advice}");
+        } else if let Some(jit) = symbolizer.borrow_mut().maps().anonymous_jit(pid as u32) {
+            eprintln!("Frames in {jit} cannot be named without a perf map.");
+        }
+    }
+    let cgroups = resolve_cgroups(&args)
+        .unwrap_or_else(|err| exit::fail(ExitCode::of(&err), &err.to_string()));
+    let mut filter = filter::SampleFilter::default();
+    if let Some(user) = &args.user {
+        let uid = filter::resolve_user(user)
+            .unwrap_or_else(|err| exit::fail(ExitCode::of(&err), &err.to_string()));
+        filter = filter.with_uid(uid);
+    }
+    if args.no_idle {
+        filter = filter.without_idle();
+    }
+    if args.no_kthreads {
+        filter = filter.without_kernel_threads();
+    }
+    if !args.include_self {
+        filter = filter.without_process_tree(process::id());
+    }
+    for dso in &args.dsos {
+        filter = filter.with_dso(dso);
+    }
+    for range in &args.address_ranges {
+        filter = filter.with_address_range(range.clone());
+    }
+    // Pairs of (CPU, PID) to open a sampler for.
+    let targets: Vec<(i32, i32)> = if !cgroups.is_empty() {
+        Vec::new()
+    } else if args.user.is_some() {
+        // Processes of the user come and go, sample everything and filter.
+        (0..sampling::num_cpus() as i32)
+            .map(|cpu| (cpu, -1))
+            .collect()
+    } else if args.pids.is_empty() {
+        vec![(args.cpu, -1)]
+    } else if args.follow_children {
+        let cpus = 0..sampling::num_cpus() as i32;
+        args.pids
+            .iter()
+            .flat_map(|&pid| cpus.clone().map(move |cpu| (cpu, pid)))
+            .collect()
+    } else {
+        args.pids.iter().map(|&pid| (-1, pid)).collect()
+    };
+    if let Some(buffer_size) = args.buffer_size {
+        // Split the budget across the samplers of all events.
+        let samplers: usize = event_options
+            .iter()
+            .map(|options| match options.event {
+                sampling::Event::BpfOutput => sampling::num_cpus(),
+                _ => targets.len() + cgroups.len() * sampling::num_cpus(),
+            })
+            .sum();
+        let bytes = buffer_size
+            .per_sampler(samplers)
+            .unwrap_or_else(|err| exit::fail(ExitCode::of(&err), &err.to_string()));
+        for options in &mut event_options {
+            options.buffer_size = Some(bytes);
+        }
+    }
+    if let Some(delay) = args.delay {
+        tracing::info!("delaying the sampling by {:?}", delay);
+        time::sleep(delay).await;
+    }
+    tracing::info!(
+        targets = targets.len(),
+        cgroups = cgroups.len(),
+        events = events.len(),
+        event = options.event.name(),
+        frequency = args.frequency,
+        "starting the sampling"
+    );
+    let mut pipeline = match &args.replay {
+        Some(path) => {
+            let backend = ReplayBackend::open(path)
+                .unwrap_or_else(|err| exit::fail_with(&format!("Failed to open {path}"), &err));
+            let sampler = sampling::Sampler::with_backend(backend, &options);
+            Pipeline::spawn(vec![sampler], PIPELINE_CAPACITY)
+        }
+        None => {
+            let bpf_map = args.bpf_map.as_ref().map(|path| {
+                bpf::PerfEventArray::open(Path::new(path)).unwrap_or_else(|err| {
+                    exit::fail_with(&format!("Failed to open the BPF map {path}"), &err)
+                })
+            });
+            let samplers = event_options
+                .iter()
+                .flat_map(|options| match options.event {
+                    sampling::Event::BpfOutput => open_bpf_output(options, bpf_map.as_ref()),
+                    _ => open_samplers(targets.clone(), &cgroups, options),
+                })
+                .collect();
+            let samplers = started(samplers);
+            warn_skid(&samplers, &event_options);
+            Pipeline::spawn(samplers, PIPELINE_CAPACITY)
+        }
+    };
+
+    let mut reports = reports::Reports::new(options.event);
+    reports.phases = (args.marker_fifo.is_some() || args.marker_signal)
+        .then(|| phase::PhaseTracker::new("start", clock::monotonic_now()));
+    let (marker_sender, mut markers) = mpsc::unbounded_channel();
+    let _marker_fifo = args.marker_fifo.as_ref().map(|path| {
+        phase::MarkerFifo::open(path, move |phase| {
+            let _ = marker_sender.send(phase);
+        })
+        .unwrap_or_else(|err| exit::fail_with(&format!("Failed to open {path}"), &err))
+    });
+    let mut marker_signal = args
+        .marker_signal
+        .then(|| signal(SignalKind::user_defined1()).expect("Failed to listen for signals."));
+    let mut signal_markers = 0;
+
+    let deadline = async {
+        match args.duration {
+            Some(duration) => time::sleep(duration).await,
+            None => future::pending().await,
+        }
+    };
+    let shutdown = shutdown_signal();
+    tokio::pin!(deadline, shutdown);
+    let mut controller = args
+        .max_loss
+        .map(|max_loss| adaptive::FrequencyController::new(args.frequency, max_loss / 100.0));
+    let mut adjust_interval = time::interval(ADJUST_PERIOD);
+
+    let mut overhead_meter = args.measure_overhead.then(overhead::OverheadMeter::start);
+    let meter = args.energy.then(|| {
+        energy::EnergyMeter::start()
+            .unwrap_or_else(|err| exit::fail_with("Failed to open RAPL energy counters", &err))
+    });
+    let recording_start = clock::monotonic_now();
+    reports.processes = args.processes.then(processes::ProcessTable::default);
+    reports.switches = args.switches.then(switches::SwitchTable::default);
+    reports.symbolizer = (args.watch || args.butterfly.is_some() || args.pruning.trim_startup)
+        .then(|| symbolizer.clone());
+    reports.hot_functions = args
+        .watch
+        .then(|| watch::HotFunctions::new(symbolizer.clone()));
+    let mut watch_interval = time::interval_at(time::Instant::now() + args.every, args.every);
+    reports.containers = args.containers.then(containers::ContainerTable::default);
+    reports.butterfly = args
+        .butterfly
+        .as_deref()
+        .map(|function| butterfly::Butterfly::new(function, symbolizer.clone()));
+    reports.timeline = args
+        .timeline
+        .then(|| timeline::Timeline::new(TIMELINE_BUCKET));
+
+    let mut exporters = ExporterRegistry::with_builtin();
+    let mut folded = FoldedExporter::default()
+        .with_pruning(args.pruning)
+        .with_symbolizer(symbolizer);
+    if let Some(mib) = args.memory_budget {
+        folded = folded.with_memory_budget(mib << 20);
+    }
+    exporters.register(Box::new(folded));
+    let Some(exporter) = exporters.get(&args.format) else {
+        let formats: Vec<_> = exporters.names().collect();
+        exit::fail(
+            ExitCode::Usage,
+            &format!(
+                "Unknown format '{}', use one of {}.",
+                args.format,
+                formats.join(", ")
+            ),
+        );
+    };
+    // Bytes of output written so far.
+    let (mut sink, written): (Box<dyn SampleSink>, _) = match &args.output {
+        // The summaries are the only output.
+        None if args.watch => (Box::new(DebugSink::new(io::sink())), Rc::new(Cell::new(0))),
+        Some(path) => {
+            let sink = RotatingSink::new(path, args.rotation, exporter)
+                .unwrap_or_else(|err| exit::fail_with(&format!("Failed to create {path}"), &err));
+            let written = sink.written();
+            (Box::new(sink), written)
+        }
+        None => {
+            let written = Rc::new(Cell::new(0));
+            let stdout = CountingWriter::new(io::stdout(), written.clone());
+            (exporter.sink(Box::new(stdout)), written)
+        }
+    };
+    let mut progress_meter =
+        (!args.no_progress && io::stderr().is_terminal()).then(progress::ProgressMeter::start);
+    let mut progress_interval =
+        time::interval_at(time::Instant::now() + PROGRESS_PERIOD, PROGRESS_PERIOD);
+    let mut num_samples = 0;
+    let below_limit = |num_samples| args.samples.map_or(true, |limit| num_samples < limit);
+    while below_limit(num_samples) {
+        let record = tokio::select! {
+            record = pipeline.recv() => record
+                .unwrap_or_else(|err| exit::fail_with("Sampling failed", &err)),
+            _ = &mut deadline => break,
+            result = &mut shutdown => {
+                result.expect("Failed to listen for signals.");
+                eprintln!("Interrupted, flushing pending samples.");
+                break;
+            }
+            _ = adjust_interval.tick(), if controller.is_some() => {
+                let controller = controller.as_mut().unwrap();
+                if let Some(frequency) = controller.adjust() {
+                    eprintln!("Changing sampling frequency to {frequency} Hz.");
+                    pipeline.set_frequency(frequency);
+                }
+                continue;
+            }
+            Some(phase) = markers.recv() => {
+                tracing::info!("starting phase {}", phase.name);
+                reports.phases.as_mut().unwrap().mark(phase.name, phase.start);
+                continue;
+            }
+            _ = async { marker_signal.as_mut().unwrap().recv().await }, if marker_signal.is_some() => {
+                signal_markers += 1;
+                let name = format!("signal-{signal_markers}");
+                tracing::info!("starting phase {}", name);
+                reports.phases.as_mut().unwrap().mark(name, clock::monotonic_now());
+                continue;
+            }
+            _ = watch_interval.tick(), if reports.hot_functions.is_some() => {
+                print_hot_functions(reports.hot_functions.as_mut().unwrap(), recording_start);
+                continue;
+            }
+            _ = progress_interval.tick(), if progress_meter.is_some() => {
+                let progress = progress_meter
+                    .as_mut()
+                    .unwrap()
+                    .progress(written.get(), pipeline.stats().buffer_peak);
+                // Overwrite the previous status line.
+                eprint!("\r{progress}\x1b[K");
+                continue;
+            }
+        };
+        match record {
+            Some(record) => {
+                if let Some(controller) = controller.as_mut() {
+                    controller.observe(&record);
+                }
+                if let Some(overhead_meter) = overhead_meter.as_mut() {
+                    overhead_meter.observe(&record);
+                }
+                if let Some(progress_meter) = progress_meter.as_mut() {
+                    progress_meter.observe(&record);
+                }
+                handle_record(
+                    record,
+                    &mut filter,
+                    &mut reports,
+                    sink.as_mut(),
+                    &options.pool,
+                    &mut num_samples,
+                );
+            }
+            None => {
+                eprintln!("All sampled processes exited.");
+                break;
+            }
+        }
+    }
+
+    if progress_meter.is_some() {
+        // Clear the status line.
+        eprint!("\r\x1b[K");
+    }
+    // Stop sampling but keep what is already buffered.
+    pipeline.stop();
+    while below_limit(num_samples) {
+        let record = pipeline
+            .recv()
+            .await
+            .unwrap_or_else(|err| exit::fail_with("Sampling failed", &err));
+        match record {
+            Some(record) => handle_record(
+                record,
+                &mut filter,
+                &mut reports,
+                sink.as_mut(),
+                &options.pool,
+                &mut num_samples,
+            ),
+            None => break,
+        }
+    }
+
+    sink.finish().expect("Failed to write the samples.");
+    let pipeline_stats = pipeline.stats();
+    let stats = pipeline.finish().await;
+    if let Some(dump) = &options.dump {
+        dump.finish().expect("Failed to write the raw records.");
+    }
+    eprintln!(
+        "Collected {} samples in {:.1} s ({:.1} samples/s), lost {}, throttled {} times.",
+        stats.samples,
+        stats.duration.as_secs_f64(),
+        stats.rate(),
+        stats.lost,
+        stats.throttles
+    );
+    eprintln!(
+        "Queued at most {} records, readers waited for the output {} times.",
+        pipeline_stats.queue_peak, pipeline_stats.stalls
+    );
+    eprintln!(
+        "Ring buffers were at most {:.0} % full.",
+        stats.buffer_peak * 100.0
+    );
+    if filter.excluded_samples() > 0 {
+        eprintln!(
+            "Left out {} samples of tauphi itself, see --include-self.",
+            filter.excluded_samples()
+        );
+    }
+    if stats.malformed > 0 {
+        eprintln!("Skipped {} malformed records.", stats.malformed);
+    }
+    match args.backpressure {
+        sampling::Backpressure::Drop => (),
+        sampling::Backpressure::Pause => eprintln!(
+            "Paused {} times for {:.1} s in total.",
+            stats.pauses,
+            stats.paused.as_secs_f64()
+        ),
+        sampling::Backpressure::Queue(_) => {
+            eprintln!("At most {} records were queued.", stats.queue_peak)
+        }
+    }
+
+    if let Some(overhead_meter) = overhead_meter {
+        let overhead = overhead_meter.report();
+        eprintln!(
+            "tauphi used {:.3} s of CPU time ({:.1} % of a CPU), {} of {} samples ({:.2} %) hit tauphi itself.",
+            overhead.cpu_time.as_secs_f64(),
+            overhead.cpu_share() * 100.0,
+            overhead.own_samples,
+            overhead.samples,
+            overhead.perturbation() * 100.0
+        );
+    }
+    if let Some(meter) = meter {
+        print_energy(&meter, &reports.samples_per_pid);
+    }
+    if let Some(phases) = &reports.phases {
+        print_phases(phases);
+    }
+    if let Some(processes) = &reports.processes {
+        print_processes(processes, recording_start);
+    }
+    if let Some(switches) = &reports.switches {
+        print_switches(switches);
+    }
+    if let Some(containers) = &reports.containers {
+        print_containers(containers);
+    }
+    if let Some(hot_functions) = reports.hot_functions.as_mut() {
+        print_hot_functions(hot_functions, recording_start);
+    }
+    if let Some(butterfly) = &reports.butterfly {
+        print_butterfly(butterfly);
+    }
+    if let Some(timeline) = &reports.timeline {
+        eprintln!(
+            "Samples per {} s of the busiest processes:",
+            TIMELINE_BUCKET.as_secs()
+        );
+        eprint!("{}", timeline.sparklines(TIMELINE_PROCESSES));
+    }
+}
+
+/// Start a sampler for each of the (CPU, PID) targets and each CPU of the cgroups.
+fn open_samplers(
+    targets: Vec<(i32, i32)>,
+    cgroups: &[PathBuf],
+    options: &sampling::SamplerOptions,
+) -> Vec<Result<sampling::Sampler, error::TauphiError>> {
+    targets
+        .into_iter()
+        .map(|(cpu, pid)| sampling::Sampler::with_options(cpu, pid, options))
+        .chain(cgroups.iter().flat_map(|cgroup| {
+            // Cgroups can be sampled only per CPU.
+            (0..sampling::num_cpus() as i32)
+                .map(|cpu| sampling::Sampler::new_cgroup(cgroup, cpu, options))
+        }))
+        .collect()
+}
+
+/// Open a sampler of bpf-output records on each CPU.
+///
+/// The BPF program decides which records to write, so the samplers are not
+/// limited to the targets. With `bpf_map`, the program writes into them.
+fn open_bpf_output(
+    options: &sampling::SamplerOptions,
+    bpf_map: Option<&bpf::PerfEventArray>,
+) -> Vec<Result<sampling::Sampler, error::TauphiError>> {
+    (0..sampling::num_cpus() as i32)
+        .map(|cpu| {
+            let sampler = sampling::Sampler::with_options(cpu, -1, options)?;
+            if let Some(bpf_map) = bpf_map {
+                bpf_map.insert(cpu as u32, &sampler)?;
+            }
+            Ok(sampler)
+        })
+        .collect()
+}
+
+/// Samplers which started, exits if none did.
+///
+/// Sessions of several samplers continue without the failed ones, e.g. of
+/// an unsupported event, a CPU going offline or an already exited process.
+fn started(samplers: Vec<Result<sampling::Sampler, error::TauphiError>>) -> Vec<sampling::Sampler> {
+    let (opened, failed): (Vec<_>, Vec<_>) = samplers.into_iter().partition(Result::is_ok);
+    let mut errors = failed.into_iter().filter_map(Result::err);
+    if opened.is_empty() {
+        if let Some(err) = errors.next() {
+            exit::fail_with("Failed to start the sampling", &err);
+        }
+    }
+    for err in errors {
+        eprintln!("Skipping a sampler: {err}");
+    }
+    opened.into_iter().filter_map(Result::ok).collect()
+}
+
+/// Warn about events sampled less precisely than requested by `:p`.
+///
+/// Without PEBS or IBS the sampled instruction pointer skids past the one
+/// which caused the event, stacks of such events are approximate.
+fn warn_skid(samplers: &[sampling::Sampler], event_options: &[sampling::SamplerOptions]) {
+    for options in event_options.iter().filter(|options| options.precise > 0) {
+        let granted = samplers
+            .iter()
+            .filter(|sampler| sampler.event() == options.event)
+            .map(sampling::Sampler::precise)
+            .min();
+        match granted {
+            Some(granted) if granted < options.precise => eprintln!(
+                "Warning: {} requested precise level {}, got {granted}, samples may skid past \
+                 the instruction which caused the event.",
+                options.event.name(),
+                options.precise
+            ),
+            _ => {}
+        }
+    }
+}
+
+/// Cgroups selected by the arguments, empty if none were.
+fn resolve_cgroups(args: &cli::Args) -> Result<Vec<PathBuf>, error::TauphiError> {
+    if let Some(path) = &args.cgroup {
+        return Ok(vec![cgroup::resolve_cgroup(path)?]);
+    }
+    if let Some(container) = &args.container {
+        return Ok(vec![cgroup::resolve_container(container)?]);
+    }
+    if args.k8s_pod.is_none() && args.k8s_namespace.is_none() {
+        return Ok(Vec::new());
+    }
+    let containers = cgroup::resolve_pods(args.k8s_namespace.as_deref(), args.k8s_pod.as_deref())?;
+    for container in &containers {
+        eprintln!(
+            "Sampling container {} of pod {}/{}",
+            container.id, container.namespace, container.pod
+        );
     }
+    Ok(containers
+        .into_iter()
+        .map(|container| container.cgroup)
+        .collect())
 }
+
+/// Print results of environment checks, returns false on any error.
+fn print_checks(checks: &[doctor::Check]) -> bool {
+    for check in checks {
+        let status = match check.status {
+            doctor::Status::Ok => "ok",
+            doctor::Status::Warning => "warning",
+            doctor::Status::Error => "error",
+        };
+        println!("{status:<8} {}: {}", check.name, check.detail);
+        if let Some(fix) = &check.fix {
+            println!("{:<8} {fix}", "");
+        }
+    }
+    checks
+        .iter()
+        .all(|check| check.status != doctor::Status::Error)
+}
+
+/// Counts of `tauphi stat` as one JSON object.
+fn stat_json(report: &stat::StatReport) -> String {
+    let counters: Vec<_> = report
+        .counts
+        .iter()
+        .map(|count| count_json(count, report.metric(count.event)))
+        .collect();
+    let threads: Vec<_> = report
+        .threads
+        .iter()
+        .map(|thread| {
+            let counters: Vec<_> = thread
+                .counts
+                .iter()
+                .map(|count| count_json(count, None))
+                .collect();
+            format!(
+                "{{\"tid\":{},\"name\":{},\"counters\":[{}]}}",
+                thread.tid,
+                exit::json_string(&thread.name),
+                counters.join(",")
+            )
+        })
+        .collect();
+    format!(
+        "{{\"command\":{},\"elapsed_ns\":{},\"exit_code\":{},\"counters\":[{}],\"threads\":[{}]}}",
+        exit::json_string(&report.command.join(" ")),
+        report.elapsed.as_nanos(),
+        report.exit_code(),
+        counters.join(","),
+        threads.join(",")
+    )
+}
+
+/// Counts of an interval of `tauphi stat --interval` as one JSON object.
+fn snapshot_json(snapshot: &stat::Snapshot) -> String {
+    let counters: Vec<_> = snapshot
+        .counts
+        .iter()
+        .map(|count| count_json(count, None))
+        .collect();
+    format!(
+        "{{\"time_ns\":{},\"counters\":[{}]}}",
+        snapshot.time.as_nanos(),
+        counters.join(",")
+    )
+}
+
+/// A count with its derived metric as one JSON object.
+fn count_json(count: &stat::Count, metric: Option<(f64, &str)>) -> String {
+    let number = |value: Option<String>| value.unwrap_or_else(|| "null".to_owned());
+    format!(
+        "{{\"event\":{},\"value\":{},\"supported\":{},\"running\":{},\"metric\":{},\"unit\":{}}}",
+        exit::json_string(count.event.name()),
+        number(count.value.map(|value| value.to_string())),
+        count.supported,
+        count.running,
+        number(metric.map(|(value, _)| value.to_string())),
+        number(metric.map(|(_, unit)| exit::json_string(unit))),
+    )
+}
+
+/// Rows of counts at the time, in the format of `tauphi stat --csv`.
+fn print_csv_counts(time: time::Duration, counts: &[stat::Count]) {
+    for count in counts {
+        let value = count
+            .value
+            .map_or_else(String::new, |value| value.to_string());
+        eprintln!(
+            "{:.6},{},{value},{:.4}",
+            time.as_secs_f64(),
+            count.event.name(),
+            count.running
+        );
+    }
+}
+
+/// Count events of the command of `tauphi stat` and print them.
+fn run_stat(args: &cli::Args) {
+    let options = stat::StatOptions {
+        events: args.events.iter().map(|spec| spec.event).collect(),
+        interval: args.interval,
+        per_thread: args.per_thread,
+    };
+    if args.stat_format == cli::StatFormat::Csv {
+        eprintln!("time,event,value,running");
+    }
+    let report = stat::count_command(&args.command, &options, |snapshot| match args.stat_format {
+        cli::StatFormat::Text => {
+            for count in &snapshot.counts {
+                let value = count
+                    .value
+                    .map_or_else(|| "<not counted>".to_owned(), |value| value.to_string());
+                eprintln!(
+                    "{:>12.6} {value:>20} {}",
+                    snapshot.time.as_secs_f64(),
+                    count.event.name()
+                );
+            }
+        }
+        cli::StatFormat::Json => eprintln!("{}", snapshot_json(snapshot)),
+        cli::StatFormat::Csv => print_csv_counts(snapshot.time, &snapshot.counts),
+    })
+    .unwrap_or_else(|err| exit::fail_with("Failed to count the command", &err));
+    match args.stat_format {
+        cli::StatFormat::Text => eprint!("{report}"),
+        cli::StatFormat::Json => eprintln!("{}", stat_json(&report)),
+        // The intervals add up to the totals.
+        cli::StatFormat::Csv if args.interval.is_some() => (),
+        cli::StatFormat::Csv => print_csv_counts(report.elapsed, &report.counts),
+    }
+    if !report.status.success() {
+        eprintln!("The command failed, {}.", report.status);
+    }
+}
+
+/// Print the phases with their start relative to the first one.
+fn print_phases(phases: &phase::PhaseTracker) {
+    let mut phases = phases.phases().peekable();
+    let Some(&(first, _)) = phases.peek() else {
+        return;
+    };
+    let start = first.start;
+    eprintln!("Phases:");
+    for (phase, samples) in phases {
+        eprintln!(
+            "  {:<20} from {:6.1} s, {} samples",
+            phase.name,
+            phase.start.saturating_sub(start) as f64 / 1e9,
+            samples
+        );
+    }
+}
+
+/// Print the lifetimes of the most sampled processes, relative to `start`.
+fn print_processes(processes: &processes::ProcessTable, start: u64) {
+    let seconds = |time: Option<u64>| match time {
+        Some(time) => format!("{:.1}", time.saturating_sub(start) as f64 / 1e9),
+        None => "-".to_owned(),
+    };
+    eprintln!(
+        "{:>8} {:>8} {:>8} {:>8} {:>8}  COMMAND",
+        "PID", "PPID", "START", "END", "SAMPLES"
+    );
+    for process in processes.processes().into_iter().take(PROCESS_TABLE_ROWS) {
+        eprintln!(
+            "{:>8} {:>8} {:>8} {:>8} {:>8}  {}",
+            process.pid,
+            process
+                .ppid
+                .map_or_else(|| "-".to_owned(), |ppid| ppid.to_string()),
+            seconds(process.start),
+            seconds(process.end),
+            process.samples,
+            process.command.as_deref().unwrap_or("?")
+        );
+    }
+}
+
+/// Print the threads with most context switches and migrations.
+fn print_switches(switches: &switches::SwitchTable) {
+    eprintln!(
+        "{:>8} {:>8} {:>8} {:>8} {:>10}  THREAD",
+        "PID", "TID", "SAMPLES", "SWITCHES", "MIGRATIONS"
+    );
+    for thread in switches.threads().into_iter().take(PROCESS_TABLE_ROWS) {
+        eprintln!(
+            "{:>8} {:>8} {:>8} {:>8} {:>10}  {}",
+            thread.pid,
+            thread.tid,
+            thread.samples,
+            thread.switches,
+            thread.migrations,
+            thread.name.as_deref().unwrap_or("?")
+        );
+    }
+}
+
+/// Print the most sampled containers, each with its busiest processes.
+fn print_containers(containers: &containers::ContainerTable) {
+    let workloads = containers.workloads();
+    let total: u64 = workloads.iter().map(|workload| workload.samples).sum();
+    let share = |samples| 100.0 * samples as f64 / total.max(1) as f64;
+    eprintln!("{:>8} {:>7}  CONTAINER", "SAMPLES", "SHARE");
+    for workload in workloads.into_iter().take(PROCESS_TABLE_ROWS) {
+        eprintln!(
+            "{:>8} {:>5.1} %  {}",
+            workload.samples,
+            share(workload.samples),
+            workload.workload
+        );
+        for process in workload.processes.into_iter().take(CONTAINER_PROCESSES) {
+            eprintln!(
+                "{:>8} {:>5.1} %    {} {}",
+                process.samples,
+                share(process.samples),
+                process.pid,
+                process.command.as_deref().unwrap_or("?")
+            );
+        }
+    }
+}
+
+/// Print the most sampled direct callers and callees of the function.
+fn print_butterfly(butterfly: &butterfly::Butterfly) {
+    let (samples, through) = butterfly.samples();
+    let share = |samples| 100.0 * samples as f64 / through.max(1) as f64;
+    eprintln!(
+        "{through} samples ({:.1} %) pass through {}.",
+        100.0 * through as f64 / samples.max(1) as f64,
+        butterfly.function()
+    );
+    eprintln!("{:>8} {:>7}  CALLER", "SAMPLES", "SHARE");
+    for (frame, samples) in butterfly.callers().into_iter().take(BUTTERFLY_ROWS) {
+        eprintln!("{samples:>8} {:>5.1} %  {frame}", share(samples));
+    }
+    eprintln!("{:>8} {:>7}  CALLEE", "SAMPLES", "SHARE");
+    for (frame, samples) in butterfly.callees().into_iter().take(BUTTERFLY_ROWS) {
+        let frame = frame.map_or("[self]".to_owned(), ToString::to_string);
+        eprintln!("{samples:>8} {:>5.1} %  {frame}", share(samples));
+    }
+}
+
+/// Print the hottest functions since the previous call, `start` is the
+/// monotonic time of the recording's start.
+fn print_hot_functions(hot_functions: &mut watch::HotFunctions, start: u64) {
+    let summary = hot_functions.summary(WATCH_FUNCTIONS);
+    let elapsed = clock::monotonic_now().saturating_sub(start) as f64 / 1e9;
+    println!("{elapsed:.1} s, {} samples:", summary.samples);
+    print!("{summary}");
+    // Keep the summaries in order with logs when piped.
+    io::stdout().flush().expect("Failed to write the summary.");
+}
+
+/// Print energy consumed during the sampling and its split among processes.
+fn print_energy(meter: &energy::EnergyMeter, samples_per_pid: &HashMap<u32, u64>) {
+    let energy = meter.read().expect("Failed to read RAPL energy counters.");
+    println!("Energy consumed:");
+    for domain in &energy {
+        println!("  {:>8} {:10.3} J", domain.domain, domain.joules);
+    }
+    // Package energy is the closest to what the sampled CPUs consumed.
+    let Some(total) = energy
+        .iter()
+        .find(|domain| domain.domain == "pkg")
+        .or(energy.first())
+    else {
+        return;
+    };
+    println!("Share of {} energy by sampled CPU time:", total.domain);
+    for (pid, joules) in energy::attribute_by_samples(total.joules, samples_per_pid) {
+        println!("  pid {pid:>8} {joules:10.3} J");
+    }
+}
+
+/// Unused sample buffers kept for reuse, covers bursts of samples.
+const SAMPLE_POOL_CAPACITY: usize = 1024;
+
+/// Records read ahead of the output, absorbs bursts of samples.
+const PIPELINE_CAPACITY: usize = 4096;
+
+/// How often the adaptive frequency is reconsidered.
+const ADJUST_PERIOD: time::Duration = time::Duration::from_secs(1);
+
+/// Write the record or account it to the reports summarizing it, drops
+/// samples rejected by the filter.
+fn handle_record(
+    record: sampling::Record,
+    filter: &mut filter::SampleFilter,
+    reports: &mut reports::Reports,
+    sink: &mut dyn SampleSink,
+    pool: &SamplePool,
+    num_samples: &mut usize,
+) {
+    match record {
+        sampling::Record::Sample(sample) if !filter.accepts(&sample) => pool.recycle(sample),
+        record => {
+            if let Some(record) = reports.observe(record) {
+                print_record(record, reports.event, sink, pool, num_samples)
+            }
+        }
+    }
+}
+
+/// Pass samples to the sink and recycle them, log process events.
+fn print_record(
+    record: sampling::Record,
+    event: sampling::Event,
+    sink: &mut dyn SampleSink,
+    pool: &SamplePool,
+    num_samples: &mut usize,
+) {
+    match record {
+        sampling::Record::Sample(sample) => {
+            // Samples of the other events do not count to --samples.
+            *num_samples += usize::from(sample.event == event);
+            sink.consume(&sample).expect("Failed to write the sample.");
+            pool.recycle(sample);
+        }
+        sampling::Record::Fork(event) if event.is_process() => {
+            eprintln!("New process {} of parent {}", event.pid, event.ppid)
+        }
+        sampling::Record::Exit(event) if event.is_process() => {
+            eprintln!("Process {} exited", event.pid)
+        }
+        _ => (),
+    }
+}
+
+/// Resolve on the first SIGINT or SIGTERM.
+async fn shutdown_signal() -> io::Result<()> {
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    let mut terminate = signal(SignalKind::terminate())?;
+    tokio::select! {
+        _ = interrupt.recv() => (),
+        _ = terminate.recv() => (),
+    }
+    Ok(())
+}
+
+/// How often the live status line is refreshed.
+const PROGRESS_PERIOD: time::Duration = time::Duration::from_secs(1);
+
+/// Length of one --timeline bucket.
+const TIMELINE_BUCKET: time::Duration = time::Duration::from_secs(1);
+
+/// Number of processes shown by --timeline.
+const TIMELINE_PROCESSES: usize = 10;
+
+/// Number of processes shown by --processes.
+const PROCESS_TABLE_ROWS: usize = 20;
+
+/// Number of functions in each summary of tauphi watch.
+const WATCH_FUNCTIONS: usize = 10;
+
+/// Number of processes shown under each container by --containers.
+const CONTAINER_PROCESSES: usize = 3;
+
+/// Number of callers and of callees shown by --butterfly.
+const BUTTERFLY_ROWS: usize = 10;
@@ -0,0 +1,145 @@
+//! Reports built from the records while sampling, printed at the end.
+use std::collections::HashMap;
+
+use tauphi_core::butterfly::Butterfly;
+use tauphi_core::containers::ContainerTable;
+use tauphi_core::phase::PhaseTracker;
+use tauphi_core::processes::ProcessTable;
+use tauphi_core::sampling::{Event, Record};
+use tauphi_core::switches::SwitchTable;
+use tauphi_core::symbols::SharedSymbolizer;
+use tauphi_core::timeline::Timeline;
+use tauphi_core::watch::HotFunctions;
+
+/// The requested reports, `None` for those not requested.
+///
+/// Only samples of the first sampled event are CPU usage. Samples of the
+/// other events, e.g. of the switches and migrations of --switches, count
+/// only to the reports made for them.
+#[derive(Debug)]
+pub struct Reports {
+    /// The first sampled event.
+    pub event: Event,
+    /// Samples of each process, for attributing the energy.
+    pub samples_per_pid: HashMap<u32, u64>,
+    /// Names the frames of the reports and sinks, snapshots the mappings of
+    /// processes on their first record. `None` if no frames are named.
+    pub symbolizer: Option<SharedSymbolizer>,
+    pub timeline: Option<Timeline>,
+    pub phases: Option<PhaseTracker>,
+    pub processes: Option<ProcessTable>,
+    pub switches: Option<SwitchTable>,
+    pub hot_functions: Option<HotFunctions>,
+    pub containers: Option<ContainerTable>,
+    pub butterfly: Option<Butterfly>,
+}
+
+impl Reports {
+    /// No reports of samples of the event, but the samples per process.
+    pub fn new(event: Event) -> Reports {
+        Reports {
+            event,
+            samples_per_pid: HashMap::new(),
+            symbolizer: None,
+            timeline: None,
+            phases: None,
+            processes: None,
+            switches: None,
+            hot_functions: None,
+            containers: None,
+            butterfly: None,
+        }
+    }
+
+    /// Account the record to the reports, returns it back unless it is
+    /// summarized by them instead of being written.
+    pub fn observe(&mut self, record: Record) -> Option<Record> {
+        if let Some(symbolizer) = &self.symbolizer {
+            symbolizer.borrow_mut().maps().observe(&record);
+        }
+        let sample = match &record {
+            Record::Fork(_) | Record::Exit(_) | Record::Comm(_) => {
+                let Some(processes) = self.processes.as_mut() else {
+                    return Some(record);
+                };
+                processes.observe(&record);
+                return None;
+            }
+            Record::Cgroup(_) => {
+                if let Some(containers) = self.containers.as_mut() {
+                    containers.observe(&record);
+                }
+                return None;
+            }
+            Record::Sample(sample) => sample,
+            _ => return Some(record),
+        };
+        if let Some(switches) = self.switches.as_mut().filter(|_| {
+            sample.event == self.event
+                || matches!(sample.event, Event::ContextSwitches | Event::CpuMigrations)
+        }) {
+            switches.add_sample(sample);
+        }
+        if sample.event != self.event {
+            return Some(record);
+        }
+        *self.samples_per_pid.entry(sample.pid).or_default() += 1;
+        if let Some(timeline) = self.timeline.as_mut() {
+            timeline.add_sample(sample);
+        }
+        if let Some(phases) = self.phases.as_mut() {
+            phases.add_sample(sample);
+        }
+        if let Some(processes) = self.processes.as_mut() {
+            processes.add_sample(sample);
+        }
+        if let Some(hot_functions) = self.hot_functions.as_mut() {
+            hot_functions.add_sample(sample);
+        }
+        if let Some(containers) = self.containers.as_mut() {
+            containers.add_sample(sample);
+        }
+        if let Some(butterfly) = self.butterfly.as_mut() {
+            butterfly.add_sample(sample);
+        }
+        Some(record)
+    }
+}
+
+#[test]
+fn reports_test() {
+    use tauphi_core::sampling::Sample;
+
+    let mut reports = Reports::new(Event::TaskClock);
+    reports.processes = Some(ProcessTable::default());
+    reports.switches = Some(SwitchTable::default());
+    reports.symbolizer = Some(SharedSymbolizer::default());
+    let sample = |event| {
+        Record::Sample(Sample {
+            pid: u32::MAX,
+            tid: u32::MAX,
+            event,
+            ..Default::default()
+        })
+    };
+    for event in [
+        Event::TaskClock,
+        Event::ContextSwitches,
+        Event::CpuMigrations,
+        Event::PageFaults,
+    ] {
+        assert!(reports.observe(sample(event)).is_some());
+    }
+    assert_eq!(reports.samples_per_pid, HashMap::from([(u32::MAX, 1)]));
+    let processes = reports.processes.as_ref().unwrap().processes();
+    assert_eq!(processes[0].samples, 1);
+    let threads = reports.switches.as_ref().unwrap().threads();
+    assert_eq!(
+        (
+            threads[0].samples,
+            threads[0].switches,
+            threads[0].migrations
+        ),
+        (1, 1, 1)
+    );
+}